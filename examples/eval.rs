@@ -16,20 +16,11 @@ fn main() -> Result<(), Error> {
     let bin = fs::read(Path::new(wasmfile)).expect("file data");
     let module = Module::new(bin.into_boxed_slice())?;
 
-    let fn_index = fn_name.map(|name| {
-        let (index, _) = module
-            .exports()
-            .iter()
-            .enumerate()
-            .find(|(_i, (e, _ty))| e == name)
-            .expect("export");
-        index
-    });
     let instance = Instance::new(&module, &[])?;
-    if let Some(fn_index) = fn_index {
-        let f = &instance.exports()[fn_index];
-        let mut result = vec![Default::default()];
-        if let Ok(()) = f.func().unwrap().call_wrapped(&[], &mut result) {
+    if let Some(fn_name) = fn_name {
+        let f = instance.get_func(fn_name).expect("export");
+        let mut result = vec![Default::default(); f.ty().returns.len()];
+        if let Ok(()) = f.call_wrapped(&[], &mut result) {
             eprintln!("{:?}", result);
             return Ok(());
         }
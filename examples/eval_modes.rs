@@ -0,0 +1,69 @@
+use anyhow::Error;
+use std::time::Instant;
+
+use wasmeval::{EvalMode, Instance, Module, Val};
+
+const WAT: &str = r#"(module
+    (memory 1)
+    (func $fib (export "fib") (param i32) (result i32)
+        (if (result i32) (i32.lt_s (local.get 0) (i32.const 2))
+            (then (local.get 0))
+            (else
+                (i32.add
+                    (call $fib (i32.sub (local.get 0) (i32.const 1)))
+                    (call $fib (i32.sub (local.get 0) (i32.const 2)))))))
+    (func (export "hash") (param i32) (result i32) (local i32 i32)
+        (loop $loop
+            (local.set 1
+                (i32.xor
+                    (i32.add (local.get 1) (local.get 2))
+                    (i32.mul (local.get 2) (i32.const 3))))
+            (br_if $loop
+                (i32.lt_s
+                    (local.tee 2 (i32.add (local.get 2) (i32.const 1)))
+                    (local.get 0))))
+        (local.get 1))
+    (func (export "memory") (param i32) (result i32) (local i32 i32)
+        (loop $loop
+            (i32.store
+                (i32.and (local.get 2) (i32.const 1020))
+                (i32.add
+                    (local.get 2)
+                    (i32.load (i32.and (i32.shl (local.get 2) (i32.const 2)) (i32.const 1020)))))
+            (local.set 1
+                (i32.add (local.get 1) (i32.load (i32.and (local.get 2) (i32.const 1020)))))
+            (br_if $loop
+                (i32.lt_s
+                    (local.tee 2 (i32.add (local.get 2) (i32.const 1)))
+                    (local.get 0))))
+        (local.get 1)))"#;
+
+/// Compares the run times of the stack and register interpreters, and
+/// reports the hottest instruction sequences of the workloads.
+fn main() -> Result<(), Error> {
+    let buf = wast::parser::ParseBuffer::new(WAT)?;
+    let mut wat = wast::parser::parse::<wast::Wat>(&buf)?;
+    let module = Module::new(wat.module.encode()?.into_boxed_slice())?;
+    for &(name, arg) in &[("fib", 27), ("hash", 10_000_000), ("memory", 5_000_000)] {
+        for &mode in &[EvalMode::Stack, EvalMode::Register, EvalMode::Profile] {
+            let instance = Instance::new(&module, &[])?;
+            instance.set_eval_mode(mode);
+            let f = instance.get_func(name).expect("export");
+            let mut result = vec![Default::default()];
+            let start = Instant::now();
+            f.call_wrapped(&[Val::I32(arg)], &mut result)?;
+            println!(
+                "{}({}) {:?}: {:?} in {:?}",
+                name,
+                arg,
+                mode,
+                result[0],
+                start.elapsed()
+            );
+            for sequence in instance.hot_sequences().iter().take(5) {
+                println!("    {:>10} {}", sequence.count, sequence.instrs.join(" "));
+            }
+        }
+    }
+    Ok(())
+}
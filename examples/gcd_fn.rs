@@ -5,7 +5,10 @@ use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
 
-use wasmeval::{eval, EvalContext, Func, FuncType, Global, Memory, MemoryImmediate, Table, Val};
+use wasmeval::{
+    eval, EvalContext, Func, FuncType, Global, Limits64, Memory, MemoryImmediate, MemoryType,
+    Mutability, Table, Val, ValType,
+};
 
 fn read_gcd(data: &[u8]) -> Result<&[u8], Error> {
     use wasmparser::{ExternalKind, ImportSectionEntryType, Parser, Payload};
@@ -60,48 +63,49 @@ struct Ctx {
 
 impl Ctx {
     pub fn new() -> Self {
-        struct G(RefCell<Val>);
-        impl Global for G {
-            fn content(&self) -> Val {
-                self.0.borrow().clone()
-            }
-            fn set_content(&self, val: &Val) {
-                *self.0.borrow_mut() = val.clone();
-            }
-        }
         #[inline]
-        fn combine_offsets(memarg: &MemoryImmediate, offset: u32) -> usize {
+        fn combine_offsets(memarg: &MemoryImmediate, offset: u64) -> usize {
             memarg.offset as usize + offset as usize
         }
         struct M(RefCell<Vec<u8>>);
         impl Memory for M {
-            fn current(&self) -> u32 {
+            fn ty(&self) -> MemoryType {
+                MemoryType {
+                    limits: Limits64 {
+                        initial: 1,
+                        maximum: Some(1),
+                    },
+                    shared: false,
+                    memory64: false,
+                }
+            }
+            fn current(&self) -> u64 {
                 1
             }
-            fn grow(&self, _delta: u32) -> u32 {
+            fn grow(&self, _delta: u64) -> u64 {
                 panic!("M grow");
             }
-            fn content_ptr(&self, memarg: &MemoryImmediate, offset: u32, size: u32) -> *const u8 {
+            fn content_ptr(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *const u8 {
                 let offset = combine_offsets(memarg, offset);
                 if offset + size as usize > self.0.borrow().len() {
                     return std::ptr::null();
                 }
                 &self.0.borrow()[offset]
             }
-            fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u32, size: u32) -> *mut u8 {
+            fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *mut u8 {
                 let offset = combine_offsets(memarg, offset);
                 if offset + size as usize > self.0.borrow().len() {
                     return std::ptr::null_mut();
                 }
                 &mut self.0.borrow_mut()[offset]
             }
-            fn clone_from_slice(&self, offset: u32, chunk: &[u8]) {
+            fn clone_from_slice(&self, offset: u64, chunk: &[u8]) {
                 let offset = offset as usize;
                 self.0.borrow_mut()[offset..(offset + chunk.len())].clone_from_slice(chunk);
             }
         }
         Self {
-            global: Rc::new(G(RefCell::new(Val::I32(65336)))),
+            global: <dyn Global>::new(ValType::I32, Mutability::Var, Val::I32(65336)).unwrap(),
             memory: Rc::new(M(RefCell::new(vec![0u8; 65336]))),
         }
     }
@@ -119,7 +123,8 @@ impl EvalContext for Ctx {
             }
         }
     }
-    fn get_memory(&self) -> Rc<dyn Memory> {
+    fn get_memory(&self, index: u32) -> Rc<dyn Memory> {
+        assert_eq!(index, 0);
         self.memory.clone()
     }
     fn get_table(&self, index: u32) -> Rc<dyn Table> {
@@ -4,7 +4,7 @@ use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
 
-use wasmeval::{External, Func, FuncType, Instance, Module, Trap, Val};
+use wasmeval::{External, Func, FuncType, Linker, Module, Trap, Val};
 
 struct Callback(Arc<FuncType>);
 impl Callback {
@@ -28,9 +28,11 @@ impl Func for Callback {
 fn main() -> Result<(), Error> {
     let bin = fs::read(Path::new("examples/hello.wasm")).expect("file data");
     let module = Module::new(bin.into_boxed_slice())?;
-    let instance = Instance::new(&module, &[External::Func(Rc::new(Callback::new()))])?;
-    let hello = &instance.exports()[0];
-    if let Ok(()) = hello.func().unwrap().call_wrapped(&[], &mut []) {
+    let mut linker = Linker::new();
+    linker.define("", "hello", External::Func(Rc::new(Callback::new())))?;
+    let instance = linker.instantiate(&module)?;
+    let run = instance.get_func("run").expect("run export");
+    if let Ok(()) = run.call_wrapped(&[], &mut []) {
         return Ok(());
     }
     bail!("some error")
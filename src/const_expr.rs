@@ -0,0 +1,392 @@
+// Constant expressions of the global initializers and the segment offsets.
+//
+// The wasmparser validator accepts only a single constant instruction,
+// so the expressions of the extended-const proposal are validated here
+// and replaced by a plain constant of the same type before the module is
+// passed to the validator. The offsets of the validator errors are mapped
+// back to the original module.
+
+use std::borrow::Cow;
+use wasmparser::{
+    BinaryReader, DataKind, DataSectionReader, ElementKind, ElementSectionReader,
+    GlobalSectionReader, ImportSectionEntryType, ImportSectionReader, InitExpr,
+    MemorySectionReader, MemoryType, Operator, Result as ParserResult, Type,
+};
+
+use crate::error::ModuleError;
+use crate::eval::EvalContext;
+use crate::values::{get_default_value, Val};
+
+const MODULE_HEADER_SIZE: usize = 8;
+
+const IMPORT_SECTION_ID: u32 = 2;
+const MEMORY_SECTION_ID: u32 = 5;
+const GLOBAL_SECTION_ID: u32 = 6;
+const ELEMENT_SECTION_ID: u32 = 9;
+const DATA_SECTION_ID: u32 = 11;
+
+fn invalid(message: &str, offset: usize) -> ModuleError {
+    ModuleError::Invalid {
+        message: message.to_string(),
+        offset,
+    }
+}
+
+/// Globals visible to the constant expressions: the imported ones.
+struct ConstExprGlobals {
+    globals: Vec<(Type, bool)>,
+}
+
+impl ConstExprGlobals {
+    fn get(&self, index: u32, offset: usize) -> Result<Type, ModuleError> {
+        match self.globals.get(index as usize) {
+            Some((_, true)) => Err(invalid("constant expression required", offset)),
+            Some((ty, false)) => Ok(*ty),
+            None => Err(invalid("unknown global", offset)),
+        }
+    }
+}
+
+/// The replaced bytes of a section: the range is relative to the section content.
+struct Patch {
+    start: usize,
+    end: usize,
+    replacement: &'static [u8],
+}
+
+/// The module bytes passed to the validator.
+pub(crate) struct LoweredModule<'a> {
+    pub(crate) buf: Cow<'a, [u8]>,
+    /// The starts of the runs of the lowered bytes, with the positions
+    /// of the original bytes they come from.
+    runs: Vec<(usize, usize)>,
+}
+
+impl<'a> LoweredModule<'a> {
+    fn unchanged(buf: &'a [u8]) -> Self {
+        LoweredModule {
+            buf: Cow::Borrowed(buf),
+            runs: vec![(0, 0)],
+        }
+    }
+
+    /// Returns the position in the original module of the lowered byte at
+    /// the `offset`. The bytes of a replaced expression map into it.
+    pub(crate) fn original_offset(&self, offset: usize) -> usize {
+        let run = match self.runs.binary_search_by_key(&offset, |&(start, _)| start) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        let (start, original_start) = self.runs[run];
+        original_start + (offset - start)
+    }
+}
+
+/// Validates the extended constant expressions in the module and returns
+/// the module bytes where each of them is replaced by a constant of the
+/// expression type. Malformed sections are left intact, so the validator
+/// can report them.
+pub(crate) fn lower_const_exprs(buf: &[u8]) -> Result<LoweredModule<'_>, ModuleError> {
+    let sections = match read_sections(buf) {
+        Ok(sections) => sections,
+        Err(_) => return Ok(LoweredModule::unchanged(buf)),
+    };
+    let mut globals = ConstExprGlobals { globals: vec![] };
+    let mut memories = vec![];
+    let mut patches = vec![];
+    for &(id, start, end) in sections.iter() {
+        let section_patches = match check_section(id, buf, start, end, &mut globals, &mut memories)
+        {
+            Ok(section_patches) => section_patches,
+            Err(CheckError::Parser) => return Ok(LoweredModule::unchanged(buf)),
+            Err(CheckError::Invalid(err)) => return Err(err),
+        };
+        patches.push(section_patches);
+    }
+    if patches
+        .iter()
+        .all(|section_patches| section_patches.is_empty())
+    {
+        return Ok(LoweredModule::unchanged(buf));
+    }
+
+    let mut result = buf[..MODULE_HEADER_SIZE].to_vec();
+    let mut runs = vec![(0, 0)];
+    let mut pos = MODULE_HEADER_SIZE;
+    for (&(id, start, end), section_patches) in sections.iter().zip(patches) {
+        runs.push((result.len(), pos));
+        if section_patches.is_empty() {
+            result.extend_from_slice(&buf[pos..end]);
+        } else {
+            let mut content = vec![];
+            let mut content_runs = vec![];
+            let mut copied = start;
+            for patch in section_patches {
+                content_runs.push((content.len(), copied));
+                content.extend_from_slice(&buf[copied..start + patch.start]);
+                content_runs.push((content.len(), start + patch.start));
+                content.extend_from_slice(patch.replacement);
+                copied = start + patch.end;
+            }
+            content_runs.push((content.len(), copied));
+            content.extend_from_slice(&buf[copied..end]);
+            result.push(id as u8);
+            write_var_u32(&mut result, content.len() as u32);
+            let content_start = result.len();
+            runs.extend(
+                content_runs
+                    .into_iter()
+                    .map(|(start, original_start)| (content_start + start, original_start)),
+            );
+            result.extend_from_slice(&content);
+        }
+        pos = end;
+    }
+    runs.push((result.len(), pos));
+    result.extend_from_slice(&buf[pos..]);
+    Ok(LoweredModule {
+        buf: Cow::Owned(result),
+        runs,
+    })
+}
+
+/// Returns the id and the content range of the module sections.
+fn read_sections(buf: &[u8]) -> ParserResult<Vec<(u32, usize, usize)>> {
+    let mut sections = vec![];
+    if buf.len() < MODULE_HEADER_SIZE {
+        return Ok(sections);
+    }
+    let mut reader = BinaryReader::new_with_offset(&buf[MODULE_HEADER_SIZE..], MODULE_HEADER_SIZE);
+    while !reader.eof() {
+        let id = reader.read_u8()?;
+        let size = reader.read_var_u32()? as usize;
+        let start = reader.original_position();
+        reader.read_bytes(size)?;
+        sections.push((id, start, start + size));
+    }
+    Ok(sections)
+}
+
+fn write_var_u32(buf: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+enum CheckError {
+    Parser,
+    Invalid(ModuleError),
+}
+
+impl From<wasmparser::BinaryReaderError> for CheckError {
+    fn from(_: wasmparser::BinaryReaderError) -> Self {
+        CheckError::Parser
+    }
+}
+
+impl From<ModuleError> for CheckError {
+    fn from(err: ModuleError) -> Self {
+        CheckError::Invalid(err)
+    }
+}
+
+fn check_section(
+    id: u32,
+    buf: &[u8],
+    start: usize,
+    end: usize,
+    globals: &mut ConstExprGlobals,
+    memories: &mut Vec<Type>,
+) -> Result<Vec<Patch>, CheckError> {
+    let data = &buf[start..end];
+    let mut patches = vec![];
+    let mut add_patch = |expr: &InitExpr, ty: Type, globals: &ConstExprGlobals| {
+        if let Some(expr_end) = check_const_expr(expr, ty, globals)? {
+            let expr_start = expr.get_binary_reader().original_position();
+            patches.push(Patch {
+                start: expr_start - start,
+                end: expr_end - start,
+                replacement: match ty {
+                    Type::I64 => &[0x42, 0x00, 0x0b],
+                    _ => &[0x41, 0x00, 0x0b],
+                },
+            });
+        }
+        Ok::<(), CheckError>(())
+    };
+    match id {
+        IMPORT_SECTION_ID => {
+            for import in ImportSectionReader::new(data, start)? {
+                match import?.ty {
+                    ImportSectionEntryType::Global(ty) => {
+                        globals.globals.push((ty.content_type, ty.mutable))
+                    }
+                    ImportSectionEntryType::Memory(ty) => memories.push(memory_index_type(&ty)),
+                    _ => (),
+                }
+            }
+        }
+        MEMORY_SECTION_ID => {
+            for memory in MemorySectionReader::new(data, start)? {
+                memories.push(memory_index_type(&memory?));
+            }
+        }
+        GLOBAL_SECTION_ID => {
+            for global in GlobalSectionReader::new(data, start)? {
+                let global = global?;
+                add_patch(&global.init_expr, global.ty.content_type, globals)?;
+            }
+        }
+        ELEMENT_SECTION_ID => {
+            for element in ElementSectionReader::new(data, start)? {
+                if let ElementKind::Active { ref init_expr, .. } = element?.kind {
+                    add_patch(init_expr, Type::I32, globals)?;
+                }
+            }
+        }
+        DATA_SECTION_ID => {
+            for chunk in DataSectionReader::new(data, start)? {
+                if let DataKind::Active {
+                    memory_index,
+                    ref init_expr,
+                } = chunk?.kind
+                {
+                    // The unknown memory is reported by the validator.
+                    if let Some(ty) = memories.get(memory_index as usize) {
+                        add_patch(init_expr, *ty, globals)?;
+                    }
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(patches)
+}
+
+fn memory_index_type(ty: &MemoryType) -> Type {
+    match ty {
+        MemoryType::M32 { .. } => Type::I32,
+        MemoryType::M64 { .. } => Type::I64,
+    }
+}
+
+/// Type checks the expression that consists of more than one instruction,
+/// and returns the position after its `end`. The single instruction
+/// expressions are left to the validator, except for the globals they
+/// read: the validator accepts the ones defined in the module.
+fn check_const_expr(
+    expr: &InitExpr,
+    ty: Type,
+    globals: &ConstExprGlobals,
+) -> Result<Option<usize>, CheckError> {
+    let mut reader = expr.get_operators_reader();
+    let (op, offset) = reader.read_with_offset()?;
+    if let Operator::End = reader.read()? {
+        if let Operator::GlobalGet { global_index } = op {
+            globals.get(global_index, offset)?;
+        }
+        return Ok(None);
+    }
+
+    let mut reader = expr.get_operators_reader();
+    let mut stack = vec![];
+    loop {
+        let (op, offset) = reader.read_with_offset()?;
+        let (params, result): (&[Type], Type) = match op {
+            Operator::I32Const { .. } => (&[], Type::I32),
+            Operator::I64Const { .. } => (&[], Type::I64),
+            Operator::F32Const { .. } => (&[], Type::F32),
+            Operator::F64Const { .. } => (&[], Type::F64),
+            Operator::V128Const { .. } => (&[], Type::V128),
+            Operator::RefNull { ty } => (&[], ty),
+            Operator::RefFunc { .. } => (&[], Type::FuncRef),
+            Operator::GlobalGet { global_index } => (&[], globals.get(global_index, offset)?),
+            Operator::I32Add | Operator::I32Sub | Operator::I32Mul => {
+                (&[Type::I32, Type::I32], Type::I32)
+            }
+            Operator::I64Add | Operator::I64Sub | Operator::I64Mul => {
+                (&[Type::I64, Type::I64], Type::I64)
+            }
+            Operator::End => {
+                if stack.len() != 1 || stack[0] != ty {
+                    return Err(invalid("type mismatch in constant expression", offset).into());
+                }
+                return Ok(Some(reader.original_position()));
+            }
+            _ => return Err(invalid("constant expression required", offset).into()),
+        };
+        if stack.len() < params.len() || stack[stack.len() - params.len()..] != *params {
+            return Err(invalid("type mismatch in constant expression", offset).into());
+        }
+        stack.truncate(stack.len() - params.len());
+        stack.push(result);
+    }
+}
+
+/// Evaluates the validated constant expression.
+pub(crate) fn eval_const_expr(
+    context: &dyn EvalContext,
+    expr: &InitExpr,
+) -> Result<Val, ModuleError> {
+    let mut reader = expr.get_operators_reader();
+    let mut stack = vec![];
+    loop {
+        let (op, offset) = reader.read_with_offset()?;
+        let val = match op {
+            Operator::I32Const { value } => Val::I32(value),
+            Operator::I64Const { value } => Val::I64(value),
+            Operator::F32Const { value } => Val::F32(value.bits()),
+            Operator::F64Const { value } => Val::F64(value.bits()),
+            Operator::V128Const { value } => Val::V128(u128::from_le_bytes(*value.bytes())),
+            Operator::RefNull { ty } => get_default_value(ty.into()),
+            Operator::RefFunc { function_index } => {
+                Val::Func(Some(context.get_function(function_index)))
+            }
+            Operator::GlobalGet { global_index } => context.get_global(global_index).content(),
+            Operator::I32Add
+            | Operator::I32Sub
+            | Operator::I32Mul
+            | Operator::I64Add
+            | Operator::I64Sub
+            | Operator::I64Mul => {
+                let b = stack.pop();
+                let a = stack.pop();
+                match (op, a, b) {
+                    (Operator::I32Add, Some(Val::I32(a)), Some(Val::I32(b))) => {
+                        Val::I32(a.wrapping_add(b))
+                    }
+                    (Operator::I32Sub, Some(Val::I32(a)), Some(Val::I32(b))) => {
+                        Val::I32(a.wrapping_sub(b))
+                    }
+                    (Operator::I32Mul, Some(Val::I32(a)), Some(Val::I32(b))) => {
+                        Val::I32(a.wrapping_mul(b))
+                    }
+                    (Operator::I64Add, Some(Val::I64(a)), Some(Val::I64(b))) => {
+                        Val::I64(a.wrapping_add(b))
+                    }
+                    (Operator::I64Sub, Some(Val::I64(a)), Some(Val::I64(b))) => {
+                        Val::I64(a.wrapping_sub(b))
+                    }
+                    (Operator::I64Mul, Some(Val::I64(a)), Some(Val::I64(b))) => {
+                        Val::I64(a.wrapping_mul(b))
+                    }
+                    _ => return Err(invalid("type mismatch in constant expression", offset)),
+                }
+            }
+            Operator::End => {
+                return match (stack.pop(), stack.is_empty()) {
+                    (Some(val), true) => Ok(val),
+                    _ => Err(invalid("type mismatch in constant expression", offset)),
+                };
+            }
+            _ => return Err(invalid("constant expression required", offset)),
+        };
+        stack.push(val);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+
+use crate::externals::ExternType;
+use crate::values::Trap;
+
+#[derive(Debug)]
+pub enum LinkError {
+    ImportCount {
+        expected: usize,
+        actual: usize,
+    },
+    IncompatibleImport {
+        module: String,
+        field: String,
+        expected: ExternType,
+        actual: ExternType,
+    },
+    UnknownImport {
+        module: String,
+        field: String,
+        expected: ExternType,
+    },
+    DuplicateDefinition {
+        module: String,
+        field: String,
+    },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::ImportCount { expected, actual } => write!(
+                f,
+                "incompatible number of imports: expected {}, found {}",
+                expected, actual
+            ),
+            LinkError::IncompatibleImport {
+                module,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "incompatible import type for {}::{}: expected {:?}, found {:?}",
+                module, field, expected, actual
+            ),
+            LinkError::UnknownImport {
+                module,
+                field,
+                expected,
+            } => write!(
+                f,
+                "unknown import {}::{}: expected {:?}",
+                module, field, expected
+            ),
+            LinkError::DuplicateDefinition { module, field } => {
+                write!(f, "{}::{} is already defined", module, field)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ModuleError {
+    Malformed { message: String, offset: usize },
+    Unsupported(String),
+    Invalid { message: String, offset: usize },
+    Link(Box<LinkError>),
+    Trap(Trap),
+}
+
+impl ModuleError {
+    pub(crate) fn malformed(err: wasmparser::BinaryReaderError) -> Self {
+        ModuleError::Malformed {
+            message: err.message().to_string(),
+            offset: err.offset(),
+        }
+    }
+
+    /// The validator error, at the `offset` in the original module.
+    pub(crate) fn invalid(err: wasmparser::BinaryReaderError, offset: usize) -> Self {
+        ModuleError::Invalid {
+            message: err.message().to_string(),
+            offset,
+        }
+    }
+}
+
+impl From<wasmparser::BinaryReaderError> for ModuleError {
+    fn from(err: wasmparser::BinaryReaderError) -> Self {
+        ModuleError::malformed(err)
+    }
+}
+
+impl From<LinkError> for ModuleError {
+    fn from(err: LinkError) -> Self {
+        ModuleError::Link(Box::new(err))
+    }
+}
+
+impl From<Trap> for ModuleError {
+    fn from(trap: Trap) -> Self {
+        ModuleError::Trap(trap)
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::Malformed { message, offset } => {
+                write!(f, "malformed module: {} (at offset {})", message, offset)
+            }
+            ModuleError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            ModuleError::Invalid { message, offset } => {
+                write!(f, "invalid module: {} (at offset {})", message, offset)
+            }
+            ModuleError::Link(err) => write!(f, "link error: {}", err),
+            ModuleError::Trap(trap) => write!(f, "{}", trap),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl std::error::Error for ModuleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModuleError::Link(err) => Some(err.as_ref()),
+            ModuleError::Trap(trap) => Some(trap),
+            _ => None,
+        }
+    }
+}
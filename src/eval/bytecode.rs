@@ -1,172 +1,941 @@
+use crate::eval::register::RegisterCode;
 use crate::eval::EvalContext;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::vec::Vec;
-use wasmparser::OperatorsReader;
+use wasmparser::{
+    BinaryReader, FunctionBody, Ieee32, Ieee64, MemoryImmediate, Type, TypeOrFuncType,
+};
 
 pub use wasmparser::Operator;
 
-pub(crate) struct BytecodeCache {
-    // TODO anchor Operator<'static> lifetime to ModuleData
-    operators: Vec<Operator<'static>>,
-    ends: HashMap<usize, usize>,
-    elses: HashMap<usize, usize>,
-    max_control_depth: usize,
-    break_cache: HashMap<(usize, u32), BreakDestination>,
-    params: HashMap<usize, usize>,
+/// Pre-resolved branch: the `keep` values on the top of the stack replace
+/// the `drop` values below them, and the execution continues at `pc`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Branch {
+    pub pc: u32,
+    pub drop: u32,
+    pub keep: u32,
 }
 
-#[derive(Debug, Clone)]
-pub enum BreakDestination {
-    BlockEnd(usize, usize),
-    LoopStart(usize, usize),
+macro_rules! instructions {
+    ($($name:ident $({ $($field:ident: $ty:ty),* })? => ($pops:expr, $pushes:expr),)*) => {
+        /// Internal instruction, translated from the operator at the same
+        /// index of the function body.
+        #[derive(Debug, Clone)]
+        pub(crate) enum Instr {
+            Unreachable,
+            Nop,
+            Block,
+            Loop,
+            If { else_pc: u32 },
+            Else { end_pc: u32 },
+            End,
+            Br { branch: Branch },
+            BrIf { branch: Branch },
+            /// Selects one of the `len + 1` branches of the `br_tables`,
+            /// starting at `targets`; the last one is the default.
+            BrTable { targets: u32, len: u32 },
+            Return,
+            Call { function_index: u32 },
+            CallIndirect { index: u32, table_index: u32 },
+            ReturnCall { function_index: u32 },
+            ReturnCallIndirect { index: u32, table_index: u32 },
+            Select,
+            V128Const { index: u32 },
+            V8x16Shuffle { index: u32 },
+            $($name $({ $($field: $ty),* })?,)*
+            /// `local.get a; local.get b; i32.add`
+            LocalGetLocalGetI32Add { a: u32, b: u32 },
+            /// `i32.const; i32.add`
+            I32ConstI32Add { value: i32 },
+            /// `local.get; i32.load`, the `memarg` is read from the load.
+            LocalGetI32Load { local_index: u32 },
+            /// `i32.eqz; br_if`
+            I32EqzBrIf { branch: Branch },
+            /// `i32.eq; br_if`
+            I32EqBrIf { branch: Branch },
+            /// `i32.ne; br_if`
+            I32NeBrIf { branch: Branch },
+            /// `i32.lt_s; br_if`
+            I32LtSBrIf { branch: Branch },
+            /// `i32.lt_u; br_if`
+            I32LtUBrIf { branch: Branch },
+            /// `i32.gt_s; br_if`
+            I32GtSBrIf { branch: Branch },
+            /// `i32.gt_u; br_if`
+            I32GtUBrIf { branch: Branch },
+            /// `i32.le_s; br_if`
+            I32LeSBrIf { branch: Branch },
+            /// `i32.le_u; br_if`
+            I32LeUBrIf { branch: Branch },
+            /// `i32.ge_s; br_if`
+            I32GeSBrIf { branch: Branch },
+            /// `i32.ge_u; br_if`
+            I32GeUBrIf { branch: Branch },
+        }
+
+        impl Instr {
+            /// Returns the name of the operator the instruction was
+            /// translated from.
+            pub fn name(&self) -> &'static str {
+                match self.unfused() {
+                    Instr::Unreachable => "Unreachable",
+                    Instr::Nop => "Nop",
+                    Instr::Block => "Block",
+                    Instr::Loop => "Loop",
+                    Instr::If { .. } => "If",
+                    Instr::Else { .. } => "Else",
+                    Instr::End => "End",
+                    Instr::Br { .. } => "Br",
+                    Instr::BrIf { .. } => "BrIf",
+                    Instr::BrTable { .. } => "BrTable",
+                    Instr::Return => "Return",
+                    Instr::Call { .. } => "Call",
+                    Instr::CallIndirect { .. } => "CallIndirect",
+                    Instr::ReturnCall { .. } => "ReturnCall",
+                    Instr::ReturnCallIndirect { .. } => "ReturnCallIndirect",
+                    Instr::Select => "Select",
+                    Instr::V128Const { .. } => "V128Const",
+                    Instr::V8x16Shuffle { .. } => "V8x16Shuffle",
+                    $(Instr::$name $({ $($field: _),* })? => stringify!($name),)*
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        /// Translates the operators that do not depend on their position,
+        /// together with the numbers of values they pop and push.
+        fn translate_operator(op: &Operator) -> Option<(Instr, usize, usize)> {
+            Some(match *op {
+                $(Operator::$name $({ $($field,)* .. })? => {
+                    (Instr::$name $({ $($field),* })?, $pops, $pushes)
+                })*
+                _ => return None,
+            })
+        }
+    };
 }
 
-fn get_returns_count(context: &dyn EvalContext, ty: &wasmparser::TypeOrFuncType) -> usize {
-    use wasmparser::{Type, TypeOrFuncType};
-    match ty {
-        TypeOrFuncType::Type(Type::EmptyBlockType) => 0,
-        TypeOrFuncType::Type(_) => 1,
-        TypeOrFuncType::FuncType(index) => {
-            let ty = context.get_type(*index);
-            let len = ty.returns.len();
-            len
+instructions! {
+    Drop => (1, 0),
+    LocalGet { local_index: u32 } => (0, 1),
+    LocalSet { local_index: u32 } => (1, 0),
+    LocalTee { local_index: u32 } => (1, 1),
+    GlobalGet { global_index: u32 } => (0, 1),
+    GlobalSet { global_index: u32 } => (1, 0),
+    I32Load { memarg: MemoryImmediate } => (1, 1),
+    I64Load { memarg: MemoryImmediate } => (1, 1),
+    F32Load { memarg: MemoryImmediate } => (1, 1),
+    F64Load { memarg: MemoryImmediate } => (1, 1),
+    I32Load8S { memarg: MemoryImmediate } => (1, 1),
+    I32Load8U { memarg: MemoryImmediate } => (1, 1),
+    I32Load16S { memarg: MemoryImmediate } => (1, 1),
+    I32Load16U { memarg: MemoryImmediate } => (1, 1),
+    I64Load8S { memarg: MemoryImmediate } => (1, 1),
+    I64Load8U { memarg: MemoryImmediate } => (1, 1),
+    I64Load16S { memarg: MemoryImmediate } => (1, 1),
+    I64Load16U { memarg: MemoryImmediate } => (1, 1),
+    I64Load32S { memarg: MemoryImmediate } => (1, 1),
+    I64Load32U { memarg: MemoryImmediate } => (1, 1),
+    I32Store { memarg: MemoryImmediate } => (2, 0),
+    I64Store { memarg: MemoryImmediate } => (2, 0),
+    F32Store { memarg: MemoryImmediate } => (2, 0),
+    F64Store { memarg: MemoryImmediate } => (2, 0),
+    I32Store8 { memarg: MemoryImmediate } => (2, 0),
+    I32Store16 { memarg: MemoryImmediate } => (2, 0),
+    I64Store8 { memarg: MemoryImmediate } => (2, 0),
+    I64Store16 { memarg: MemoryImmediate } => (2, 0),
+    I64Store32 { memarg: MemoryImmediate } => (2, 0),
+    MemorySize { mem: u32 } => (0, 1),
+    MemoryGrow { mem: u32 } => (1, 1),
+    I32Const { value: i32 } => (0, 1),
+    I64Const { value: i64 } => (0, 1),
+    F32Const { value: Ieee32 } => (0, 1),
+    F64Const { value: Ieee64 } => (0, 1),
+    RefNull {} => (0, 1),
+    RefIsNull => (1, 1),
+    RefFunc { function_index: u32 } => (0, 1),
+    I32Eqz => (1, 1),
+    I32Eq => (2, 1),
+    I32Ne => (2, 1),
+    I32LtS => (2, 1),
+    I32LtU => (2, 1),
+    I32GtS => (2, 1),
+    I32GtU => (2, 1),
+    I32LeS => (2, 1),
+    I32LeU => (2, 1),
+    I32GeS => (2, 1),
+    I32GeU => (2, 1),
+    I64Eqz => (1, 1),
+    I64Eq => (2, 1),
+    I64Ne => (2, 1),
+    I64LtS => (2, 1),
+    I64LtU => (2, 1),
+    I64GtS => (2, 1),
+    I64GtU => (2, 1),
+    I64LeS => (2, 1),
+    I64LeU => (2, 1),
+    I64GeS => (2, 1),
+    I64GeU => (2, 1),
+    F32Eq => (2, 1),
+    F32Ne => (2, 1),
+    F32Lt => (2, 1),
+    F32Gt => (2, 1),
+    F32Le => (2, 1),
+    F32Ge => (2, 1),
+    F64Eq => (2, 1),
+    F64Ne => (2, 1),
+    F64Lt => (2, 1),
+    F64Gt => (2, 1),
+    F64Le => (2, 1),
+    F64Ge => (2, 1),
+    I32Clz => (1, 1),
+    I32Ctz => (1, 1),
+    I32Popcnt => (1, 1),
+    I32Add => (2, 1),
+    I32Sub => (2, 1),
+    I32Mul => (2, 1),
+    I32DivS => (2, 1),
+    I32DivU => (2, 1),
+    I32RemS => (2, 1),
+    I32RemU => (2, 1),
+    I32And => (2, 1),
+    I32Or => (2, 1),
+    I32Xor => (2, 1),
+    I32Shl => (2, 1),
+    I32ShrS => (2, 1),
+    I32ShrU => (2, 1),
+    I32Rotl => (2, 1),
+    I32Rotr => (2, 1),
+    I64Clz => (1, 1),
+    I64Ctz => (1, 1),
+    I64Popcnt => (1, 1),
+    I64Add => (2, 1),
+    I64Sub => (2, 1),
+    I64Mul => (2, 1),
+    I64DivS => (2, 1),
+    I64DivU => (2, 1),
+    I64RemS => (2, 1),
+    I64RemU => (2, 1),
+    I64And => (2, 1),
+    I64Or => (2, 1),
+    I64Xor => (2, 1),
+    I64Shl => (2, 1),
+    I64ShrS => (2, 1),
+    I64ShrU => (2, 1),
+    I64Rotl => (2, 1),
+    I64Rotr => (2, 1),
+    F32Abs => (1, 1),
+    F32Neg => (1, 1),
+    F32Ceil => (1, 1),
+    F32Floor => (1, 1),
+    F32Trunc => (1, 1),
+    F32Nearest => (1, 1),
+    F32Sqrt => (1, 1),
+    F32Add => (2, 1),
+    F32Sub => (2, 1),
+    F32Mul => (2, 1),
+    F32Div => (2, 1),
+    F32Min => (2, 1),
+    F32Max => (2, 1),
+    F32Copysign => (2, 1),
+    F64Abs => (1, 1),
+    F64Neg => (1, 1),
+    F64Ceil => (1, 1),
+    F64Floor => (1, 1),
+    F64Trunc => (1, 1),
+    F64Nearest => (1, 1),
+    F64Sqrt => (1, 1),
+    F64Add => (2, 1),
+    F64Sub => (2, 1),
+    F64Mul => (2, 1),
+    F64Div => (2, 1),
+    F64Min => (2, 1),
+    F64Max => (2, 1),
+    F64Copysign => (2, 1),
+    I32WrapI64 => (1, 1),
+    I32TruncF32S => (1, 1),
+    I32TruncF32U => (1, 1),
+    I32TruncF64S => (1, 1),
+    I32TruncF64U => (1, 1),
+    I64ExtendI32S => (1, 1),
+    I64ExtendI32U => (1, 1),
+    I64TruncF32S => (1, 1),
+    I64TruncF32U => (1, 1),
+    I64TruncF64S => (1, 1),
+    I64TruncF64U => (1, 1),
+    F32ConvertI32S => (1, 1),
+    F32ConvertI32U => (1, 1),
+    F32ConvertI64S => (1, 1),
+    F32ConvertI64U => (1, 1),
+    F32DemoteF64 => (1, 1),
+    F64ConvertI32S => (1, 1),
+    F64ConvertI32U => (1, 1),
+    F64ConvertI64S => (1, 1),
+    F64ConvertI64U => (1, 1),
+    F64PromoteF32 => (1, 1),
+    I32ReinterpretF32 => (1, 1),
+    I64ReinterpretF64 => (1, 1),
+    F32ReinterpretI32 => (1, 1),
+    F64ReinterpretI64 => (1, 1),
+    I32Extend8S => (1, 1),
+    I32Extend16S => (1, 1),
+    I64Extend8S => (1, 1),
+    I64Extend16S => (1, 1),
+    I64Extend32S => (1, 1),
+    I32TruncSatF32S => (1, 1),
+    I32TruncSatF32U => (1, 1),
+    I32TruncSatF64S => (1, 1),
+    I32TruncSatF64U => (1, 1),
+    I64TruncSatF32S => (1, 1),
+    I64TruncSatF32U => (1, 1),
+    I64TruncSatF64S => (1, 1),
+    I64TruncSatF64U => (1, 1),
+    MemoryInit { segment: u32, mem: u32 } => (3, 0),
+    DataDrop { segment: u32 } => (0, 0),
+    MemoryCopy { src: u32, dst: u32 } => (3, 0),
+    MemoryFill { mem: u32 } => (3, 0),
+    TableInit { segment: u32, table: u32 } => (3, 0),
+    ElemDrop { segment: u32 } => (0, 0),
+    TableCopy { dst_table: u32, src_table: u32 } => (3, 0),
+    TableFill { table: u32 } => (3, 0),
+    TableGet { table: u32 } => (1, 1),
+    TableSet { table: u32 } => (2, 0),
+    TableGrow { table: u32 } => (2, 1),
+    TableSize { table: u32 } => (0, 1),
+    MemoryAtomicNotify { memarg: MemoryImmediate } => (2, 1),
+    MemoryAtomicWait32 { memarg: MemoryImmediate } => (3, 1),
+    MemoryAtomicWait64 { memarg: MemoryImmediate } => (3, 1),
+    AtomicFence {} => (0, 0),
+    I32AtomicLoad { memarg: MemoryImmediate } => (1, 1),
+    I64AtomicLoad { memarg: MemoryImmediate } => (1, 1),
+    I32AtomicLoad8U { memarg: MemoryImmediate } => (1, 1),
+    I32AtomicLoad16U { memarg: MemoryImmediate } => (1, 1),
+    I64AtomicLoad8U { memarg: MemoryImmediate } => (1, 1),
+    I64AtomicLoad16U { memarg: MemoryImmediate } => (1, 1),
+    I64AtomicLoad32U { memarg: MemoryImmediate } => (1, 1),
+    I32AtomicStore { memarg: MemoryImmediate } => (2, 0),
+    I64AtomicStore { memarg: MemoryImmediate } => (2, 0),
+    I32AtomicStore8 { memarg: MemoryImmediate } => (2, 0),
+    I32AtomicStore16 { memarg: MemoryImmediate } => (2, 0),
+    I64AtomicStore8 { memarg: MemoryImmediate } => (2, 0),
+    I64AtomicStore16 { memarg: MemoryImmediate } => (2, 0),
+    I64AtomicStore32 { memarg: MemoryImmediate } => (2, 0),
+    I32AtomicRmwAdd { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmwAdd { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw8AddU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw16AddU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw8AddU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw16AddU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw32AddU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmwSub { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmwSub { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw8SubU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw16SubU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw8SubU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw16SubU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw32SubU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmwAnd { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmwAnd { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw8AndU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw16AndU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw8AndU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw16AndU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw32AndU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmwOr { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmwOr { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw8OrU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw16OrU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw8OrU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw16OrU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw32OrU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmwXor { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmwXor { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw8XorU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw16XorU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw8XorU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw16XorU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw32XorU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmwXchg { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmwXchg { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw8XchgU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmw16XchgU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw8XchgU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw16XchgU { memarg: MemoryImmediate } => (2, 1),
+    I64AtomicRmw32XchgU { memarg: MemoryImmediate } => (2, 1),
+    I32AtomicRmwCmpxchg { memarg: MemoryImmediate } => (3, 1),
+    I64AtomicRmwCmpxchg { memarg: MemoryImmediate } => (3, 1),
+    I32AtomicRmw8CmpxchgU { memarg: MemoryImmediate } => (3, 1),
+    I32AtomicRmw16CmpxchgU { memarg: MemoryImmediate } => (3, 1),
+    I64AtomicRmw8CmpxchgU { memarg: MemoryImmediate } => (3, 1),
+    I64AtomicRmw16CmpxchgU { memarg: MemoryImmediate } => (3, 1),
+    I64AtomicRmw32CmpxchgU { memarg: MemoryImmediate } => (3, 1),
+    V128Load { memarg: MemoryImmediate } => (1, 1),
+    V128Store { memarg: MemoryImmediate } => (2, 0),
+    I8x16Splat => (1, 1),
+    I8x16ExtractLaneS { lane: u8 } => (1, 1),
+    I8x16ExtractLaneU { lane: u8 } => (1, 1),
+    I8x16ReplaceLane { lane: u8 } => (2, 1),
+    I16x8Splat => (1, 1),
+    I16x8ExtractLaneS { lane: u8 } => (1, 1),
+    I16x8ExtractLaneU { lane: u8 } => (1, 1),
+    I16x8ReplaceLane { lane: u8 } => (2, 1),
+    I32x4Splat => (1, 1),
+    I32x4ExtractLane { lane: u8 } => (1, 1),
+    I32x4ReplaceLane { lane: u8 } => (2, 1),
+    I64x2Splat => (1, 1),
+    I64x2ExtractLane { lane: u8 } => (1, 1),
+    I64x2ReplaceLane { lane: u8 } => (2, 1),
+    F32x4Splat => (1, 1),
+    F32x4ExtractLane { lane: u8 } => (1, 1),
+    F32x4ReplaceLane { lane: u8 } => (2, 1),
+    F64x2Splat => (1, 1),
+    F64x2ExtractLane { lane: u8 } => (1, 1),
+    F64x2ReplaceLane { lane: u8 } => (2, 1),
+    I8x16Eq => (2, 1),
+    I8x16Ne => (2, 1),
+    I8x16LtS => (2, 1),
+    I8x16LtU => (2, 1),
+    I8x16GtS => (2, 1),
+    I8x16GtU => (2, 1),
+    I8x16LeS => (2, 1),
+    I8x16LeU => (2, 1),
+    I8x16GeS => (2, 1),
+    I8x16GeU => (2, 1),
+    I16x8Eq => (2, 1),
+    I16x8Ne => (2, 1),
+    I16x8LtS => (2, 1),
+    I16x8LtU => (2, 1),
+    I16x8GtS => (2, 1),
+    I16x8GtU => (2, 1),
+    I16x8LeS => (2, 1),
+    I16x8LeU => (2, 1),
+    I16x8GeS => (2, 1),
+    I16x8GeU => (2, 1),
+    I32x4Eq => (2, 1),
+    I32x4Ne => (2, 1),
+    I32x4LtS => (2, 1),
+    I32x4LtU => (2, 1),
+    I32x4GtS => (2, 1),
+    I32x4GtU => (2, 1),
+    I32x4LeS => (2, 1),
+    I32x4LeU => (2, 1),
+    I32x4GeS => (2, 1),
+    I32x4GeU => (2, 1),
+    F32x4Eq => (2, 1),
+    F32x4Ne => (2, 1),
+    F32x4Lt => (2, 1),
+    F32x4Gt => (2, 1),
+    F32x4Le => (2, 1),
+    F32x4Ge => (2, 1),
+    F64x2Eq => (2, 1),
+    F64x2Ne => (2, 1),
+    F64x2Lt => (2, 1),
+    F64x2Gt => (2, 1),
+    F64x2Le => (2, 1),
+    F64x2Ge => (2, 1),
+    V128Not => (1, 1),
+    V128And => (2, 1),
+    V128AndNot => (2, 1),
+    V128Or => (2, 1),
+    V128Xor => (2, 1),
+    V128Bitselect => (3, 1),
+    I8x16Abs => (1, 1),
+    I8x16Neg => (1, 1),
+    I8x16AnyTrue => (1, 1),
+    I8x16AllTrue => (1, 1),
+    I8x16Bitmask => (1, 1),
+    I8x16Shl => (2, 1),
+    I8x16ShrS => (2, 1),
+    I8x16ShrU => (2, 1),
+    I8x16Add => (2, 1),
+    I8x16AddSaturateS => (2, 1),
+    I8x16AddSaturateU => (2, 1),
+    I8x16Sub => (2, 1),
+    I8x16SubSaturateS => (2, 1),
+    I8x16SubSaturateU => (2, 1),
+    I8x16MinS => (2, 1),
+    I8x16MinU => (2, 1),
+    I8x16MaxS => (2, 1),
+    I8x16MaxU => (2, 1),
+    I16x8Abs => (1, 1),
+    I16x8Neg => (1, 1),
+    I16x8AnyTrue => (1, 1),
+    I16x8AllTrue => (1, 1),
+    I16x8Bitmask => (1, 1),
+    I16x8Shl => (2, 1),
+    I16x8ShrS => (2, 1),
+    I16x8ShrU => (2, 1),
+    I16x8Add => (2, 1),
+    I16x8AddSaturateS => (2, 1),
+    I16x8AddSaturateU => (2, 1),
+    I16x8Sub => (2, 1),
+    I16x8SubSaturateS => (2, 1),
+    I16x8SubSaturateU => (2, 1),
+    I16x8Mul => (2, 1),
+    I16x8MinS => (2, 1),
+    I16x8MinU => (2, 1),
+    I16x8MaxS => (2, 1),
+    I16x8MaxU => (2, 1),
+    I32x4Abs => (1, 1),
+    I32x4Neg => (1, 1),
+    I32x4AnyTrue => (1, 1),
+    I32x4AllTrue => (1, 1),
+    I32x4Bitmask => (1, 1),
+    I32x4Shl => (2, 1),
+    I32x4ShrS => (2, 1),
+    I32x4ShrU => (2, 1),
+    I32x4Add => (2, 1),
+    I32x4Sub => (2, 1),
+    I32x4Mul => (2, 1),
+    I32x4MinS => (2, 1),
+    I32x4MinU => (2, 1),
+    I32x4MaxS => (2, 1),
+    I32x4MaxU => (2, 1),
+    I64x2Neg => (1, 1),
+    I64x2Shl => (2, 1),
+    I64x2ShrS => (2, 1),
+    I64x2ShrU => (2, 1),
+    I64x2Add => (2, 1),
+    I64x2Sub => (2, 1),
+    I64x2Mul => (2, 1),
+    F32x4Abs => (1, 1),
+    F32x4Neg => (1, 1),
+    F32x4Sqrt => (1, 1),
+    F32x4Add => (2, 1),
+    F32x4Sub => (2, 1),
+    F32x4Mul => (2, 1),
+    F32x4Div => (2, 1),
+    F32x4Min => (2, 1),
+    F32x4Max => (2, 1),
+    F64x2Abs => (1, 1),
+    F64x2Neg => (1, 1),
+    F64x2Sqrt => (1, 1),
+    F64x2Add => (2, 1),
+    F64x2Sub => (2, 1),
+    F64x2Mul => (2, 1),
+    F64x2Div => (2, 1),
+    F64x2Min => (2, 1),
+    F64x2Max => (2, 1),
+    I32x4TruncSatF32x4S => (1, 1),
+    I32x4TruncSatF32x4U => (1, 1),
+    F32x4ConvertI32x4S => (1, 1),
+    F32x4ConvertI32x4U => (1, 1),
+    V8x16Swizzle => (2, 1),
+    V8x16LoadSplat { memarg: MemoryImmediate } => (1, 1),
+    V16x8LoadSplat { memarg: MemoryImmediate } => (1, 1),
+    V32x4LoadSplat { memarg: MemoryImmediate } => (1, 1),
+    V64x2LoadSplat { memarg: MemoryImmediate } => (1, 1),
+    I8x16NarrowI16x8S => (2, 1),
+    I8x16NarrowI16x8U => (2, 1),
+    I16x8NarrowI32x4S => (2, 1),
+    I16x8NarrowI32x4U => (2, 1),
+    I16x8WidenLowI8x16S => (1, 1),
+    I16x8WidenHighI8x16S => (1, 1),
+    I16x8WidenLowI8x16U => (1, 1),
+    I16x8WidenHighI8x16U => (1, 1),
+    I32x4WidenLowI16x8S => (1, 1),
+    I32x4WidenHighI16x8S => (1, 1),
+    I32x4WidenLowI16x8U => (1, 1),
+    I32x4WidenHighI16x8U => (1, 1),
+    I16x8Load8x8S { memarg: MemoryImmediate } => (1, 1),
+    I16x8Load8x8U { memarg: MemoryImmediate } => (1, 1),
+    I32x4Load16x4S { memarg: MemoryImmediate } => (1, 1),
+    I32x4Load16x4U { memarg: MemoryImmediate } => (1, 1),
+    I64x2Load32x2S { memarg: MemoryImmediate } => (1, 1),
+    I64x2Load32x2U { memarg: MemoryImmediate } => (1, 1),
+    I8x16RoundingAverageU => (2, 1),
+    I16x8RoundingAverageU => (2, 1),
+}
+
+impl Instr {
+    /// Returns the instruction a superinstruction replaced, or the same
+    /// instruction if it is not a superinstruction.
+    pub fn unfused(&self) -> Instr {
+        match *self {
+            Instr::LocalGetLocalGetI32Add { a, .. } => Instr::LocalGet { local_index: a },
+            Instr::I32ConstI32Add { value } => Instr::I32Const { value },
+            Instr::LocalGetI32Load { local_index } => Instr::LocalGet { local_index },
+            Instr::I32EqzBrIf { .. } => Instr::I32Eqz,
+            Instr::I32EqBrIf { .. } => Instr::I32Eq,
+            Instr::I32NeBrIf { .. } => Instr::I32Ne,
+            Instr::I32LtSBrIf { .. } => Instr::I32LtS,
+            Instr::I32LtUBrIf { .. } => Instr::I32LtU,
+            Instr::I32GtSBrIf { .. } => Instr::I32GtS,
+            Instr::I32GtUBrIf { .. } => Instr::I32GtU,
+            Instr::I32LeSBrIf { .. } => Instr::I32LeS,
+            Instr::I32LeUBrIf { .. } => Instr::I32LeU,
+            Instr::I32GeSBrIf { .. } => Instr::I32GeS,
+            Instr::I32GeUBrIf { .. } => Instr::I32GeU,
+            ref instr => instr.clone(),
         }
     }
 }
 
-fn get_params_count(context: &dyn EvalContext, ty: &wasmparser::TypeOrFuncType) -> usize {
-    use wasmparser::TypeOrFuncType;
+/// Replaces the first instruction of the frequent sequences with the
+/// superinstruction that executes the whole sequence. The rest of the
+/// sequence stays in place, so the instructions still match the operators,
+/// and is skipped by the superinstruction. Only the instructions after the
+/// control instructions are branch targets, so the fused sequences are
+/// never entered in the middle.
+fn fuse(instrs: &mut [Instr]) {
+    let mut pc = 0;
+    while pc < instrs.len() {
+        let fused = match instrs[pc..] {
+            [Instr::LocalGet { local_index: a }, Instr::LocalGet { local_index: b }, Instr::I32Add, ..] => {
+                Some((Instr::LocalGetLocalGetI32Add { a, b }, 3))
+            }
+            [Instr::I32Const { value }, Instr::I32Add, ..] => {
+                Some((Instr::I32ConstI32Add { value }, 2))
+            }
+            [Instr::LocalGet { local_index }, Instr::I32Load { .. }, ..] => {
+                Some((Instr::LocalGetI32Load { local_index }, 2))
+            }
+            [ref compare, Instr::BrIf { branch }, ..] => match *compare {
+                Instr::I32Eqz => Some(Instr::I32EqzBrIf { branch }),
+                Instr::I32Eq => Some(Instr::I32EqBrIf { branch }),
+                Instr::I32Ne => Some(Instr::I32NeBrIf { branch }),
+                Instr::I32LtS => Some(Instr::I32LtSBrIf { branch }),
+                Instr::I32LtU => Some(Instr::I32LtUBrIf { branch }),
+                Instr::I32GtS => Some(Instr::I32GtSBrIf { branch }),
+                Instr::I32GtU => Some(Instr::I32GtUBrIf { branch }),
+                Instr::I32LeS => Some(Instr::I32LeSBrIf { branch }),
+                Instr::I32LeU => Some(Instr::I32LeUBrIf { branch }),
+                Instr::I32GeS => Some(Instr::I32GeSBrIf { branch }),
+                Instr::I32GeU => Some(Instr::I32GeUBrIf { branch }),
+                _ => None,
+            }
+            .map(|instr| (instr, 2)),
+            _ => None,
+        };
+        match fused {
+            Some((instr, len)) => {
+                instrs[pc] = instr;
+                pc += len;
+            }
+            None => pc += 1,
+        }
+    }
+}
+
+/// Returns the number of the block params and results.
+fn block_type(context: &dyn EvalContext, ty: TypeOrFuncType) -> (usize, usize) {
     match ty {
-        TypeOrFuncType::Type(_) => 0,
+        TypeOrFuncType::Type(Type::EmptyBlockType) => (0, 0),
+        TypeOrFuncType::Type(_) => (0, 1),
         TypeOrFuncType::FuncType(index) => {
-            let ty = context.get_type(*index);
-            let len = ty.params.len();
-            len
+            let ty = context.get_type(index);
+            (ty.params.len(), ty.returns.len())
+        }
+    }
+}
+
+enum BranchSource {
+    Instr(usize),
+    Table(usize),
+}
+
+struct ControlFrame {
+    /// The operand stack height below the block params.
+    height: usize,
+    params: usize,
+    results: usize,
+    /// Branches to loops go back to the start, other branches are
+    /// collected and resolved at the `end`.
+    loop_start: Option<usize>,
+    branches: Vec<BranchSource>,
+    if_pc: Option<usize>,
+    else_pc: Option<usize>,
+}
+
+impl ControlFrame {
+    fn new(height: usize, params: usize, results: usize) -> ControlFrame {
+        ControlFrame {
+            height: height - params,
+            params,
+            results,
+            loop_start: None,
+            branches: Vec::new(),
+            if_pc: None,
+            else_pc: None,
+        }
+    }
+}
+
+/// Creates the branch to the block at `depth`. The code after branches and
+/// `unreachable` is never executed, so its stack heights do not matter and
+/// are only kept from underflowing.
+fn branch_to(
+    control: &mut [ControlFrame],
+    depth: u32,
+    height: usize,
+    source: BranchSource,
+) -> Branch {
+    let j = control.len() - 1 - depth as usize;
+    let frame = &mut control[j];
+    let keep = match frame.loop_start {
+        Some(_) => frame.params,
+        None => frame.results,
+    };
+    let drop = height.saturating_sub(keep).saturating_sub(frame.height);
+    let pc = match frame.loop_start {
+        Some(start) => start,
+        None => {
+            frame.branches.push(source);
+            0
         }
+    };
+    Branch {
+        pc: pc as u32,
+        drop: drop as u32,
+        keep: keep as u32,
     }
 }
 
+pub(crate) struct BytecodeCache {
+    instrs: Box<[Instr]>,
+    br_tables: Box<[Branch]>,
+    v128_constants: Box<[u128]>,
+    /// The function body operators: instructions are translated one to one,
+    /// so the operators can be read again for their fuel costs and offsets.
+    code: Box<[u8]>,
+    code_offset: usize,
+    /// The operand stack heights before the instructions, and at the end.
+    heights: Box<[u32]>,
+    fuel_costs: RefCell<Option<(usize, Rc<[u64]>)>>,
+    register_code: RefCell<Option<Rc<RegisterCode>>>,
+}
+
 impl BytecodeCache {
-    pub fn new(
-        reader: OperatorsReader<'static>,
-        context: &dyn EvalContext,
-        returns_count: usize,
-    ) -> Self {
-        let operators = reader
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()
-            .expect("ops");
-        let mut ends = HashMap::new();
-        let mut elses = HashMap::new();
-        let mut max_control_depth = 0;
-        let mut break_cache = HashMap::new();
-        let mut params = HashMap::new();
-
-        let mut control: Vec<(usize, Option<usize>, Vec<(usize, u32)>)> = Vec::new();
-        for i in (0..operators.len()).rev() {
-            match operators[i] {
+    pub fn new(body: &FunctionBody, context: &dyn EvalContext, returns_count: usize) -> Self {
+        let code_offset = body
+            .get_operators_reader()
+            .expect("operators reader")
+            .original_position();
+        let range = body.range();
+        let mut reader = body.get_binary_reader();
+        reader
+            .read_bytes(code_offset - range.start)
+            .expect("locals");
+        let code: Box<[u8]> = reader
+            .read_bytes(range.end - code_offset)
+            .expect("operators")
+            .into();
+
+        let mut instrs = Vec::new();
+        let mut heights = Vec::new();
+        let mut br_tables: Vec<Branch> = Vec::new();
+        let mut v128_constants = Vec::new();
+        let mut control = vec![ControlFrame::new(0, 0, returns_count)];
+        let mut height = 0;
+        let mut reader = BinaryReader::new_with_offset(&code, code_offset);
+        while !reader.eof() {
+            let pc = instrs.len();
+            heights.push(height as u32);
+            let op = reader.read_operator().expect("ops");
+            let (instr, pops, pushes) = match op {
+                Operator::Unreachable => {
+                    height = control.last().unwrap().height;
+                    (Instr::Unreachable, 0, 0)
+                }
+                Operator::Nop => (Instr::Nop, 0, 0),
+                Operator::Block { ty } => {
+                    let (params, results) = block_type(context, ty);
+                    control.push(ControlFrame::new(height, params, results));
+                    (Instr::Block, 0, 0)
+                }
+                Operator::Loop { ty } => {
+                    let (params, results) = block_type(context, ty);
+                    let mut frame = ControlFrame::new(height, params, results);
+                    frame.loop_start = Some(pc + 1);
+                    control.push(frame);
+                    (Instr::Loop, 0, 0)
+                }
+                Operator::If { ty } => {
+                    height = height.saturating_sub(1);
+                    let (params, results) = block_type(context, ty);
+                    let mut frame = ControlFrame::new(height, params, results);
+                    frame.if_pc = Some(pc);
+                    control.push(frame);
+                    (Instr::If { else_pc: 0 }, 0, 0)
+                }
+                Operator::Else => {
+                    let frame = control.last_mut().unwrap();
+                    let if_pc = frame.if_pc.take().unwrap();
+                    instrs[if_pc] = Instr::If {
+                        else_pc: pc as u32 + 1,
+                    };
+                    frame.else_pc = Some(pc);
+                    height = frame.height + frame.params;
+                    (Instr::Else { end_pc: 0 }, 0, 0)
+                }
                 Operator::End => {
-                    control.push((i, None, Vec::new()));
-                    max_control_depth = max_control_depth.max(control.len());
-                }
-                Operator::Loop { ref ty } => {
-                    let (_, _, jumps) = control.pop().unwrap();
-                    let params_count = get_params_count(context, ty);
-                    params.insert(i, params_count);
-                    for br in jumps {
-                        break_cache.insert(br, BreakDestination::LoopStart(i + 1, params_count));
+                    let frame = control.pop().unwrap();
+                    let next_pc = pc as u32 + 1;
+                    if let Some(if_pc) = frame.if_pc {
+                        instrs[if_pc] = Instr::If { else_pc: next_pc };
                     }
-                }
-                Operator::Block { ref ty } => {
-                    let (end, _, jumps) = control.pop().unwrap();
-                    let returns_count = get_returns_count(context, ty);
-                    params.insert(i, get_params_count(context, ty));
-                    for br in jumps {
-                        break_cache.insert(br, BreakDestination::BlockEnd(end + 1, returns_count));
+                    if let Some(else_pc) = frame.else_pc {
+                        instrs[else_pc] = Instr::Else { end_pc: next_pc };
                     }
-                }
-                Operator::If { ref ty } => {
-                    let (end, maybe_else, jumps) = control.pop().unwrap();
-                    if let Some(el) = maybe_else {
-                        elses.insert(i, el + 1);
-                    } else {
-                        elses.insert(i, end + 1);
+                    for source in frame.branches {
+                        match source {
+                            BranchSource::Instr(j) => match instrs[j] {
+                                Instr::Br { ref mut branch } | Instr::BrIf { ref mut branch } => {
+                                    branch.pc = next_pc
+                                }
+                                _ => unreachable!(),
+                            },
+                            BranchSource::Table(j) => br_tables[j].pc = next_pc,
+                        }
                     }
-                    params.insert(i, get_params_count(context, ty));
-                    let returns_count = get_returns_count(context, ty);
-                    for br in jumps {
-                        break_cache.insert(br, BreakDestination::BlockEnd(end + 1, returns_count));
+                    height = frame.height + frame.results;
+                    if control.is_empty() {
+                        (Instr::Return, 0, 0)
+                    } else {
+                        (Instr::End, 0, 0)
                     }
                 }
-
-                Operator::Else => {
-                    control.last_mut().unwrap().1 = Some(i);
-                    ends.insert(i, control.last_mut().unwrap().0 + 1);
+                Operator::Br { relative_depth } => {
+                    let source = BranchSource::Instr(pc);
+                    let branch = branch_to(&mut control, relative_depth, height, source);
+                    height = control.last().unwrap().height;
+                    (Instr::Br { branch }, 0, 0)
                 }
-
-                Operator::Br { relative_depth } | Operator::BrIf { relative_depth } => {
-                    let j = control.len() - 1 - relative_depth as usize;
-                    control[j].2.push((i, relative_depth));
+                Operator::BrIf { relative_depth } => {
+                    height = height.saturating_sub(1);
+                    let source = BranchSource::Instr(pc);
+                    let branch = branch_to(&mut control, relative_depth, height, source);
+                    (Instr::BrIf { branch }, 0, 0)
                 }
                 Operator::BrTable { ref table } => {
+                    height = height.saturating_sub(1);
+                    let targets = br_tables.len();
                     for entry in table.targets() {
-                        let relative_depth = entry.unwrap().0;
-                        let j = control.len() - 1 - relative_depth as usize;
-                        control[j].2.push((i, relative_depth));
+                        let (relative_depth, _) = entry.expect("br_table entry");
+                        let source = BranchSource::Table(br_tables.len());
+                        let branch = branch_to(&mut control, relative_depth, height, source);
+                        br_tables.push(branch);
                     }
+                    height = control.last().unwrap().height;
+                    let instr = Instr::BrTable {
+                        targets: targets as u32,
+                        len: table.len() as u32,
+                    };
+                    (instr, 0, 0)
                 }
-
-                _ => (),
-            }
-        }
-
-        assert!(control.len() == 1);
-        let (end, _, jumps) = control.into_iter().next().unwrap();
-        for br in jumps {
-            break_cache.insert(br, BreakDestination::BlockEnd(end + 1, returns_count));
+                Operator::Return => {
+                    height = control.last().unwrap().height;
+                    (Instr::Return, 0, 0)
+                }
+                Operator::Call { function_index } => {
+                    let f = context.get_function(function_index);
+                    let ty = f.ty();
+                    let instr = Instr::Call { function_index };
+                    (instr, ty.params.len(), ty.returns.len())
+                }
+                Operator::CallIndirect { index, table_index } => {
+                    let ty = context.get_type(index);
+                    let instr = Instr::CallIndirect { index, table_index };
+                    (instr, ty.params.len() + 1, ty.returns.len())
+                }
+                Operator::ReturnCall { function_index } => {
+                    height = control.last().unwrap().height;
+                    (Instr::ReturnCall { function_index }, 0, 0)
+                }
+                Operator::ReturnCallIndirect { index, table_index } => {
+                    height = control.last().unwrap().height;
+                    (Instr::ReturnCallIndirect { index, table_index }, 0, 0)
+                }
+                Operator::Select | Operator::TypedSelect { .. } => (Instr::Select, 3, 1),
+                Operator::V128Const { value } => {
+                    let index = v128_constants.len() as u32;
+                    v128_constants.push(u128::from_le_bytes(*value.bytes()));
+                    (Instr::V128Const { index }, 0, 1)
+                }
+                Operator::V8x16Shuffle { lanes } => {
+                    let index = v128_constants.len() as u32;
+                    v128_constants.push(u128::from_le_bytes(lanes));
+                    (Instr::V8x16Shuffle { index }, 2, 1)
+                }
+                ref op => translate_operator(op).expect("operator"),
+            };
+            height = height.saturating_sub(pops) + pushes;
+            instrs.push(instr);
         }
+        assert!(control.is_empty());
+        heights.push(height as u32);
+        fuse(&mut instrs);
 
         BytecodeCache {
-            operators,
-            ends,
-            elses,
-            max_control_depth,
-            break_cache,
-            params,
+            instrs: instrs.into_boxed_slice(),
+            br_tables: br_tables.into_boxed_slice(),
+            v128_constants: v128_constants.into_boxed_slice(),
+            code,
+            code_offset,
+            heights: heights.into_boxed_slice(),
+            fuel_costs: RefCell::new(None),
+            register_code: RefCell::new(None),
         }
     }
 
-    pub fn break_to(&self, from: usize, depth: u32) -> BreakDestination {
-        self.break_cache[&(from, depth)].clone()
+    pub fn instrs(&self) -> &[Instr] {
+        &self.instrs
     }
 
-    pub fn skip_to_else(&self, from_if: usize) -> usize {
-        self.elses[&from_if].clone()
+    pub fn br_table_entry(&self, index: u32) -> &Branch {
+        &self.br_tables[index as usize]
     }
 
-    pub fn skip_to_end(&self, from_else: usize) -> usize {
-        self.ends[&from_else].clone()
+    pub fn v128_constant(&self, index: u32) -> u128 {
+        self.v128_constants[index as usize]
     }
 
-    pub fn len(&self) -> usize {
-        self.operators.len()
+    /// Returns the operand stack height before the instruction. The heights
+    /// of the unreachable code are not meaningful.
+    pub fn height(&self, pc: usize) -> usize {
+        self.heights[pc] as usize
     }
 
-    pub fn operators(&self) -> &[Operator] {
-        &self.operators
+    /// Returns the register form of the function, lowered on the first use.
+    pub fn register_code(&self, locals: usize, return_arity: usize) -> Rc<RegisterCode> {
+        self.register_code
+            .borrow_mut()
+            .get_or_insert_with(|| Rc::new(RegisterCode::new(self, locals, return_arity)))
+            .clone()
     }
 
-    pub fn block_params_count(&self, i: usize) -> usize {
-        self.params[&i].clone()
+    fn operators(&self) -> impl Iterator<Item = (Operator<'_>, usize)> + '_ {
+        let mut reader = BinaryReader::new_with_offset(&self.code, self.code_offset);
+        std::iter::from_fn(move || {
+            if reader.eof() {
+                return None;
+            }
+            let position = reader.original_position();
+            Some((reader.read_operator().expect("ops"), position))
+        })
     }
 
-    pub fn position(&self, i: usize) -> usize {
-        // TODO real bytecode position
-        i
+    /// Returns the costs of the instructions: the costs of the operators they
+    /// were translated from. The costs are cached for the last `cost` function.
+    pub fn fuel_costs(&self, cost: fn(&Operator) -> u64) -> Rc<[u64]> {
+        let mut cache = self.fuel_costs.borrow_mut();
+        if let Some((cached, ref costs)) = *cache {
+            if cached == cost as usize {
+                return costs.clone();
+            }
+        }
+        let costs: Rc<[u64]> = self.operators().map(|(op, _)| cost(&op)).collect();
+        *cache = Some((cost as usize, costs.clone()));
+        costs
     }
 
-    pub fn max_control_depth(&self) -> usize {
-        self.max_control_depth
+    /// Returns the module offset of the instruction.
+    pub fn position(&self, pc: usize) -> usize {
+        self.operators()
+            .nth(pc)
+            .map_or(self.code_offset + self.code.len(), |(_, position)| position)
     }
 }
 
@@ -1,15 +1,136 @@
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use crate::eval::{Operator, SequenceProfile};
 use crate::externals::{Func, FuncType, Global, Memory, Table};
-use crate::instance::InstanceData;
+use crate::instance::{ElementItems, InstanceData};
+use crate::values::Val;
+
+const DEFAULT_MAX_CALL_DEPTH: usize = 10000;
+pub(crate) const DEFAULT_VALUE_STACK_SIZE: usize = 10000;
+
+/// Limits of the interpreter stacks. Only the first nested calls are kept
+/// on the native stack, the frames of the deeper ones are on the heap, so
+/// the call depth does not depend on the thread running the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackLimits {
+    pub max_call_depth: usize,
+    pub value_stack_size: usize,
+}
+
+impl Default for StackLimits {
+    fn default() -> Self {
+        StackLimits {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            value_stack_size: DEFAULT_VALUE_STACK_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuelGranularity {
+    PerInstruction,
+    /// Fuel is charged at control flow instructions for all operators
+    /// executed since the previous charge.
+    PerBasicBlock,
+}
+
+/// Form of the function bodies the interpreter executes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvalMode {
+    /// The operators are executed on the value stack.
+    #[default]
+    Stack,
+    /// The locals and the operand stack slots are registers of the frame,
+    /// and the arithmetic operators read and write them directly. The fuel
+    /// is not counted in this mode: the stack is used when it is enabled.
+    Register,
+    /// Same as `Stack`, without the superinstructions, and the executed
+    /// instruction sequences are counted in the `profile` of the context.
+    Profile,
+}
+
+#[derive(Clone, Copy)]
+pub struct FuelConfig {
+    pub cost: fn(&Operator) -> u64,
+    pub granularity: FuelGranularity,
+}
+
+impl Default for FuelConfig {
+    fn default() -> Self {
+        FuelConfig {
+            cost: |_| 1,
+            granularity: FuelGranularity::PerInstruction,
+        }
+    }
+}
+
+/// Stops execution of the instance code from any thread. The running code
+/// is unwound with the `Interrupted` trap at the next loop iteration or call.
+/// An interrupt requested while nothing runs stops the next call.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub(crate) fn new() -> InterruptHandle {
+        InterruptHandle {
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+    }
+
+    /// Resets the pending interrupt, and returns whether it was requested.
+    pub(crate) fn take(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed) && self.interrupted.swap(false, Ordering::SeqCst)
+    }
+}
 
 pub trait EvalContext {
     fn get_function(&self, index: u32) -> Rc<dyn Func>;
     fn get_global(&self, index: u32) -> Rc<dyn Global>;
-    fn get_memory(&self) -> Rc<dyn Memory>;
+    fn get_memory(&self, index: u32) -> Rc<dyn Memory>;
     fn get_table(&self, index: u32) -> Rc<dyn Table>;
     fn get_type(&self, index: u32) -> Arc<FuncType>;
+    /// Returns the passive data segment content, or an empty slice
+    /// if the segment was dropped. Without segments, `memory.init` traps
+    /// unless it copies nothing.
+    fn get_data(&self, _index: u32) -> &[u8] {
+        &[]
+    }
+    fn drop_data(&self, _index: u32) {}
+    /// Returns the passive element segment items, or an empty slice
+    /// if the segment was dropped. Without segments, `table.init` traps
+    /// unless it copies nothing.
+    fn get_element(&self, _index: u32) -> Rc<[Val]> {
+        Rc::new([])
+    }
+    fn drop_element(&self, _index: u32) {}
+    fn stack_limits(&self) -> StackLimits {
+        Default::default()
+    }
+    fn eval_mode(&self) -> EvalMode {
+        Default::default()
+    }
+    fn fuel_config(&self) -> Option<FuelConfig> {
+        None
+    }
+    fn profile(&self) -> Option<&SequenceProfile> {
+        None
+    }
+    /// Returns false when there is not enough fuel left.
+    fn consume_fuel(&self, _amount: u64) -> bool {
+        true
+    }
+    /// Returns true, once, after the interrupt was requested.
+    fn take_interrupt(&self) -> bool {
+        false
+    }
 }
 
 impl<'a> EvalContext for Rc<InstanceData> {
@@ -19,9 +140,8 @@ impl<'a> EvalContext for Rc<InstanceData> {
     fn get_global(&self, index: u32) -> Rc<dyn Global> {
         self.globals[index as usize].clone()
     }
-    fn get_memory(&self) -> Rc<dyn Memory> {
-        const INDEX: usize = 0;
-        self.memories[INDEX].clone()
+    fn get_memory(&self, index: u32) -> Rc<dyn Memory> {
+        self.memories[index as usize].clone()
     }
     fn get_table(&self, index: u32) -> Rc<dyn Table> {
         self.tables[index as usize].clone()
@@ -29,4 +149,43 @@ impl<'a> EvalContext for Rc<InstanceData> {
     fn get_type(&self, index: u32) -> Arc<FuncType> {
         self.module_data.types[index as usize].clone()
     }
+    fn get_data(&self, index: u32) -> &[u8] {
+        if self.dropped_data[index as usize].get() {
+            return &[];
+        }
+        self.module_data.data[index as usize].data
+    }
+    fn drop_data(&self, index: u32) {
+        self.dropped_data[index as usize].set(true);
+    }
+    fn get_element(&self, index: u32) -> ElementItems {
+        self.elements[index as usize].borrow().clone()
+    }
+    fn drop_element(&self, index: u32) {
+        *self.elements[index as usize].borrow_mut() = Rc::new([]);
+    }
+    fn stack_limits(&self) -> StackLimits {
+        self.stack_limits.get()
+    }
+    fn eval_mode(&self) -> EvalMode {
+        self.eval_mode.get()
+    }
+    fn fuel_config(&self) -> Option<FuelConfig> {
+        self.fuel_config.get()
+    }
+    fn profile(&self) -> Option<&SequenceProfile> {
+        Some(&self.profile)
+    }
+    fn consume_fuel(&self, amount: u64) -> bool {
+        let fuel = self.fuel.get();
+        if fuel < amount {
+            self.fuel.set(0);
+            return false;
+        }
+        self.fuel.set(fuel - amount);
+        true
+    }
+    fn take_interrupt(&self) -> bool {
+        self.interrupt_handle.take()
+    }
 }
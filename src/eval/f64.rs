@@ -44,6 +44,9 @@ pub fn neg(a: u64) -> u64 {
 
 #[inline]
 pub fn ceil(a: u64) -> u64 {
+    if is_nan(a) {
+        return a | NAN_DATA_CANONICAL;
+    }
     unsafe {
         let a: f64 = transmute(a);
         transmute(a.ceil())
@@ -52,6 +55,9 @@ pub fn ceil(a: u64) -> u64 {
 
 #[inline]
 pub fn floor(a: u64) -> u64 {
+    if is_nan(a) {
+        return a | NAN_DATA_CANONICAL;
+    }
     unsafe {
         let a: f64 = transmute(a);
         transmute(a.floor())
@@ -60,6 +66,9 @@ pub fn floor(a: u64) -> u64 {
 
 #[inline]
 pub fn trunc(a: u64) -> u64 {
+    if is_nan(a) {
+        return a | NAN_DATA_CANONICAL;
+    }
     unsafe {
         let a: f64 = transmute(a);
         transmute(a.trunc())
@@ -68,6 +77,9 @@ pub fn trunc(a: u64) -> u64 {
 
 #[inline]
 pub fn nearby(a: u64) -> u64 {
+    if is_nan(a) {
+        return a | NAN_DATA_CANONICAL;
+    }
     unsafe {
         let a: f64 = transmute(a);
         transmute(if a.fract().abs() != 0.5 {
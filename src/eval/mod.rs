@@ -1,33 +1,147 @@
-use crate::values::{Trap, TrapKind, Val};
+use crate::externals::{copy_between_memories, Func, Memory};
+use crate::instance::InstanceData;
+use crate::memory::{WaitResult, PAGE_SIZE};
+use crate::values::{collect_roots, RootScope, Slot, Trap, TrapKind, Val};
+use std::cell::Cell;
 use std::rc::Rc;
+use std::sync::atomic::{self, Ordering};
+use std::time::Duration;
 
 use self::f32 as wasm_f32;
 use self::f64 as wasm_f64;
 
-pub(crate) use bytecode::{BreakDestination, BytecodeCache, EvalSource, Operator};
+pub(crate) use bytecode::{Branch, BytecodeCache, EvalSource, Instr, Operator};
 
-pub use context::EvalContext;
+pub(crate) use context::DEFAULT_VALUE_STACK_SIZE;
+pub use context::{
+    EvalContext, EvalMode, FuelConfig, FuelGranularity, InterruptHandle, StackLimits,
+};
+pub use profile::{HotSequence, SequenceProfile};
+
+use profile::Trace;
+use register::RegisterCode;
 
 mod bytecode;
 mod context;
 mod f32;
 mod f64;
+mod profile;
+mod register;
+mod v128;
+
+/// The evaluations the calls nest on the native stack of a thread. The
+/// deeper calls are evaluated in a loop, with their frames on the heap.
+/// An evaluation takes about 2KB of native stack, but over 100KB in the
+/// debug builds.
+const NATIVE_CALLS_DEPTH: usize = if cfg!(debug_assertions) { 2 } else { 64 };
+/// The evaluations the host functions calling back into the instance
+/// functions may nest beyond the calls. The deeper re-entries trap.
+const MAX_HOST_REENTRIES: usize = if cfg!(debug_assertions) { 8 } else { 64 };
+
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+    static NATIVE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+struct CallDepthGuard;
+
+impl CallDepthGuard {
+    fn enter(max_call_depth: usize) -> Result<CallDepthGuard, Trap> {
+        CALL_DEPTH.with(|depth| {
+            if depth.get() >= max_call_depth {
+                return Err(Trap::new(TrapKind::StackOverflow, 0));
+            }
+            depth.set(depth.get() + 1);
+            Ok(CallDepthGuard)
+        })
+    }
+}
+
+impl Drop for CallDepthGuard {
+    fn drop(&mut self) {
+        CALL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
 
-#[allow(dead_code)]
-const STACK_LIMIT: u32 = 10;
-
-fn get_br_table_entry(table: &wasmparser::BrTable, i: u32) -> u32 {
-    let i = table.len().min(i as usize);
-    let it = table.targets();
-    it.skip(i)
-        .next()
-        .expect("br_table entry")
-        .expect("valid br_table entry")
-        .0
+/// Returns the number of evaluations nested on the native stack.
+fn native_depth() -> usize {
+    NATIVE_DEPTH.with(Cell::get)
+}
+
+/// Counts an evaluation nested on the native stack.
+struct NativeDepthGuard;
+
+impl NativeDepthGuard {
+    fn enter() -> Result<NativeDepthGuard, Trap> {
+        NATIVE_DEPTH.with(|depth| {
+            if depth.get() >= NATIVE_CALLS_DEPTH + MAX_HOST_REENTRIES {
+                return Err(Trap::new(TrapKind::StackOverflow, 0));
+            }
+            depth.set(depth.get() + 1);
+            Ok(NativeDepthGuard)
+        })
+    }
+}
+
+impl Drop for NativeDepthGuard {
+    fn drop(&mut self) {
+        NATIVE_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Calls `f` on the native stack, with the arguments at the start of the
+/// `frame` where the results are left.
+#[inline]
+fn call_native(f: &Rc<dyn Func>, frame: &mut [Slot]) -> Result<(), Trap> {
+    let mut next = f.call_slots(frame)?;
+    while let Some(f) = next {
+        next = f.call_slots(frame)?;
+    }
+    Ok(())
+}
+
+/// Returns the function `call_indirect` calls, the one of the table entry
+/// at `func_index` if it has the `type_index` type.
+#[inline]
+fn indirect_func(
+    context: &dyn EvalContext,
+    type_index: u32,
+    table_index: u32,
+    func_index: u32,
+) -> Result<Rc<dyn Func>, TrapKind> {
+    let table = context.get_table(table_index);
+    let ty = context.get_type(type_index);
+    let f = match table.get_func_with_type(func_index, type_index) {
+        Ok(Some(f)) => f,
+        Ok(None) => return Err(TrapKind::Uninitialized),
+        Err(_) => return Err(TrapKind::UndefinedElement),
+    };
+    if f.ty().as_ref() != ty.as_ref() {
+        return Err(TrapKind::SignatureMismatch);
+    }
+    Ok(f)
+}
+
+fn is_basic_block_boundary(instr: &Instr) -> bool {
+    matches!(
+        instr,
+        Instr::Loop
+            | Instr::If { .. }
+            | Instr::Else { .. }
+            | Instr::End
+            | Instr::Br { .. }
+            | Instr::BrIf { .. }
+            | Instr::BrTable { .. }
+            | Instr::Return
+            | Instr::Call { .. }
+            | Instr::CallIndirect { .. }
+            | Instr::ReturnCall { .. }
+            | Instr::ReturnCallIndirect { .. }
+    )
 }
 
 struct EvalStack<'a> {
-    stack: &'a mut [Val],
+    stack: &'a mut [Slot],
     sp: usize,
 }
 impl<'a> EvalStack<'a> {
@@ -35,69 +149,331 @@ impl<'a> EvalStack<'a> {
         if len == 0 {
             return;
         }
-        for i in (start + len)..self.sp {
-            let v = ::std::mem::replace(&mut self.stack[i], Default::default());
-            self.stack[i - len] = v;
-        }
+        self.stack.copy_within(start + len..self.sp, start);
         self.sp -= len;
     }
-    fn push(&mut self, v: Val) {
+    fn push(&mut self, v: Slot) {
         self.stack[self.sp] = v;
         self.sp += 1;
     }
-    fn pop(&mut self) -> Val {
+    fn is_full(&self) -> bool {
+        self.sp >= self.stack.len()
+    }
+    fn has_room(&self, count: usize) -> bool {
+        self.stack.len() - self.sp >= count
+    }
+    fn pop(&mut self) -> Slot {
         self.sp -= 1;
-        let v = ::std::mem::replace(&mut self.stack[self.sp], Default::default());
-        v
+        self.stack[self.sp]
     }
-    fn last(&self) -> Val {
-        self.stack[self.sp - 1].clone()
+    fn last(&self) -> Slot {
+        self.stack[self.sp - 1]
     }
-    fn last_mut(&mut self) -> &mut Val {
+    fn last_mut(&mut self) -> &mut Slot {
         &mut self.stack[self.sp - 1]
     }
     fn len(&self) -> usize {
         self.sp
     }
-    fn local(&self, index: u32) -> Val {
-        self.stack[index as usize].clone()
+    fn local(&self, index: u32) -> Slot {
+        self.stack[index as usize]
     }
-    fn local_mut(&mut self, index: u32) -> &mut Val {
+    fn local_mut(&mut self, index: u32) -> &mut Slot {
         &mut self.stack[index as usize]
     }
 }
 
-#[allow(unused_variables)]
+type MemoryCache = Option<(u32, Rc<dyn Memory>)>;
+
+/// An instance function the interpreter enters without a native call: its
+/// frame is initialized on the stack, with `sp` slots of locals.
+pub struct Activation {
+    pub(crate) context: Rc<InstanceData>,
+    pub(crate) source: Rc<dyn EvalSource>,
+    pub(crate) return_arity: usize,
+    pub(crate) sp: usize,
+}
+
+/// Why the evaluation of the instructions stopped before their end.
+enum Exit {
+    End,
+    /// The callee and the instruction to continue with once it returns.
+    /// Its arguments are on the top of the stack.
+    Call(Rc<dyn Func>, usize),
+    /// The callee arguments are moved to the bottom of the stack.
+    TailCall(Rc<dyn Func>),
+}
+
+enum FrameCode<'a> {
+    Borrowed(&'a dyn EvalContext, &'a dyn EvalSource),
+    Activated(Activation),
+}
+
+/// A function body being evaluated. The interpreter keeps the frames of
+/// the calls here instead of on the native stack.
+struct Frame<'a> {
+    code: FrameCode<'a>,
+    return_arity: usize,
+    /// The start of the frame in the stack.
+    base: usize,
+    state: FrameState,
+    roots: RootScope,
+    _depth_guard: CallDepthGuard,
+}
+
+/// Where the evaluation of the body continues, after a call.
+struct FrameState {
+    register_code: Option<Rc<RegisterCode>>,
+    /// The next register instruction, when the register code is evaluated.
+    register_pc: usize,
+    /// The next instruction, `0` when the register instruction does not
+    /// continue a call.
+    pc: usize,
+    sp: usize,
+    memory_cache: MemoryCache,
+    trace: Trace,
+}
+
+impl<'a> Frame<'a> {
+    /// Creates the frame in the `roots` scope, the one of the tail caller,
+    /// or a new one.
+    fn new(
+        code: FrameCode<'a>,
+        return_arity: usize,
+        base: usize,
+        sp: usize,
+        roots: Option<RootScope>,
+    ) -> Result<Self, Trap> {
+        let (context, bytecode) = (code.context(), code.source().bytecode());
+        let depth_guard = CallDepthGuard::enter(context.stack_limits().max_call_depth)?;
+        let state = FrameState::new(context, bytecode, return_arity, sp);
+        Ok(Frame {
+            code,
+            return_arity,
+            base,
+            state,
+            roots: roots.unwrap_or_else(RootScope::enter),
+            _depth_guard: depth_guard,
+        })
+    }
+}
+
+impl FrameState {
+    fn new(
+        context: &dyn EvalContext,
+        bytecode: &BytecodeCache,
+        return_arity: usize,
+        sp: usize,
+    ) -> FrameState {
+        // The register code does not count the fuel.
+        let register_code =
+            if context.eval_mode() == EvalMode::Register && context.fuel_config().is_none() {
+                Some(bytecode.register_code(sp, return_arity))
+            } else {
+                None
+            };
+        FrameState {
+            register_code,
+            register_pc: 0,
+            pc: 0,
+            sp,
+            memory_cache: None,
+            trace: Trace::default(),
+        }
+    }
+}
+
+impl<'a> FrameCode<'a> {
+    fn context(&self) -> &dyn EvalContext {
+        match self {
+            FrameCode::Borrowed(context, _) => *context,
+            FrameCode::Activated(activation) => &activation.context,
+        }
+    }
+
+    fn source(&self) -> &dyn EvalSource {
+        match self {
+            FrameCode::Borrowed(_, source) => *source,
+            FrameCode::Activated(activation) => &*activation.source,
+        }
+    }
+}
+
+/// Evaluates the function body, the results are moved to the bottom of
+/// the stack. The calls nest on the native stack up to `NATIVE_CALLS_DEPTH`
+/// evaluations: the deeper calls of the instance functions, and the tail
+/// calls, are evaluated in the loop here.
 pub(crate) fn eval<'a>(
     context: &'a (dyn EvalContext + 'a),
-    source: &dyn EvalSource,
+    source: &'a dyn EvalSource,
     return_arity: usize,
-    stack_: &mut [Val],
-    sp_: usize,
+    stack: &mut [Slot],
+    sp: usize,
 ) -> Result<(), Trap> {
+    let _native_depth = NativeDepthGuard::enter()?;
+    let depth_guard = CallDepthGuard::enter(context.stack_limits().max_call_depth)?;
+    let roots = RootScope::enter();
+    let bytecode = source.bytecode();
+    let mut state = FrameState::new(context, bytecode, return_arity, sp);
+    match resume(context, bytecode, return_arity, stack, &mut state)? {
+        Exit::End => {
+            roots.leave(&stack[..return_arity]);
+            Ok(())
+        }
+        exit => {
+            let root = Frame {
+                code: FrameCode::Borrowed(context, source),
+                return_arity,
+                base: 0,
+                state,
+                roots,
+                _depth_guard: depth_guard,
+            };
+            eval_frames(root, exit, stack)
+        }
+    }
+}
+
+/// Continues the evaluation of the `root` frame after its `exit`, with the
+/// frames of the calls on the heap.
+#[inline(never)]
+fn eval_frames(root: Frame, exit: Exit, stack: &mut [Slot]) -> Result<(), Trap> {
+    let mut frames = vec![root];
+    let mut exit = Some(exit);
+    while let Some(frame) = frames.last_mut() {
+        let Frame {
+            ref code,
+            return_arity,
+            base,
+            ref mut state,
+            ..
+        } = *frame;
+        let exit = match exit.take() {
+            Some(exit) => exit,
+            None => {
+                let (context, bytecode) = (code.context(), code.source().bytecode());
+                resume(context, bytecode, return_arity, &mut stack[base..], state)?
+            }
+        };
+        let (f, base, mut tail_roots) = match exit {
+            Exit::End => {
+                let roots = frames.pop().unwrap().roots;
+                roots.leave(&stack[base..base + return_arity]);
+                continue;
+            }
+            Exit::Call(f, pc) => {
+                let ty = f.ty();
+                let (params_len, returns_len) = (ty.params.len(), ty.returns.len());
+                state.pc = pc;
+                state.sp = state.sp + returns_len - params_len;
+                (f, base + state.sp - returns_len, None)
+            }
+            Exit::TailCall(f) => {
+                // The callee continues in the scope of the caller, with the
+                // roots of its arguments.
+                let roots = frames.pop().unwrap().roots;
+                roots.retain(&stack[base..base + f.ty().params.len()]);
+                (f, base, Some(roots))
+            }
+        };
+        let mut next = Some(f);
+        while let Some(f) = next {
+            let frame = &mut stack[base..];
+            if let Some(activation) = f.activate(frame)? {
+                let (return_arity, sp) = (activation.return_arity, activation.sp);
+                let code = FrameCode::Activated(activation);
+                frames.push(Frame::new(code, return_arity, base, sp, tail_roots.take())?);
+                break;
+            }
+            next = f.call_slots(frame)?;
+        }
+        if let Some(roots) = tail_roots {
+            roots.leave(&stack[base..base + return_arity]);
+        }
+    }
+    Ok(())
+}
+
+/// Continues the evaluation of the body in its `frame`, until the next
+/// call or the end, where the results are moved to the bottom of the frame.
+fn resume(
+    context: &dyn EvalContext,
+    bytecode: &BytecodeCache,
+    return_arity: usize,
+    frame: &mut [Slot],
+    state: &mut FrameState,
+) -> Result<Exit, Trap> {
+    if let Some(register_code) = state.register_code.clone() {
+        return register::eval(
+            context,
+            bytecode,
+            &register_code,
+            return_arity,
+            frame,
+            state,
+        );
+    }
     let mut stack = EvalStack {
-        stack: stack_,
-        sp: sp_,
+        stack: frame,
+        sp: state.sp,
     };
+    let end = bytecode.instrs().len();
+    let exit = run(
+        context,
+        bytecode,
+        &mut stack,
+        &mut state.memory_cache,
+        &mut state.trace,
+        state.pc,
+        end,
+    )?;
+    if let Exit::End = exit {
+        stack.compress_stack_items(0, stack.len() - return_arity);
+    }
+    state.sp = stack.sp;
+    Ok(exit)
+}
 
-    let bytecode = source.bytecode();
-    let operators = bytecode.operators();
-    let mut i = 0;
-    let mut block_returns = Vec::with_capacity(bytecode.max_control_depth() + 1);
-    let mut memory_cache: Option<Rc<_>> = None;
-    block_returns.push(0);
-    macro_rules! val_ty {
+/// Runs the instructions from `start` until `end`, the `return`, a call
+/// or a trap.
+#[allow(unused_variables)]
+fn run(
+    context: &dyn EvalContext,
+    bytecode: &BytecodeCache,
+    stack: &mut EvalStack,
+    memory_cache: &mut MemoryCache,
+    trace: &mut Trace,
+    start: usize,
+    end: usize,
+) -> Result<Exit, Trap> {
+    let instrs = bytecode.instrs();
+    let mut i = start;
+    let fuel = context
+        .fuel_config()
+        .map(|config| (config, bytecode.fuel_costs(config.cost)));
+    let mut fuel_pending = 0u64;
+    let profile = match context.eval_mode() {
+        EvalMode::Profile => context.profile(),
+        _ => None,
+    };
+    // The fuel and the profile are counted for the instructions the
+    // superinstructions replaced, which are executed instead.
+    let unfused = fuel.is_some() || profile.is_some();
+    macro_rules! slot_from {
         (i32) => {
-            Val::I32
+            Slot::from_i32
         };
         (i64) => {
-            Val::I64
+            Slot::from_i64
         };
         (f32) => {
-            Val::F32
+            Slot::from_f32
         };
         (f64) => {
-            Val::F64
+            Slot::from_f64
+        };
+        (v128) => {
+            Slot::from_v128
         };
     }
     macro_rules! val_size {
@@ -113,6 +489,9 @@ pub(crate) fn eval<'a>(
         (f64) => {
             8
         };
+        (v128) => {
+            16
+        };
     }
     macro_rules! rust_ty {
         (i32) => {
@@ -127,20 +506,32 @@ pub(crate) fn eval<'a>(
         (f64) => {
             u64
         };
+        (v128) => {
+            u128
+        };
     }
     macro_rules! push {
-        ($e:expr; $ty:ident) => {
-            stack.push(val_ty!($ty)($e));
-        };
+        ($e:expr; $ty:ident) => {{
+            let val = slot_from!($ty)($e);
+            push_val!(val)
+        }};
+    }
+    macro_rules! push_val {
+        ($val:expr) => {{
+            if stack.is_full() {
+                trap!(TrapKind::StackOverflow);
+            }
+            stack.push($val)
+        }};
     }
     macro_rules! pop {
         ($ty:ident) => {
-            stack.pop().$ty().unwrap()
+            stack.pop().$ty()
         };
     }
     macro_rules! trap {
         ($kind:expr) => {
-            return Err(Trap::new($kind, bytecode.position(i)));
+            return Err(Trap::new($kind, bytecode.position(i)))
         };
     }
     macro_rules! step {
@@ -154,172 +545,307 @@ pub(crate) fn eval<'a>(
             push!($e; $ty);
         }};
     }
+    macro_rules! pop_address {
+        () => {
+            // Validated to be `i64` for memory64 memories, and `i32` otherwise.
+            stack.pop().address()
+        };
+    }
     macro_rules! load {
         ($memarg:expr; $ty:ident) => {{
-            let offset = pop!(i32) as u32;
-            let ptr = context
-                .get_memory()
-                .content_ptr($memarg, offset, val_size!($ty));
+            let offset = pop_address!();
+            load!($memarg, offset; $ty)
+        }};
+        ($memarg:expr, $offset:expr; $ty:ident) => {{
+            let offset = $offset;
+            let ptr = memory!($memarg.memory).content_ptr($memarg, offset, val_size!($ty));
             if ptr.is_null() {
                 trap!(TrapKind::OutOfBounds);
             }
-            let val = unsafe { *(ptr as *const rust_ty!($ty)) };
+            let val = unsafe { (ptr as *const rust_ty!($ty)).read_unaligned() };
             push!(val; $ty);
         }};
         ($memarg:expr; $ty:ident as $tt:ident) => {{
-            let offset = pop!(i32) as u32;
-            let ptr = context
-                .get_memory()
-                .content_ptr($memarg, offset, std::mem::size_of::<$tt>() as u32);
+            let offset = pop_address!();
+            let ptr = memory!($memarg.memory).content_ptr(
+                $memarg,
+                offset,
+                std::mem::size_of::<$tt>() as u32,
+            );
             if ptr.is_null() {
                 trap!(TrapKind::OutOfBounds);
             }
-            let val = unsafe { *(ptr as *const $tt) } as rust_ty!($ty);
+            let val = unsafe { (ptr as *const $tt).read_unaligned() } as rust_ty!($ty);
             push!(val; $ty);
         }};
     }
+    macro_rules! load_v128 {
+        ($memarg:expr; $size:expr) => {{
+            let offset = pop_address!();
+            let ptr = memory!($memarg.memory).content_ptr($memarg, offset, $size);
+            if ptr.is_null() {
+                trap!(TrapKind::OutOfBounds);
+            }
+            let mut bytes = [0u8; 16];
+            unsafe {
+                std::ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), $size);
+            }
+            u128::from_le_bytes(bytes)
+        }};
+    }
     macro_rules! memory {
-        () => {
-            memory_cache.get_or_insert_with(|| context.get_memory().clone())
-        };
+        ($index:expr) => {{
+            let index = $index;
+            if memory_cache
+                .as_ref()
+                .map_or(true, |(cached, _)| *cached != index)
+            {
+                *memory_cache = Some((index, context.get_memory(index)));
+            }
+            &memory_cache.as_ref().unwrap().1
+        }};
     }
     macro_rules! store {
         ($memarg:expr; $ty:ident) => {{
             let val = pop!($ty);
-            let offset = pop!(i32) as u32;
-            let ptr = memory!().content_ptr_mut($memarg, offset, val_size!($ty));
+            let offset = pop_address!();
+            let ptr = memory!($memarg.memory).content_ptr_mut($memarg, offset, val_size!($ty));
             if ptr.is_null() {
                 trap!(TrapKind::OutOfBounds);
             }
             unsafe {
-                *(ptr as *mut rust_ty!($ty)) = val;
+                (ptr as *mut rust_ty!($ty)).write_unaligned(val);
             }
         }};
         ($memarg:expr; $ty:ident as $tt:ident) => {{
             let val = pop!($ty) as $tt;
-            let offset = pop!(i32) as u32;
-            let ptr = memory!().content_ptr_mut($memarg, offset, std::mem::size_of::<$tt>() as u32);
+            let offset = pop_address!();
+            let ptr = memory!($memarg.memory).content_ptr_mut(
+                $memarg,
+                offset,
+                std::mem::size_of::<$tt>() as u32,
+            );
             if ptr.is_null() {
                 trap!(TrapKind::OutOfBounds);
             }
             unsafe {
-                *(ptr as *mut $tt) = val;
+                (ptr as *mut $tt).write_unaligned(val);
             }
         }};
     }
 
-    macro_rules! break_to {
-        ($depth:expr) => {{
-            let target_depth = block_returns.len() - $depth as usize - 1;
-            match bytecode.break_to(i, $depth) {
-                BreakDestination::BlockEnd(end, tail_len) => {
-                    i = end;
-                    let leave = block_returns[target_depth];
-                    block_returns.truncate(target_depth);
-                    stack.compress_stack_items(leave, stack.len() - tail_len - leave);
-                }
-                BreakDestination::LoopStart(start, tail_len) => {
-                    i = start;
-                    let leave = block_returns[target_depth];
-                    block_returns.truncate(target_depth + 1);
-                    stack.compress_stack_items(leave, stack.len() - tail_len - leave);
+    macro_rules! atomic_ty {
+        (u8) => {
+            atomic::AtomicU8
+        };
+        (u16) => {
+            atomic::AtomicU16
+        };
+        (u32) => {
+            atomic::AtomicU32
+        };
+        (u64) => {
+            atomic::AtomicU64
+        };
+    }
+    macro_rules! atomic {
+        ($memarg:expr, $offset:expr; $tt:ident) => {{
+            let size = std::mem::size_of::<$tt>() as u32;
+            if $offset.wrapping_add($memarg.offset as u64) % size as u64 != 0 {
+                trap!(TrapKind::UnalignedAtomic);
+            }
+            let ptr = memory!($memarg.memory).content_ptr_mut($memarg, $offset, size);
+            if ptr.is_null() {
+                trap!(TrapKind::OutOfBounds);
+            }
+            // The memory buffers are allocated aligned to 8 bytes at least,
+            // which makes an aligned address an aligned pointer. The pointers
+            // of the host memories may not be aligned.
+            if ptr as usize % size as usize != 0 {
+                trap!(TrapKind::UnalignedAtomic);
+            }
+            unsafe { &*(ptr as *const atomic_ty!($tt)) }
+        }};
+    }
+    macro_rules! atomic_load {
+        ($memarg:expr; $ty:ident as $tt:ident) => {{
+            let offset = pop_address!();
+            let val = atomic!($memarg, offset; $tt).load(Ordering::SeqCst);
+            push!(val as rust_ty!($ty); $ty)
+        }};
+    }
+    macro_rules! atomic_store {
+        ($memarg:expr; $ty:ident as $tt:ident) => {{
+            let val = pop!($ty) as $tt;
+            let offset = pop_address!();
+            atomic!($memarg, offset; $tt).store(val, Ordering::SeqCst);
+        }};
+    }
+    macro_rules! atomic_rmw {
+        ($memarg:expr; $ty:ident as $tt:ident, $op:ident) => {{
+            let val = pop!($ty) as $tt;
+            let offset = pop_address!();
+            let old = atomic!($memarg, offset; $tt).$op(val, Ordering::SeqCst);
+            push!(old as rust_ty!($ty); $ty)
+        }};
+    }
+    macro_rules! atomic_cmpxchg {
+        ($memarg:expr; $ty:ident as $tt:ident) => {{
+            let replacement = pop!($ty) as $tt;
+            let expected = pop!($ty) as $tt;
+            let offset = pop_address!();
+            let cell = atomic!($memarg, offset; $tt);
+            let old = match cell.compare_exchange(
+                expected,
+                replacement,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(old) | Err(old) => old,
+            };
+            push!(old as rust_ty!($ty); $ty)
+        }};
+    }
+
+    macro_rules! branch {
+        ($branch:expr) => {{
+            let Branch { pc, drop, keep } = *$branch;
+            // Only loops are entered from below.
+            if pc as usize <= i {
+                if context.take_interrupt() {
+                    trap!(TrapKind::Interrupted);
                 }
+                collect_roots(&stack.stack[..stack.sp]);
             }
+            stack.compress_stack_items(stack.len() - (keep + drop) as usize, drop as usize);
+            i = pc as usize;
             continue;
         }};
     }
     macro_rules! call {
         ($f:expr) => {{
+            if context.take_interrupt() {
+                trap!(TrapKind::Interrupted);
+            }
             // TODO better signature check
             let params_len = $f.ty().params.len();
             let returns_len = $f.ty().returns.len();
-            let result = $f.call(&mut stack.stack[stack.sp - params_len..]);
-            match result {
-                Ok(()) => {
-                    stack.sp = stack.sp + returns_len - params_len;
-                }
-                Err(trap) => {
-                    return Err(trap);
-                }
+            if stack.sp - params_len + returns_len > stack.stack.len() {
+                trap!(TrapKind::StackOverflow);
+            }
+            if native_depth() >= NATIVE_CALLS_DEPTH {
+                return Ok(Exit::Call($f, i + 1));
             }
+            call_native(&$f, &mut stack.stack[stack.sp - params_len..])?;
+            stack.sp = stack.sp + returns_len - params_len;
         }};
     }
-    macro_rules! op_notimpl {
-        () => {
-            trap!(TrapKind::User(format!(
-                "operator not implemented {:?}",
-                operators[i]
-            )));
-        };
+    macro_rules! indirect_func {
+        ($index:expr, $table_index:expr) => {{
+            let func_index = pop!(i32) as u32;
+            match indirect_func(context, $index, $table_index, func_index) {
+                Ok(f) => f,
+                Err(kind) => trap!(kind),
+            }
+        }};
+    }
+    macro_rules! compare_br_if {
+        ($branch:expr; |$a:ident, $b:ident| $e:expr) => {{
+            let $b = pop!(i32);
+            let $a = pop!(i32);
+            i += 1;
+            if $e {
+                branch!($branch);
+            }
+        }};
+    }
+    macro_rules! return_call {
+        ($f:expr) => {{
+            if context.take_interrupt() {
+                trap!(TrapKind::Interrupted);
+            }
+            let params_len = $f.ty().params.len();
+            stack.compress_stack_items(0, stack.len() - params_len);
+            return Ok(Exit::TailCall($f));
+        }};
     }
 
     // TODO validate stack state
     // TODO handle traps
 
-    while i < operators.len() {
-        match &operators[i] {
-            Operator::Unreachable => {
-                trap!(TrapKind::Unreachable);
+    while i < end {
+        let unfused_instr;
+        let instr = if unfused {
+            unfused_instr = instrs[i].unfused();
+            &unfused_instr
+        } else {
+            &instrs[i]
+        };
+        if let Some((ref fuel_config, ref fuel_costs)) = fuel {
+            fuel_pending += fuel_costs[i];
+            if fuel_config.granularity == FuelGranularity::PerInstruction
+                || is_basic_block_boundary(instr)
+            {
+                if !context.consume_fuel(fuel_pending) {
+                    trap!(TrapKind::OutOfFuel);
+                }
+                fuel_pending = 0;
             }
-            Operator::Nop => (),
-            Operator::Block { ty } | Operator::Loop { ty } => {
-                block_returns.push(stack.len() - bytecode.block_params_count(i));
+        }
+        if let Some(profile) = profile {
+            profile.record(trace, i, instr);
+        }
+        match instr {
+            Instr::Unreachable => {
+                trap!(TrapKind::Unreachable);
             }
-            Operator::If { ty } => {
+            Instr::Nop => (),
+            Instr::Block | Instr::Loop | Instr::End => (),
+            Instr::If { else_pc } => {
                 let c = pop!(i32);
-                block_returns.push(stack.len() - bytecode.block_params_count(i));
                 if c == 0 {
-                    i = bytecode.skip_to_else(i);
+                    i = *else_pc as usize;
                     continue;
                 }
             }
-            Operator::Else => {
-                i = bytecode.skip_to_end(i);
-                block_returns.pop().unwrap();
+            Instr::Else { end_pc } => {
+                i = *end_pc as usize;
                 continue;
             }
-            Operator::End => {
-                if i + 1 >= bytecode.len() {
-                    break;
-                }
-                block_returns.pop().unwrap();
-            }
-            Operator::Br { relative_depth } => break_to!(*relative_depth),
-            Operator::BrIf { relative_depth } => {
+            Instr::Br { branch } => branch!(branch),
+            Instr::BrIf { branch } => {
                 let c = pop!(i32);
                 if c != 0 {
-                    break_to!(*relative_depth);
+                    branch!(branch);
                 }
             }
-            Operator::BrTable { table } => {
-                let i = pop!(i32);
-                break_to!(get_br_table_entry(table, i as u32));
+            Instr::BrTable { targets, len } => {
+                let index = (pop!(i32) as u32).min(*len);
+                branch!(bytecode.br_table_entry(targets + index));
             }
-            Operator::Return => {
+            Instr::Return => {
                 break;
             }
-            Operator::Call { function_index } => {
+            Instr::Call { function_index } => {
                 let f = context.get_function(*function_index);
                 call!(f)
             }
-            Operator::CallIndirect { index, table_index } => {
-                let func_index = pop!(i32) as u32;
-                let table = context.get_table(*table_index);
-                let ty = context.get_type(*index);
-                let f = match table.get_func_with_type(func_index, *index) {
-                    Ok(Some(f)) => f,
-                    Ok(None) => trap!(TrapKind::Uninitialized),
-                    Err(_) => trap!(TrapKind::UndefinedElement),
-                };
-                if f.ty().as_ref() != ty.as_ref() {
-                    trap!(TrapKind::SignatureMismatch);
-                }
+            Instr::CallIndirect { index, table_index } => {
+                let f = indirect_func!(*index, *table_index);
                 call!(f)
             }
-            Operator::Drop => {
+            Instr::ReturnCall { function_index } => {
+                let f = context.get_function(*function_index);
+                return_call!(f)
+            }
+            Instr::ReturnCallIndirect { index, table_index } => {
+                let f = indirect_func!(*index, *table_index);
+                return_call!(f)
+            }
+            Instr::Drop => {
                 stack.pop();
             }
-            Operator::Select => {
+            Instr::Select => {
                 let c = pop!(i32);
                 if c != 0 {
                     stack.pop();
@@ -328,161 +854,212 @@ pub(crate) fn eval<'a>(
                     *stack.last_mut() = v;
                 }
             }
-            Operator::TypedSelect { .. } => op_notimpl!(),
-            Operator::LocalGet { local_index } => stack.push(stack.local(*local_index)),
-            Operator::LocalSet { local_index } => {
+            Instr::LocalGet { local_index } => {
+                let val = stack.local(*local_index);
+                push_val!(val)
+            }
+            Instr::LocalSet { local_index } => {
                 *stack.local_mut(*local_index) = stack.pop();
             }
-            Operator::LocalTee { local_index } => {
+            Instr::LocalTee { local_index } => {
                 *stack.local_mut(*local_index) = stack.last();
             }
-            Operator::GlobalGet { global_index } => {
+            Instr::GlobalGet { global_index } => {
                 let g = context.get_global(*global_index);
-                stack.push(g.content());
+                push_val!(Slot::from_val(&g.content()));
             }
-            Operator::GlobalSet { global_index } => {
+            Instr::GlobalSet { global_index } => {
                 let g = context.get_global(*global_index);
-                g.set_content(&stack.pop());
+                g.set_content(&stack.pop().to_val(g.ty().ty));
             }
-            Operator::I32Load { memarg } => {
+            Instr::I32Load { memarg } => {
                 load!(memarg; i32);
             }
-            Operator::I64Load { memarg } => {
+            Instr::I64Load { memarg } => {
                 load!(memarg; i64);
             }
-            Operator::F32Load { memarg } => {
+            Instr::F32Load { memarg } => {
                 load!(memarg; f32);
             }
-            Operator::F64Load { memarg } => {
+            Instr::F64Load { memarg } => {
                 load!(memarg; f64);
             }
-            Operator::I32Load8S { memarg } => {
+            Instr::I32Load8S { memarg } => {
                 load!(memarg; i32 as i8);
             }
-            Operator::I32Load8U { memarg } => {
+            Instr::I32Load8U { memarg } => {
                 load!(memarg; i32 as u8);
             }
-            Operator::I32Load16S { memarg } => {
+            Instr::I32Load16S { memarg } => {
                 load!(memarg; i32 as i16);
             }
-            Operator::I32Load16U { memarg } => {
+            Instr::I32Load16U { memarg } => {
                 load!(memarg; i32 as u16);
             }
-            Operator::I64Load8S { memarg } => {
+            Instr::I64Load8S { memarg } => {
                 load!(memarg; i64 as i8);
             }
-            Operator::I64Load8U { memarg } => {
+            Instr::I64Load8U { memarg } => {
                 load!(memarg; i64 as u8);
             }
-            Operator::I64Load16S { memarg } => {
+            Instr::I64Load16S { memarg } => {
                 load!(memarg; i64 as i16);
             }
-            Operator::I64Load16U { memarg } => {
+            Instr::I64Load16U { memarg } => {
                 load!(memarg; i64 as u16);
             }
-            Operator::I64Load32S { memarg } => {
+            Instr::I64Load32S { memarg } => {
                 load!(memarg; i64 as i32);
             }
-            Operator::I64Load32U { memarg } => {
+            Instr::I64Load32U { memarg } => {
                 load!(memarg; i64 as u32);
             }
-            Operator::I32Store { memarg } => {
+            Instr::I32Store { memarg } => {
                 store!(memarg; i32);
             }
-            Operator::I64Store { memarg } => {
+            Instr::I64Store { memarg } => {
                 store!(memarg; i64);
             }
-            Operator::F32Store { memarg } => {
+            Instr::F32Store { memarg } => {
                 store!(memarg; f32);
             }
-            Operator::F64Store { memarg } => {
+            Instr::F64Store { memarg } => {
                 store!(memarg; f64);
             }
-            Operator::I32Store8 { memarg } => {
+            Instr::I32Store8 { memarg } => {
                 store!(memarg; i32 as u8);
             }
-            Operator::I32Store16 { memarg } => {
+            Instr::I32Store16 { memarg } => {
                 store!(memarg; i32 as u16);
             }
-            Operator::I64Store8 { memarg } => {
+            Instr::I64Store8 { memarg } => {
                 store!(memarg; i64 as u8);
             }
-            Operator::I64Store16 { memarg } => {
+            Instr::I64Store16 { memarg } => {
                 store!(memarg; i64 as u16);
             }
-            Operator::I64Store32 { memarg } => {
+            Instr::I64Store32 { memarg } => {
                 store!(memarg; i64 as u32);
             }
-            Operator::MemorySize { .. } => {
-                let current = context.get_memory().current();
-                push!(current as i32; i32)
+            Instr::MemorySize { mem, .. } => {
+                let memory = memory!(*mem);
+                let current = memory.current();
+                if memory.ty().memory64 {
+                    push!(current as i64; i64)
+                } else {
+                    push!(current as i32; i32)
+                }
             }
-            Operator::MemoryGrow { .. } => {
-                let delta = pop!(i32) as u32;
-                let current = context.get_memory().grow(delta);
-                push!(current as i32; i32)
-            }
-            Operator::I32Const { value } => push!(*value; i32),
-            Operator::I64Const { value } => push!(*value; i64),
-            Operator::F32Const { value } => push!(value.bits(); f32),
-            Operator::F64Const { value } => push!(value.bits(); f64),
-            Operator::I32Eqz => step!(|a:i32| -> i32 if a == 0 { 1 } else { 0 }),
-            Operator::I32Eq => step!(|a:i32, b:i32| -> i32 if a == b { 1 } else { 0 }),
-            Operator::I32Ne => step!(|a:i32, b:i32| -> i32 if a == b { 0 } else { 1 }),
-            Operator::I32LtS => step!(|a:i32, b:i32| -> i32 if a < b { 1 } else { 0 }),
-            Operator::I32LtU => {
+            Instr::MemoryGrow { mem, .. } => {
+                let delta = pop_address!();
+                let memory = memory!(*mem);
+                let current = memory.grow(delta);
+                if memory.ty().memory64 {
+                    push!(current as i64; i64)
+                } else {
+                    push!(current as i32; i32)
+                }
+            }
+            Instr::MemoryInit { segment, mem } => {
+                let len = pop!(i32) as u32;
+                let src = pop!(i32) as u32;
+                let dst = pop_address!();
+                let data = context.get_data(*segment);
+                let memory = memory!(*mem);
+                if src as u64 + len as u64 > data.len() as u64
+                    || dst as u128 + len as u128 > memory.current() as u128 * PAGE_SIZE as u128
+                {
+                    trap!(TrapKind::OutOfBounds);
+                }
+                memory.clone_from_slice(dst, &data[src as usize..(src + len) as usize]);
+            }
+            Instr::DataDrop { segment } => context.drop_data(*segment),
+            Instr::MemoryCopy {
+                src: src_mem,
+                dst: dst_mem,
+            } => {
+                let len = pop_address!();
+                let src = pop_address!();
+                let dst = pop_address!();
+                let result = if src_mem == dst_mem {
+                    memory!(*dst_mem).copy_within(dst, src, len)
+                } else {
+                    let src_memory = memory!(*src_mem).clone();
+                    copy_between_memories(memory!(*dst_mem).as_ref(), dst, &*src_memory, src, len)
+                };
+                if result.is_err() {
+                    trap!(TrapKind::OutOfBounds);
+                }
+            }
+            Instr::MemoryFill { mem } => {
+                let len = pop_address!();
+                let val = pop!(i32) as u8;
+                let dst = pop_address!();
+                if memory!(*mem).fill(dst, val, len).is_err() {
+                    trap!(TrapKind::OutOfBounds);
+                }
+            }
+            Instr::I32Const { value } => push!(*value; i32),
+            Instr::I64Const { value } => push!(*value; i64),
+            Instr::F32Const { value } => push!(value.bits(); f32),
+            Instr::F64Const { value } => push!(value.bits(); f64),
+            Instr::I32Eqz => step!(|a:i32| -> i32 if a == 0 { 1 } else { 0 }),
+            Instr::I32Eq => step!(|a:i32, b:i32| -> i32 if a == b { 1 } else { 0 }),
+            Instr::I32Ne => step!(|a:i32, b:i32| -> i32 if a == b { 0 } else { 1 }),
+            Instr::I32LtS => step!(|a:i32, b:i32| -> i32 if a < b { 1 } else { 0 }),
+            Instr::I32LtU => {
                 step!(|a:i32, b:i32| -> i32 if (a as u32) < b as u32 { 1 } else { 0 })
             }
-            Operator::I32GtS => step!(|a:i32, b:i32| -> i32 if a > b { 1 } else { 0 }),
-            Operator::I32GtU => {
+            Instr::I32GtS => step!(|a:i32, b:i32| -> i32 if a > b { 1 } else { 0 }),
+            Instr::I32GtU => {
                 step!(|a:i32, b:i32| -> i32 if (a as u32) > b as u32 { 1 } else { 0 })
             }
-            Operator::I32LeS => step!(|a:i32, b:i32| -> i32 if a <= b { 1 } else { 0 }),
-            Operator::I32LeU => {
+            Instr::I32LeS => step!(|a:i32, b:i32| -> i32 if a <= b { 1 } else { 0 }),
+            Instr::I32LeU => {
                 step!(|a:i32, b:i32| -> i32 if (a as u32) <= b as u32 { 1 } else { 0 })
             }
-            Operator::I32GeS => step!(|a:i32, b:i32| -> i32 if a >= b { 1 } else { 0 }),
-            Operator::I32GeU => {
+            Instr::I32GeS => step!(|a:i32, b:i32| -> i32 if a >= b { 1 } else { 0 }),
+            Instr::I32GeU => {
                 step!(|a:i32, b:i32| -> i32 if (a as u32) >= b as u32 { 1 } else { 0 })
             }
-            Operator::I64Eqz => step!(|a:i64| -> i32 if a == 0 { 1 } else { 0 }),
-            Operator::I64Eq => step!(|a:i64, b:i64| -> i32 if a == b { 1 } else { 0 }),
-            Operator::I64Ne => step!(|a:i64, b:i64| -> i32 if a == b { 0 } else { 1 }),
-            Operator::I64LtS => step!(|a:i64, b:i64| -> i32 if a < b { 1 } else { 0 }),
-            Operator::I64LtU => {
+            Instr::I64Eqz => step!(|a:i64| -> i32 if a == 0 { 1 } else { 0 }),
+            Instr::I64Eq => step!(|a:i64, b:i64| -> i32 if a == b { 1 } else { 0 }),
+            Instr::I64Ne => step!(|a:i64, b:i64| -> i32 if a == b { 0 } else { 1 }),
+            Instr::I64LtS => step!(|a:i64, b:i64| -> i32 if a < b { 1 } else { 0 }),
+            Instr::I64LtU => {
                 step!(|a:i64, b:i64| -> i32 if (a as u64) < b as u64 { 1 } else { 0 })
             }
-            Operator::I64GtS => step!(|a:i64, b:i64| -> i32 if a > b { 1 } else { 0 }),
-            Operator::I64GtU => {
+            Instr::I64GtS => step!(|a:i64, b:i64| -> i32 if a > b { 1 } else { 0 }),
+            Instr::I64GtU => {
                 step!(|a:i64, b:i64| -> i32 if (a as u64) > b as u64 { 1 } else { 0 })
             }
-            Operator::I64LeS => step!(|a:i64, b:i64| -> i32 if a <= b { 1 } else { 0 }),
-            Operator::I64LeU => {
+            Instr::I64LeS => step!(|a:i64, b:i64| -> i32 if a <= b { 1 } else { 0 }),
+            Instr::I64LeU => {
                 step!(|a:i64, b:i64| -> i32 if (a as u64) <= b as u64 { 1 } else { 0 })
             }
-            Operator::I64GeS => step!(|a:i64, b:i64| -> i32 if a >= b { 1 } else { 0 }),
-            Operator::I64GeU => {
+            Instr::I64GeS => step!(|a:i64, b:i64| -> i32 if a >= b { 1 } else { 0 }),
+            Instr::I64GeU => {
                 step!(|a:i64, b:i64| -> i32 if (a as u64) >= b as u64 { 1 } else { 0 })
             }
-            Operator::F32Eq => step!(|a:f32, b:f32| -> i32 wasm_f32::eq(a, b)),
-            Operator::F32Ne => step!(|a:f32, b:f32| -> i32 wasm_f32::ne(a, b)),
-            Operator::F32Lt => step!(|a:f32, b:f32| -> i32 wasm_f32::lt(a, b)),
-            Operator::F32Gt => step!(|a:f32, b:f32| -> i32 wasm_f32::gt(a, b)),
-            Operator::F32Le => step!(|a:f32, b:f32| -> i32 wasm_f32::le(a, b)),
-            Operator::F32Ge => step!(|a:f32, b:f32| -> i32 wasm_f32::ge(a, b)),
-            Operator::F64Eq => step!(|a:f64, b:f64| -> i32 wasm_f64::eq(a, b)),
-            Operator::F64Ne => step!(|a:f64, b:f64| -> i32 wasm_f64::ne(a, b)),
-            Operator::F64Lt => step!(|a:f64, b:f64| -> i32 wasm_f64::lt(a, b)),
-            Operator::F64Gt => step!(|a:f64, b:f64| -> i32 wasm_f64::gt(a, b)),
-            Operator::F64Le => step!(|a:f64, b:f64| -> i32 wasm_f64::le(a, b)),
-            Operator::F64Ge => step!(|a:f64, b:f64| -> i32 wasm_f64::ge(a, b)),
-            Operator::I32Clz => step!(|a:i32| -> i32 a.leading_zeros() as i32),
-            Operator::I32Ctz => step!(|a:i32| -> i32 a.trailing_zeros() as i32),
-            Operator::I32Popcnt => step!(|a:i32| -> i32 a.count_ones() as i32),
-            Operator::I32Add => step!(|a:i32, b:i32| -> i32 a.wrapping_add(b)),
-            Operator::I32Sub => step!(|a:i32, b:i32| -> i32 a.wrapping_sub(b)),
-            Operator::I32Mul => step!(|a:i32, b:i32| -> i32 a.wrapping_mul(b)),
-            Operator::I32DivS => step!(|a: i32, b: i32| -> i32 {
+            Instr::F32Eq => step!(|a:f32, b:f32| -> i32 wasm_f32::eq(a, b)),
+            Instr::F32Ne => step!(|a:f32, b:f32| -> i32 wasm_f32::ne(a, b)),
+            Instr::F32Lt => step!(|a:f32, b:f32| -> i32 wasm_f32::lt(a, b)),
+            Instr::F32Gt => step!(|a:f32, b:f32| -> i32 wasm_f32::gt(a, b)),
+            Instr::F32Le => step!(|a:f32, b:f32| -> i32 wasm_f32::le(a, b)),
+            Instr::F32Ge => step!(|a:f32, b:f32| -> i32 wasm_f32::ge(a, b)),
+            Instr::F64Eq => step!(|a:f64, b:f64| -> i32 wasm_f64::eq(a, b)),
+            Instr::F64Ne => step!(|a:f64, b:f64| -> i32 wasm_f64::ne(a, b)),
+            Instr::F64Lt => step!(|a:f64, b:f64| -> i32 wasm_f64::lt(a, b)),
+            Instr::F64Gt => step!(|a:f64, b:f64| -> i32 wasm_f64::gt(a, b)),
+            Instr::F64Le => step!(|a:f64, b:f64| -> i32 wasm_f64::le(a, b)),
+            Instr::F64Ge => step!(|a:f64, b:f64| -> i32 wasm_f64::ge(a, b)),
+            Instr::I32Clz => step!(|a:i32| -> i32 a.leading_zeros() as i32),
+            Instr::I32Ctz => step!(|a:i32| -> i32 a.trailing_zeros() as i32),
+            Instr::I32Popcnt => step!(|a:i32| -> i32 a.count_ones() as i32),
+            Instr::I32Add => step!(|a:i32, b:i32| -> i32 a.wrapping_add(b)),
+            Instr::I32Sub => step!(|a:i32, b:i32| -> i32 a.wrapping_sub(b)),
+            Instr::I32Mul => step!(|a:i32, b:i32| -> i32 a.wrapping_mul(b)),
+            Instr::I32DivS => step!(|a: i32, b: i32| -> i32 {
                 if let Some(c) = a.checked_div(b) {
                     c
                 } else {
@@ -493,7 +1070,7 @@ pub(crate) fn eval<'a>(
                     });
                 }
             }),
-            Operator::I32DivU => step!(|a: i32, b: i32| -> i32 {
+            Instr::I32DivU => step!(|a: i32, b: i32| -> i32 {
                 if let Some(c) = (a as u32).checked_div(b as u32) {
                     c as i32
                 } else {
@@ -504,7 +1081,7 @@ pub(crate) fn eval<'a>(
                     });
                 }
             }),
-            Operator::I32RemS => step!(|a: i32, b: i32| -> i32 {
+            Instr::I32RemS => step!(|a: i32, b: i32| -> i32 {
                 if let Some(c) = a.checked_rem(b) {
                     c
                 } else if b == 0 {
@@ -514,7 +1091,7 @@ pub(crate) fn eval<'a>(
                     0
                 }
             }),
-            Operator::I32RemU => step!(|a: i32, b: i32| -> i32 {
+            Instr::I32RemU => step!(|a: i32, b: i32| -> i32 {
                 if let Some(c) = (a as u32).checked_rem(b as u32) {
                     c as i32
                 } else {
@@ -525,23 +1102,23 @@ pub(crate) fn eval<'a>(
                     });
                 }
             }),
-            Operator::I32And => step!(|a:i32, b:i32| -> i32 a & b),
-            Operator::I32Or => step!(|a:i32, b:i32| -> i32 a | b),
-            Operator::I32Xor => step!(|a:i32, b:i32| -> i32 a ^ b),
-            Operator::I32Shl => step!(|a:i32, b:i32| -> i32 a.wrapping_shl(b as u32)),
-            Operator::I32ShrS => step!(|a:i32, b:i32| -> i32 a.wrapping_shr(b as u32)),
-            Operator::I32ShrU => {
+            Instr::I32And => step!(|a:i32, b:i32| -> i32 a & b),
+            Instr::I32Or => step!(|a:i32, b:i32| -> i32 a | b),
+            Instr::I32Xor => step!(|a:i32, b:i32| -> i32 a ^ b),
+            Instr::I32Shl => step!(|a:i32, b:i32| -> i32 a.wrapping_shl(b as u32)),
+            Instr::I32ShrS => step!(|a:i32, b:i32| -> i32 a.wrapping_shr(b as u32)),
+            Instr::I32ShrU => {
                 step!(|a:i32, b:i32| -> i32 (a as u32).wrapping_shr(b as u32) as i32)
             }
-            Operator::I32Rotl => step!(|a:i32, b:i32| -> i32 a.rotate_left(b as u32)),
-            Operator::I32Rotr => step!(|a:i32, b:i32| -> i32 a.rotate_right(b as u32)),
-            Operator::I64Clz => step!(|a:i64| -> i64 a.leading_zeros() as i64),
-            Operator::I64Ctz => step!(|a:i64| -> i64 a.trailing_zeros() as i64),
-            Operator::I64Popcnt => step!(|a:i64| -> i64 a.count_ones() as i64),
-            Operator::I64Add => step!(|a:i64, b:i64| -> i64 a.wrapping_add(b)),
-            Operator::I64Sub => step!(|a:i64, b:i64| -> i64 a.wrapping_sub(b)),
-            Operator::I64Mul => step!(|a:i64, b:i64| -> i64 a.wrapping_mul(b)),
-            Operator::I64DivS => step!(|a: i64, b: i64| -> i64 {
+            Instr::I32Rotl => step!(|a:i32, b:i32| -> i32 a.rotate_left(b as u32)),
+            Instr::I32Rotr => step!(|a:i32, b:i32| -> i32 a.rotate_right(b as u32)),
+            Instr::I64Clz => step!(|a:i64| -> i64 a.leading_zeros() as i64),
+            Instr::I64Ctz => step!(|a:i64| -> i64 a.trailing_zeros() as i64),
+            Instr::I64Popcnt => step!(|a:i64| -> i64 a.count_ones() as i64),
+            Instr::I64Add => step!(|a:i64, b:i64| -> i64 a.wrapping_add(b)),
+            Instr::I64Sub => step!(|a:i64, b:i64| -> i64 a.wrapping_sub(b)),
+            Instr::I64Mul => step!(|a:i64, b:i64| -> i64 a.wrapping_mul(b)),
+            Instr::I64DivS => step!(|a: i64, b: i64| -> i64 {
                 if let Some(c) = a.checked_div(b) {
                     c
                 } else {
@@ -552,7 +1129,7 @@ pub(crate) fn eval<'a>(
                     });
                 }
             }),
-            Operator::I64DivU => step!(|a: i64, b: i64| -> i64 {
+            Instr::I64DivU => step!(|a: i64, b: i64| -> i64 {
                 if let Some(c) = (a as u64).checked_div(b as u64) {
                     c as i64
                 } else {
@@ -563,7 +1140,7 @@ pub(crate) fn eval<'a>(
                     });
                 }
             }),
-            Operator::I64RemS => step!(|a: i64, b: i64| -> i64 {
+            Instr::I64RemS => step!(|a: i64, b: i64| -> i64 {
                 if let Some(c) = a.checked_rem(b) {
                     c
                 } else if b == 0 {
@@ -573,7 +1150,7 @@ pub(crate) fn eval<'a>(
                     0
                 }
             }),
-            Operator::I64RemU => step!(|a: i64, b: i64| -> i64 {
+            Instr::I64RemU => step!(|a: i64, b: i64| -> i64 {
                 if let Some(c) = (a as u64).checked_rem(b as u64) {
                     c as i64
                 } else {
@@ -584,383 +1161,917 @@ pub(crate) fn eval<'a>(
                     });
                 }
             }),
-            Operator::I64And => step!(|a: i64, b: i64| -> i64 a & b),
-            Operator::I64Or => step!(|a: i64, b: i64| -> i64 a | b),
-            Operator::I64Xor => step!(|a: i64, b: i64| -> i64 a ^ b),
-            Operator::I64Shl => step!(|a: i64, b: i64| -> i64 a.wrapping_shl(b as u32)),
-            Operator::I64ShrS => step!(|a: i64, b: i64| -> i64 a.wrapping_shr(b as u32)),
-            Operator::I64ShrU => {
+            Instr::I64And => step!(|a: i64, b: i64| -> i64 a & b),
+            Instr::I64Or => step!(|a: i64, b: i64| -> i64 a | b),
+            Instr::I64Xor => step!(|a: i64, b: i64| -> i64 a ^ b),
+            Instr::I64Shl => step!(|a: i64, b: i64| -> i64 a.wrapping_shl(b as u32)),
+            Instr::I64ShrS => step!(|a: i64, b: i64| -> i64 a.wrapping_shr(b as u32)),
+            Instr::I64ShrU => {
                 step!(|a: i64, b: i64| -> i64 (a as u64).wrapping_shr(b as u32) as i64)
             }
-            Operator::I64Rotl => step!(|a:i64, b:i64| -> i64 a.rotate_left(b as u32)),
-            Operator::I64Rotr => step!(|a:i64, b:i64| -> i64 a.rotate_right(b as u32)),
-            Operator::F32Abs => step!(|a:f32| -> f32 wasm_f32::abs(a)),
-            Operator::F32Neg => step!(|a:f32| -> f32 wasm_f32::neg(a)),
-            Operator::F32Ceil => step!(|a:f32| -> f32 wasm_f32::ceil(a)),
-            Operator::F32Floor => step!(|a:f32| -> f32 wasm_f32::floor(a)),
-            Operator::F32Trunc => step!(|a:f32| -> f32 wasm_f32::trunc(a)),
-            Operator::F32Nearest => step!(|a:f32| -> f32 wasm_f32::nearby(a)),
-            Operator::F32Sqrt => step!(|a:f32| -> f32 wasm_f32::sqrt(a)),
-            Operator::F32Add => step!(|a:f32, b:f32| -> f32 wasm_f32::add(a, b)),
-            Operator::F32Sub => step!(|a:f32, b:f32| -> f32 wasm_f32::sub(a, b)),
-            Operator::F32Mul => step!(|a:f32, b:f32| -> f32 wasm_f32::mul(a, b)),
-            Operator::F32Div => step!(|a:f32, b:f32| -> f32 wasm_f32::div(a, b)),
-            Operator::F32Min => step!(|a:f32, b:f32| -> f32 wasm_f32::min(a, b)),
-            Operator::F32Max => step!(|a:f32, b:f32| -> f32 wasm_f32::max(a, b)),
-            Operator::F32Copysign => step!(|a:f32, b:f32| -> f32 wasm_f32::copysign(a, b)),
-            Operator::F64Abs => step!(|a:f64| -> f64 wasm_f64::abs(a)),
-            Operator::F64Neg => step!(|a:f64| -> f64 wasm_f64::neg(a)),
-            Operator::F64Ceil => step!(|a:f64| -> f64 wasm_f64::ceil(a)),
-            Operator::F64Floor => step!(|a:f64| -> f64 wasm_f64::floor(a)),
-            Operator::F64Trunc => step!(|a:f64| -> f64 wasm_f64::trunc(a)),
-            Operator::F64Nearest => step!(|a:f64| -> f64 wasm_f64::nearby(a)),
-            Operator::F64Sqrt => step!(|a:f64| -> f64 wasm_f64::sqrt(a)),
-            Operator::F64Add => step!(|a:f64, b:f64| -> f64 wasm_f64::add(a, b)),
-            Operator::F64Sub => step!(|a:f64, b:f64| -> f64 wasm_f64::sub(a, b)),
-            Operator::F64Mul => step!(|a:f64, b:f64| -> f64 wasm_f64::mul(a, b)),
-            Operator::F64Div => step!(|a:f64, b:f64| -> f64 wasm_f64::div(a, b)),
-            Operator::F64Min => step!(|a:f64, b:f64| -> f64 wasm_f64::min(a, b)),
-            Operator::F64Max => step!(|a:f64, b:f64| -> f64 wasm_f64::max(a, b)),
-            Operator::F64Copysign => step!(|a:f64, b:f64| -> f64 wasm_f64::copysign(a, b)),
-            Operator::I32WrapI64 => step!(|a:i64| -> i32 a as i32),
-            Operator::I32TruncF32S => step!(|a:f32| -> i32 match wasm_f32::trunc_i32(a) {
+            Instr::I64Rotl => step!(|a:i64, b:i64| -> i64 a.rotate_left(b as u32)),
+            Instr::I64Rotr => step!(|a:i64, b:i64| -> i64 a.rotate_right(b as u32)),
+            Instr::F32Abs => step!(|a:f32| -> f32 wasm_f32::abs(a)),
+            Instr::F32Neg => step!(|a:f32| -> f32 wasm_f32::neg(a)),
+            Instr::F32Ceil => step!(|a:f32| -> f32 wasm_f32::ceil(a)),
+            Instr::F32Floor => step!(|a:f32| -> f32 wasm_f32::floor(a)),
+            Instr::F32Trunc => step!(|a:f32| -> f32 wasm_f32::trunc(a)),
+            Instr::F32Nearest => step!(|a:f32| -> f32 wasm_f32::nearby(a)),
+            Instr::F32Sqrt => step!(|a:f32| -> f32 wasm_f32::sqrt(a)),
+            Instr::F32Add => step!(|a:f32, b:f32| -> f32 wasm_f32::add(a, b)),
+            Instr::F32Sub => step!(|a:f32, b:f32| -> f32 wasm_f32::sub(a, b)),
+            Instr::F32Mul => step!(|a:f32, b:f32| -> f32 wasm_f32::mul(a, b)),
+            Instr::F32Div => step!(|a:f32, b:f32| -> f32 wasm_f32::div(a, b)),
+            Instr::F32Min => step!(|a:f32, b:f32| -> f32 wasm_f32::min(a, b)),
+            Instr::F32Max => step!(|a:f32, b:f32| -> f32 wasm_f32::max(a, b)),
+            Instr::F32Copysign => step!(|a:f32, b:f32| -> f32 wasm_f32::copysign(a, b)),
+            Instr::F64Abs => step!(|a:f64| -> f64 wasm_f64::abs(a)),
+            Instr::F64Neg => step!(|a:f64| -> f64 wasm_f64::neg(a)),
+            Instr::F64Ceil => step!(|a:f64| -> f64 wasm_f64::ceil(a)),
+            Instr::F64Floor => step!(|a:f64| -> f64 wasm_f64::floor(a)),
+            Instr::F64Trunc => step!(|a:f64| -> f64 wasm_f64::trunc(a)),
+            Instr::F64Nearest => step!(|a:f64| -> f64 wasm_f64::nearby(a)),
+            Instr::F64Sqrt => step!(|a:f64| -> f64 wasm_f64::sqrt(a)),
+            Instr::F64Add => step!(|a:f64, b:f64| -> f64 wasm_f64::add(a, b)),
+            Instr::F64Sub => step!(|a:f64, b:f64| -> f64 wasm_f64::sub(a, b)),
+            Instr::F64Mul => step!(|a:f64, b:f64| -> f64 wasm_f64::mul(a, b)),
+            Instr::F64Div => step!(|a:f64, b:f64| -> f64 wasm_f64::div(a, b)),
+            Instr::F64Min => step!(|a:f64, b:f64| -> f64 wasm_f64::min(a, b)),
+            Instr::F64Max => step!(|a:f64, b:f64| -> f64 wasm_f64::max(a, b)),
+            Instr::F64Copysign => step!(|a:f64, b:f64| -> f64 wasm_f64::copysign(a, b)),
+            Instr::I32WrapI64 => step!(|a:i64| -> i32 a as i32),
+            Instr::I32TruncF32S => step!(|a:f32| -> i32 match wasm_f32::trunc_i32(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I32TruncF32U => step!(|a:f32| -> i32 match wasm_f32::trunc_u32(a) {
+            Instr::I32TruncF32U => step!(|a:f32| -> i32 match wasm_f32::trunc_u32(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I32TruncF64S => step!(|a:f64| -> i32 match wasm_f64::trunc_i32(a) {
+            Instr::I32TruncF64S => step!(|a:f64| -> i32 match wasm_f64::trunc_i32(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I32TruncF64U => step!(|a:f64| -> i32 match wasm_f64::trunc_u32(a) {
+            Instr::I32TruncF64U => step!(|a:f64| -> i32 match wasm_f64::trunc_u32(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I64ExtendI32S => step!(|a:i32| -> i64 (a as i64)),
-            Operator::I64ExtendI32U => step!(|a:i32| -> i64 (a as u32 as i64)),
-            Operator::I64TruncF32S => step!(|a:f32| -> i64 match wasm_f32::trunc_i64(a) {
+            Instr::I64ExtendI32S => step!(|a:i32| -> i64 (a as i64)),
+            Instr::I64ExtendI32U => step!(|a:i32| -> i64 (a as u32 as i64)),
+            Instr::I64TruncF32S => step!(|a:f32| -> i64 match wasm_f32::trunc_i64(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I64TruncF32U => step!(|a:f32| -> i64 match wasm_f32::trunc_u64(a) {
+            Instr::I64TruncF32U => step!(|a:f32| -> i64 match wasm_f32::trunc_u64(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I64TruncF64S => step!(|a:f64| -> i64 match wasm_f64::trunc_i64(a) {
+            Instr::I64TruncF64S => step!(|a:f64| -> i64 match wasm_f64::trunc_i64(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::I64TruncF64U => step!(|a:f64| -> i64 match wasm_f64::trunc_u64(a) {
+            Instr::I64TruncF64U => step!(|a:f64| -> i64 match wasm_f64::trunc_u64(a) {
                 Ok(c) => c,
                 Err(kind) => trap!(kind),
             }),
-            Operator::F32ConvertI32S => step!(|a:i32| -> f32 wasm_f32::from_i32(a)),
-            Operator::F32ConvertI32U => step!(|a:i32| -> f32 wasm_f32::from_u32(a)),
-            Operator::F32ConvertI64S => step!(|a:i64| -> f32 wasm_f32::from_i64(a)),
-            Operator::F32ConvertI64U => step!(|a:i64| -> f32 wasm_f32::from_u64(a)),
-            Operator::F32DemoteF64 => step!(|a:f64| -> f32 wasm_f32::from_f64(a)),
-            Operator::F64ConvertI32S => step!(|a:i32| -> f64 wasm_f64::from_i32(a)),
-            Operator::F64ConvertI32U => step!(|a:i32| -> f64 wasm_f64::from_u32(a)),
-            Operator::F64ConvertI64S => step!(|a:i64| -> f64 wasm_f64::from_i64(a)),
-            Operator::F64ConvertI64U => step!(|a:i64| -> f64 wasm_f64::from_u64(a)),
-            Operator::F64PromoteF32 => step!(|a:f32| -> f64 wasm_f64::from_f32(a)),
-            Operator::I32ReinterpretF32 => step!(|a:f32| -> i32 a as i32),
-            Operator::I64ReinterpretF64 => step!(|a:f64| -> i64 a as i64),
-            Operator::F32ReinterpretI32 => step!(|a:i32| -> f32 a as u32),
-            Operator::F64ReinterpretI64 => step!(|a:i64| -> f64 a as u64),
-            Operator::I32TruncSatF32S => step!(|a:f32| -> i32 wasm_f32::trunc_i32_sat(a)),
-            Operator::I32TruncSatF32U => step!(|a:f32| -> i32 wasm_f32::trunc_u32_sat(a)),
-            Operator::I32TruncSatF64S => step!(|a:f64| -> i32 wasm_f64::trunc_i32_sat(a)),
-            Operator::I32TruncSatF64U => step!(|a:f64| -> i32 wasm_f64::trunc_u32_sat(a)),
-            Operator::I64TruncSatF32S => step!(|a:f32| -> i64 wasm_f32::trunc_i64_sat(a)),
-            Operator::I64TruncSatF32U => step!(|a:f32| -> i64 wasm_f32::trunc_u64_sat(a)),
-            Operator::I64TruncSatF64S => step!(|a:f64| -> i64 wasm_f64::trunc_i64_sat(a)),
-            Operator::I64TruncSatF64U => step!(|a:f64| -> i64 wasm_f64::trunc_u64_sat(a)),
-            Operator::I32Extend16S => step!(|a: i32| -> i32 (a as i16) as i32),
-            Operator::I32Extend8S => step!(|a: i32| -> i32 (a as i8) as i32),
-            Operator::I64Extend32S => step!(|a: i64| -> i64 (a as i32) as i64),
-            Operator::I64Extend16S => step!(|a: i64| -> i64 (a as i16) as i64),
-            Operator::I64Extend8S => step!(|a: i64| -> i64 (a as i8) as i64),
-            Operator::I32AtomicLoad { .. }
-            | Operator::I32AtomicLoad16U { .. }
-            | Operator::I32AtomicLoad8U { .. }
-            | Operator::I64AtomicLoad { .. }
-            | Operator::I64AtomicLoad32U { .. }
-            | Operator::I64AtomicLoad16U { .. }
-            | Operator::I64AtomicLoad8U { .. }
-            | Operator::I32AtomicStore { .. }
-            | Operator::I32AtomicStore16 { .. }
-            | Operator::I32AtomicStore8 { .. }
-            | Operator::I64AtomicStore { .. }
-            | Operator::I64AtomicStore32 { .. }
-            | Operator::I64AtomicStore16 { .. }
-            | Operator::I64AtomicStore8 { .. }
-            | Operator::I32AtomicRmwAdd { .. }
-            | Operator::I32AtomicRmwSub { .. }
-            | Operator::I32AtomicRmwAnd { .. }
-            | Operator::I32AtomicRmwOr { .. }
-            | Operator::I32AtomicRmwXor { .. }
-            | Operator::I32AtomicRmw16AddU { .. }
-            | Operator::I32AtomicRmw16SubU { .. }
-            | Operator::I32AtomicRmw16AndU { .. }
-            | Operator::I32AtomicRmw16OrU { .. }
-            | Operator::I32AtomicRmw16XorU { .. }
-            | Operator::I32AtomicRmw8AddU { .. }
-            | Operator::I32AtomicRmw8SubU { .. }
-            | Operator::I32AtomicRmw8AndU { .. }
-            | Operator::I32AtomicRmw8OrU { .. }
-            | Operator::I32AtomicRmw8XorU { .. }
-            | Operator::I64AtomicRmwAdd { .. }
-            | Operator::I64AtomicRmwSub { .. }
-            | Operator::I64AtomicRmwAnd { .. }
-            | Operator::I64AtomicRmwOr { .. }
-            | Operator::I64AtomicRmwXor { .. }
-            | Operator::I64AtomicRmw32AddU { .. }
-            | Operator::I64AtomicRmw32SubU { .. }
-            | Operator::I64AtomicRmw32AndU { .. }
-            | Operator::I64AtomicRmw32OrU { .. }
-            | Operator::I64AtomicRmw32XorU { .. }
-            | Operator::I64AtomicRmw16AddU { .. }
-            | Operator::I64AtomicRmw16SubU { .. }
-            | Operator::I64AtomicRmw16AndU { .. }
-            | Operator::I64AtomicRmw16OrU { .. }
-            | Operator::I64AtomicRmw16XorU { .. }
-            | Operator::I64AtomicRmw8AddU { .. }
-            | Operator::I64AtomicRmw8SubU { .. }
-            | Operator::I64AtomicRmw8AndU { .. }
-            | Operator::I64AtomicRmw8OrU { .. }
-            | Operator::I64AtomicRmw8XorU { .. }
-            | Operator::I32AtomicRmwXchg { .. }
-            | Operator::I32AtomicRmw16XchgU { .. }
-            | Operator::I32AtomicRmw8XchgU { .. }
-            | Operator::I32AtomicRmwCmpxchg { .. }
-            | Operator::I32AtomicRmw16CmpxchgU { .. }
-            | Operator::I32AtomicRmw8CmpxchgU { .. }
-            | Operator::I64AtomicRmwXchg { .. }
-            | Operator::I64AtomicRmw32XchgU { .. }
-            | Operator::I64AtomicRmw16XchgU { .. }
-            | Operator::I64AtomicRmw8XchgU { .. }
-            | Operator::I64AtomicRmwCmpxchg { .. }
-            | Operator::I64AtomicRmw32CmpxchgU { .. }
-            | Operator::I64AtomicRmw16CmpxchgU { .. }
-            | Operator::I64AtomicRmw8CmpxchgU { .. }
-            | Operator::MemoryAtomicNotify { .. }
-            | Operator::MemoryAtomicWait32 { .. }
-            | Operator::MemoryAtomicWait64 { .. } => op_notimpl!(),
-            Operator::AtomicFence { ref flags } => op_notimpl!(),
-            Operator::RefNull { .. } | Operator::RefIsNull | Operator::RefFunc { .. } => {
-                op_notimpl!()
-            }
-            Operator::V128Load { .. } | Operator::V128Store { .. } => op_notimpl!(),
-            Operator::V128Const { .. }
-            | Operator::I8x16Splat
-            | Operator::I16x8Splat
-            | Operator::I32x4Splat
-            | Operator::I64x2Splat
-            | Operator::F32x4Splat
-            | Operator::F64x2Splat => op_notimpl!(),
-            Operator::I8x16ExtractLaneS { lane }
-            | Operator::I8x16ExtractLaneU { lane }
-            | Operator::I16x8ExtractLaneS { lane }
-            | Operator::I16x8ExtractLaneU { lane }
-            | Operator::I32x4ExtractLane { lane }
-            | Operator::I8x16ReplaceLane { lane }
-            | Operator::I16x8ReplaceLane { lane }
-            | Operator::I32x4ReplaceLane { lane }
-            | Operator::I64x2ExtractLane { lane }
-            | Operator::I64x2ReplaceLane { lane }
-            | Operator::F32x4ExtractLane { lane }
-            | Operator::F32x4ReplaceLane { lane }
-            | Operator::F64x2ExtractLane { lane }
-            | Operator::F64x2ReplaceLane { lane } => op_notimpl!(),
-            Operator::F32x4Eq
-            | Operator::F32x4Ne
-            | Operator::F32x4Lt
-            | Operator::F32x4Gt
-            | Operator::F32x4Le
-            | Operator::F32x4Ge
-            | Operator::F64x2Eq
-            | Operator::F64x2Ne
-            | Operator::F64x2Lt
-            | Operator::F64x2Gt
-            | Operator::F64x2Le
-            | Operator::F64x2Ge
-            | Operator::F32x4Add
-            | Operator::F32x4Sub
-            | Operator::F32x4Mul
-            | Operator::F32x4Div
-            | Operator::F32x4Min
-            | Operator::F32x4Max
-            | Operator::F64x2Add
-            | Operator::F64x2Sub
-            | Operator::F64x2Mul
-            | Operator::F64x2Div
-            | Operator::F64x2Min
-            | Operator::F64x2Max
-            | Operator::I8x16Eq
-            | Operator::I8x16Ne
-            | Operator::I8x16LtS
-            | Operator::I8x16LtU
-            | Operator::I8x16GtS
-            | Operator::I8x16GtU
-            | Operator::I8x16LeS
-            | Operator::I8x16LeU
-            | Operator::I8x16GeS
-            | Operator::I8x16GeU
-            | Operator::I16x8Eq
-            | Operator::I16x8Ne
-            | Operator::I16x8LtS
-            | Operator::I16x8LtU
-            | Operator::I16x8GtS
-            | Operator::I16x8GtU
-            | Operator::I16x8LeS
-            | Operator::I16x8LeU
-            | Operator::I16x8GeS
-            | Operator::I16x8GeU
-            | Operator::I32x4Eq
-            | Operator::I32x4Ne
-            | Operator::I32x4LtS
-            | Operator::I32x4LtU
-            | Operator::I32x4GtS
-            | Operator::I32x4GtU
-            | Operator::I32x4LeS
-            | Operator::I32x4LeU
-            | Operator::I32x4GeS
-            | Operator::I32x4GeU
-            | Operator::V128And
-            | Operator::V128Or
-            | Operator::V128Xor
-            | Operator::I8x16Add
-            | Operator::I8x16AddSaturateS
-            | Operator::I8x16AddSaturateU
-            | Operator::I8x16Sub
-            | Operator::I8x16SubSaturateS
-            | Operator::I8x16SubSaturateU
-            | Operator::I16x8Add
-            | Operator::I16x8AddSaturateS
-            | Operator::I16x8AddSaturateU
-            | Operator::I16x8Sub
-            | Operator::I16x8SubSaturateS
-            | Operator::I16x8SubSaturateU
-            | Operator::I16x8Mul
-            | Operator::I32x4Add
-            | Operator::I32x4Sub
-            | Operator::I32x4Mul
-            | Operator::I64x2Add
-            | Operator::I64x2Sub
-            | Operator::F32x4Abs
-            | Operator::F32x4Neg
-            | Operator::F32x4Sqrt
-            | Operator::F64x2Abs
-            | Operator::F64x2Neg
-            | Operator::F64x2Sqrt
-            | Operator::F32x4ConvertI32x4S
-            | Operator::F32x4ConvertI32x4U
-            | Operator::V128Not
-            | Operator::I8x16Neg
-            | Operator::I8x16Abs
-            | Operator::I16x8Neg
-            | Operator::I16x8Abs
-            | Operator::I32x4Neg
-            | Operator::I32x4Abs
-            | Operator::I64x2Neg
-            | Operator::I32x4TruncSatF32x4S
-            | Operator::I32x4TruncSatF32x4U
-            | Operator::V128Bitselect
-            | Operator::I8x16AnyTrue
-            | Operator::I8x16AllTrue
-            | Operator::I16x8AnyTrue
-            | Operator::I16x8AllTrue
-            | Operator::I32x4AnyTrue
-            | Operator::I32x4AllTrue
-            | Operator::I8x16Shl
-            | Operator::I8x16ShrS
-            | Operator::I8x16ShrU
-            | Operator::I8x16Bitmask
-            | Operator::I8x16MinS
-            | Operator::I8x16MinU
-            | Operator::I8x16MaxS
-            | Operator::I8x16MaxU
-            | Operator::I16x8Shl
-            | Operator::I16x8ShrS
-            | Operator::I16x8ShrU
-            | Operator::I16x8Bitmask
-            | Operator::I16x8MinS
-            | Operator::I16x8MinU
-            | Operator::I16x8MaxS
-            | Operator::I16x8MaxU
-            | Operator::I32x4Shl
-            | Operator::I32x4ShrS
-            | Operator::I32x4ShrU
-            | Operator::I32x4Bitmask
-            | Operator::I32x4MinS
-            | Operator::I32x4MinU
-            | Operator::I32x4MaxS
-            | Operator::I32x4MaxU
-            | Operator::I64x2Shl
-            | Operator::I64x2ShrS
-            | Operator::I64x2ShrU
-            | Operator::V8x16Swizzle => op_notimpl!(),
-            Operator::V8x16Shuffle { ref lanes } => op_notimpl!(),
-            Operator::V8x16LoadSplat { .. }
-            | Operator::V16x8LoadSplat { .. }
-            | Operator::V32x4LoadSplat { .. }
-            | Operator::V64x2LoadSplat { .. } => op_notimpl!(),
-            Operator::MemoryCopy { .. } | Operator::MemoryFill { .. } => op_notimpl!(),
-            Operator::MemoryInit { segment, .. }
-            | Operator::DataDrop { segment }
-            | Operator::ElemDrop { segment } => op_notimpl!(),
-            Operator::TableInit { table, segment } => op_notimpl!(),
-            Operator::TableCopy {
+            Instr::F32ConvertI32S => step!(|a:i32| -> f32 wasm_f32::from_i32(a)),
+            Instr::F32ConvertI32U => step!(|a:i32| -> f32 wasm_f32::from_u32(a)),
+            Instr::F32ConvertI64S => step!(|a:i64| -> f32 wasm_f32::from_i64(a)),
+            Instr::F32ConvertI64U => step!(|a:i64| -> f32 wasm_f32::from_u64(a)),
+            Instr::F32DemoteF64 => step!(|a:f64| -> f32 wasm_f32::from_f64(a)),
+            Instr::F64ConvertI32S => step!(|a:i32| -> f64 wasm_f64::from_i32(a)),
+            Instr::F64ConvertI32U => step!(|a:i32| -> f64 wasm_f64::from_u32(a)),
+            Instr::F64ConvertI64S => step!(|a:i64| -> f64 wasm_f64::from_i64(a)),
+            Instr::F64ConvertI64U => step!(|a:i64| -> f64 wasm_f64::from_u64(a)),
+            Instr::F64PromoteF32 => step!(|a:f32| -> f64 wasm_f64::from_f32(a)),
+            Instr::I32ReinterpretF32 => step!(|a:f32| -> i32 a as i32),
+            Instr::I64ReinterpretF64 => step!(|a:f64| -> i64 a as i64),
+            Instr::F32ReinterpretI32 => step!(|a:i32| -> f32 a as u32),
+            Instr::F64ReinterpretI64 => step!(|a:i64| -> f64 a as u64),
+            Instr::I32TruncSatF32S => step!(|a:f32| -> i32 wasm_f32::trunc_i32_sat(a)),
+            Instr::I32TruncSatF32U => step!(|a:f32| -> i32 wasm_f32::trunc_u32_sat(a)),
+            Instr::I32TruncSatF64S => step!(|a:f64| -> i32 wasm_f64::trunc_i32_sat(a)),
+            Instr::I32TruncSatF64U => step!(|a:f64| -> i32 wasm_f64::trunc_u32_sat(a)),
+            Instr::I64TruncSatF32S => step!(|a:f32| -> i64 wasm_f32::trunc_i64_sat(a)),
+            Instr::I64TruncSatF32U => step!(|a:f32| -> i64 wasm_f32::trunc_u64_sat(a)),
+            Instr::I64TruncSatF64S => step!(|a:f64| -> i64 wasm_f64::trunc_i64_sat(a)),
+            Instr::I64TruncSatF64U => step!(|a:f64| -> i64 wasm_f64::trunc_u64_sat(a)),
+            Instr::I32Extend16S => step!(|a: i32| -> i32 (a as i16) as i32),
+            Instr::I32Extend8S => step!(|a: i32| -> i32 (a as i8) as i32),
+            Instr::I64Extend32S => step!(|a: i64| -> i64 (a as i32) as i64),
+            Instr::I64Extend16S => step!(|a: i64| -> i64 (a as i16) as i64),
+            Instr::I64Extend8S => step!(|a: i64| -> i64 (a as i8) as i64),
+            Instr::I32AtomicLoad { memarg } => atomic_load!(memarg; i32 as u32),
+            Instr::I32AtomicLoad16U { memarg } => atomic_load!(memarg; i32 as u16),
+            Instr::I32AtomicLoad8U { memarg } => atomic_load!(memarg; i32 as u8),
+            Instr::I32AtomicStore { memarg } => atomic_store!(memarg; i32 as u32),
+            Instr::I32AtomicStore16 { memarg } => atomic_store!(memarg; i32 as u16),
+            Instr::I32AtomicStore8 { memarg } => atomic_store!(memarg; i32 as u8),
+            Instr::I32AtomicRmwAdd { memarg } => atomic_rmw!(memarg; i32 as u32, fetch_add),
+            Instr::I32AtomicRmwSub { memarg } => atomic_rmw!(memarg; i32 as u32, fetch_sub),
+            Instr::I32AtomicRmwAnd { memarg } => atomic_rmw!(memarg; i32 as u32, fetch_and),
+            Instr::I32AtomicRmwOr { memarg } => atomic_rmw!(memarg; i32 as u32, fetch_or),
+            Instr::I32AtomicRmwXor { memarg } => atomic_rmw!(memarg; i32 as u32, fetch_xor),
+            Instr::I32AtomicRmwXchg { memarg } => atomic_rmw!(memarg; i32 as u32, swap),
+            Instr::I32AtomicRmwCmpxchg { memarg } => atomic_cmpxchg!(memarg; i32 as u32),
+            Instr::I32AtomicRmw16AddU { memarg } => atomic_rmw!(memarg; i32 as u16, fetch_add),
+            Instr::I32AtomicRmw16SubU { memarg } => atomic_rmw!(memarg; i32 as u16, fetch_sub),
+            Instr::I32AtomicRmw16AndU { memarg } => atomic_rmw!(memarg; i32 as u16, fetch_and),
+            Instr::I32AtomicRmw16OrU { memarg } => atomic_rmw!(memarg; i32 as u16, fetch_or),
+            Instr::I32AtomicRmw16XorU { memarg } => atomic_rmw!(memarg; i32 as u16, fetch_xor),
+            Instr::I32AtomicRmw16XchgU { memarg } => atomic_rmw!(memarg; i32 as u16, swap),
+            Instr::I32AtomicRmw16CmpxchgU { memarg } => atomic_cmpxchg!(memarg; i32 as u16),
+            Instr::I32AtomicRmw8AddU { memarg } => atomic_rmw!(memarg; i32 as u8, fetch_add),
+            Instr::I32AtomicRmw8SubU { memarg } => atomic_rmw!(memarg; i32 as u8, fetch_sub),
+            Instr::I32AtomicRmw8AndU { memarg } => atomic_rmw!(memarg; i32 as u8, fetch_and),
+            Instr::I32AtomicRmw8OrU { memarg } => atomic_rmw!(memarg; i32 as u8, fetch_or),
+            Instr::I32AtomicRmw8XorU { memarg } => atomic_rmw!(memarg; i32 as u8, fetch_xor),
+            Instr::I32AtomicRmw8XchgU { memarg } => atomic_rmw!(memarg; i32 as u8, swap),
+            Instr::I32AtomicRmw8CmpxchgU { memarg } => atomic_cmpxchg!(memarg; i32 as u8),
+            Instr::I64AtomicLoad { memarg } => atomic_load!(memarg; i64 as u64),
+            Instr::I64AtomicLoad32U { memarg } => atomic_load!(memarg; i64 as u32),
+            Instr::I64AtomicLoad16U { memarg } => atomic_load!(memarg; i64 as u16),
+            Instr::I64AtomicLoad8U { memarg } => atomic_load!(memarg; i64 as u8),
+            Instr::I64AtomicStore { memarg } => atomic_store!(memarg; i64 as u64),
+            Instr::I64AtomicStore32 { memarg } => atomic_store!(memarg; i64 as u32),
+            Instr::I64AtomicStore16 { memarg } => atomic_store!(memarg; i64 as u16),
+            Instr::I64AtomicStore8 { memarg } => atomic_store!(memarg; i64 as u8),
+            Instr::I64AtomicRmwAdd { memarg } => atomic_rmw!(memarg; i64 as u64, fetch_add),
+            Instr::I64AtomicRmwSub { memarg } => atomic_rmw!(memarg; i64 as u64, fetch_sub),
+            Instr::I64AtomicRmwAnd { memarg } => atomic_rmw!(memarg; i64 as u64, fetch_and),
+            Instr::I64AtomicRmwOr { memarg } => atomic_rmw!(memarg; i64 as u64, fetch_or),
+            Instr::I64AtomicRmwXor { memarg } => atomic_rmw!(memarg; i64 as u64, fetch_xor),
+            Instr::I64AtomicRmwXchg { memarg } => atomic_rmw!(memarg; i64 as u64, swap),
+            Instr::I64AtomicRmwCmpxchg { memarg } => atomic_cmpxchg!(memarg; i64 as u64),
+            Instr::I64AtomicRmw32AddU { memarg } => atomic_rmw!(memarg; i64 as u32, fetch_add),
+            Instr::I64AtomicRmw32SubU { memarg } => atomic_rmw!(memarg; i64 as u32, fetch_sub),
+            Instr::I64AtomicRmw32AndU { memarg } => atomic_rmw!(memarg; i64 as u32, fetch_and),
+            Instr::I64AtomicRmw32OrU { memarg } => atomic_rmw!(memarg; i64 as u32, fetch_or),
+            Instr::I64AtomicRmw32XorU { memarg } => atomic_rmw!(memarg; i64 as u32, fetch_xor),
+            Instr::I64AtomicRmw32XchgU { memarg } => atomic_rmw!(memarg; i64 as u32, swap),
+            Instr::I64AtomicRmw32CmpxchgU { memarg } => atomic_cmpxchg!(memarg; i64 as u32),
+            Instr::I64AtomicRmw16AddU { memarg } => atomic_rmw!(memarg; i64 as u16, fetch_add),
+            Instr::I64AtomicRmw16SubU { memarg } => atomic_rmw!(memarg; i64 as u16, fetch_sub),
+            Instr::I64AtomicRmw16AndU { memarg } => atomic_rmw!(memarg; i64 as u16, fetch_and),
+            Instr::I64AtomicRmw16OrU { memarg } => atomic_rmw!(memarg; i64 as u16, fetch_or),
+            Instr::I64AtomicRmw16XorU { memarg } => atomic_rmw!(memarg; i64 as u16, fetch_xor),
+            Instr::I64AtomicRmw16XchgU { memarg } => atomic_rmw!(memarg; i64 as u16, swap),
+            Instr::I64AtomicRmw16CmpxchgU { memarg } => atomic_cmpxchg!(memarg; i64 as u16),
+            Instr::I64AtomicRmw8AddU { memarg } => atomic_rmw!(memarg; i64 as u8, fetch_add),
+            Instr::I64AtomicRmw8SubU { memarg } => atomic_rmw!(memarg; i64 as u8, fetch_sub),
+            Instr::I64AtomicRmw8AndU { memarg } => atomic_rmw!(memarg; i64 as u8, fetch_and),
+            Instr::I64AtomicRmw8OrU { memarg } => atomic_rmw!(memarg; i64 as u8, fetch_or),
+            Instr::I64AtomicRmw8XorU { memarg } => atomic_rmw!(memarg; i64 as u8, fetch_xor),
+            Instr::I64AtomicRmw8XchgU { memarg } => atomic_rmw!(memarg; i64 as u8, swap),
+            Instr::I64AtomicRmw8CmpxchgU { memarg } => atomic_cmpxchg!(memarg; i64 as u8),
+            Instr::MemoryAtomicNotify { memarg } => {
+                let count = pop!(i32) as u32;
+                let offset = pop_address!();
+                // Only validates the address.
+                let _ = atomic!(memarg, offset; u32);
+                let woken = match memory!(memarg.memory).as_shared() {
+                    Some(memory) => memory.notify(offset + memarg.offset as u64, count),
+                    // Nobody can wait on an unshared memory.
+                    None => 0,
+                };
+                push!(woken as i32; i32)
+            }
+            Instr::MemoryAtomicWait32 { memarg } => {
+                let timeout = pop!(i64);
+                let expected = pop!(i32) as u32;
+                let offset = pop_address!();
+                let cell = atomic!(memarg, offset; u32);
+                let memory = memory!(memarg.memory).clone();
+                let memory = match memory.as_shared() {
+                    Some(memory) => memory,
+                    None => trap!(TrapKind::ExpectedSharedMemory),
+                };
+                // Negative timeout means waiting forever.
+                let timeout = if timeout < 0 {
+                    None
+                } else {
+                    Some(Duration::from_nanos(timeout as u64))
+                };
+                let result = memory.wait(
+                    offset + memarg.offset as u64,
+                    || cell.load(Ordering::SeqCst) == expected,
+                    timeout,
+                    || context.take_interrupt(),
+                );
+                if let WaitResult::Interrupted = result {
+                    trap!(TrapKind::Interrupted);
+                }
+                push!(result as i32; i32)
+            }
+            Instr::MemoryAtomicWait64 { memarg } => {
+                let timeout = pop!(i64);
+                let expected = pop!(i64) as u64;
+                let offset = pop_address!();
+                let cell = atomic!(memarg, offset; u64);
+                let memory = memory!(memarg.memory).clone();
+                let memory = match memory.as_shared() {
+                    Some(memory) => memory,
+                    None => trap!(TrapKind::ExpectedSharedMemory),
+                };
+                // Negative timeout means waiting forever.
+                let timeout = if timeout < 0 {
+                    None
+                } else {
+                    Some(Duration::from_nanos(timeout as u64))
+                };
+                let result = memory.wait(
+                    offset + memarg.offset as u64,
+                    || cell.load(Ordering::SeqCst) == expected,
+                    timeout,
+                    || context.take_interrupt(),
+                );
+                if let WaitResult::Interrupted = result {
+                    trap!(TrapKind::Interrupted);
+                }
+                push!(result as i32; i32)
+            }
+            Instr::AtomicFence { .. } => atomic::fence(Ordering::SeqCst),
+            Instr::RefNull {} => push_val!(Slot::default()),
+            Instr::RefIsNull => {
+                let val = stack.pop();
+                push!(val.is_null() as i32; i32)
+            }
+            Instr::RefFunc { function_index } => {
+                let f = context.get_function(*function_index);
+                push_val!(Slot::from_val(&Val::Func(Some(f))))
+            }
+            Instr::TableInit { table, segment } => {
+                let len = pop!(i32) as u32;
+                let src = pop!(i32) as u32;
+                let dst = pop!(i32) as u32;
+                let items = context.get_element(*segment);
+                if src as u64 + len as u64 > items.len() as u64 {
+                    trap!(TrapKind::TableOutOfBounds);
+                }
+                let items = &items[src as usize..(src + len) as usize];
+                if context.get_table(*table).init(dst, items).is_err() {
+                    trap!(TrapKind::TableOutOfBounds);
+                }
+            }
+            Instr::ElemDrop { segment } => context.drop_element(*segment),
+            Instr::TableCopy {
                 dst_table,
                 src_table,
-            } => op_notimpl!(),
-            Operator::TableGet { table }
-            | Operator::TableSet { table }
-            | Operator::TableGrow { table }
-            | Operator::TableSize { table }
-            | Operator::TableFill { table } => op_notimpl!(),
-            Operator::V128AndNot
-            | Operator::I64x2Mul
-            | Operator::I8x16NarrowI16x8S
-            | Operator::I8x16NarrowI16x8U
-            | Operator::I16x8NarrowI32x4S
-            | Operator::I16x8NarrowI32x4U
-            | Operator::I16x8WidenLowI8x16S
-            | Operator::I16x8WidenHighI8x16S
-            | Operator::I16x8WidenLowI8x16U
-            | Operator::I16x8WidenHighI8x16U
-            | Operator::I32x4WidenLowI16x8S
-            | Operator::I32x4WidenHighI16x8S
-            | Operator::I32x4WidenLowI16x8U
-            | Operator::I32x4WidenHighI16x8U
-            | Operator::I16x8Load8x8S { .. }
-            | Operator::I16x8Load8x8U { .. }
-            | Operator::I32x4Load16x4S { .. }
-            | Operator::I32x4Load16x4U { .. }
-            | Operator::I64x2Load32x2S { .. }
-            | Operator::I64x2Load32x2U { .. }
-            | Operator::I8x16RoundingAverageU
-            | Operator::I16x8RoundingAverageU => op_notimpl!(),
-            Operator::ReturnCall { .. } | Operator::ReturnCallIndirect { .. } => op_notimpl!(),
+            } => {
+                let len = pop!(i32) as u32;
+                let src = pop!(i32) as u32;
+                let dst = pop!(i32) as u32;
+                let result = if dst_table == src_table {
+                    context.get_table(*dst_table).copy_within(dst, src, len)
+                } else {
+                    let src_table = context.get_table(*src_table);
+                    if src as u64 + len as u64 > src_table.size() as u64 {
+                        trap!(TrapKind::TableOutOfBounds);
+                    }
+                    let items = (src..src + len)
+                        .map(|i| src_table.get(i))
+                        .collect::<Result<Vec<_>, _>>();
+                    items.and_then(|items| context.get_table(*dst_table).init(dst, &items))
+                };
+                if result.is_err() {
+                    trap!(TrapKind::TableOutOfBounds);
+                }
+            }
+            Instr::TableGet { table } => {
+                let index = pop!(i32) as u32;
+                match context.get_table(*table).get(index) {
+                    Ok(val) => push_val!(Slot::from_val(&val)),
+                    Err(_) => trap!(TrapKind::TableOutOfBounds),
+                }
+            }
+            Instr::TableSet { table } => {
+                let table = context.get_table(*table);
+                let val = stack.pop().to_val(table.ty().element);
+                let index = pop!(i32) as u32;
+                if table.set(index, val).is_err() {
+                    trap!(TrapKind::TableOutOfBounds);
+                }
+            }
+            Instr::TableGrow { table } => {
+                let delta = pop!(i32) as u32;
+                let table = context.get_table(*table);
+                let init = stack.pop().to_val(table.ty().element);
+                let previous = table.grow(delta, init);
+                push!(previous as i32; i32)
+            }
+            Instr::TableSize { table } => {
+                let size = context.get_table(*table).size();
+                push!(size as i32; i32)
+            }
+            Instr::TableFill { table } => {
+                let len = pop!(i32) as u32;
+                let table = context.get_table(*table);
+                let val = stack.pop().to_val(table.ty().element);
+                let offset = pop!(i32) as u32;
+                if table.fill(offset, val, len).is_err() {
+                    trap!(TrapKind::TableOutOfBounds);
+                }
+            }
+            Instr::V128Load { memarg } => push!(load_v128!(memarg; 16); v128),
+            Instr::V128Store { memarg } => {
+                let val = pop!(v128);
+                let offset = pop_address!();
+                let ptr = memory!(memarg.memory).content_ptr_mut(memarg, offset, 16);
+                if ptr.is_null() {
+                    trap!(TrapKind::OutOfBounds);
+                }
+                unsafe {
+                    std::ptr::copy_nonoverlapping(val.to_le_bytes().as_ptr(), ptr, 16);
+                }
+            }
+            Instr::V128Const { index } => push!(bytecode.v128_constant(*index); v128),
+            Instr::V8x16LoadSplat { memarg } => {
+                let val = load_v128!(memarg; 1);
+                push!(v128::u8x16::splat(val as u8); v128)
+            }
+            Instr::V16x8LoadSplat { memarg } => {
+                let val = load_v128!(memarg; 2);
+                push!(v128::u16x8::splat(val as u16); v128)
+            }
+            Instr::V32x4LoadSplat { memarg } => {
+                let val = load_v128!(memarg; 4);
+                push!(v128::u32x4::splat(val as u32); v128)
+            }
+            Instr::V64x2LoadSplat { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::u64x2::splat(val as u64); v128)
+            }
+            Instr::I16x8Load8x8S { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::i16x8_widen_i8x16(val, false, true); v128)
+            }
+            Instr::I16x8Load8x8U { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::i16x8_widen_i8x16(val, false, false); v128)
+            }
+            Instr::I32x4Load16x4S { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::i32x4_widen_i16x8(val, false, true); v128)
+            }
+            Instr::I32x4Load16x4U { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::i32x4_widen_i16x8(val, false, false); v128)
+            }
+            Instr::I64x2Load32x2S { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::i64x2_widen_i32x4(val, false, true); v128)
+            }
+            Instr::I64x2Load32x2U { memarg } => {
+                let val = load_v128!(memarg; 8);
+                push!(v128::i64x2_widen_i32x4(val, false, false); v128)
+            }
+            Instr::I8x16Splat => step!(|a:i32| -> v128 v128::u8x16::splat(a as u8)),
+            Instr::I16x8Splat => step!(|a:i32| -> v128 v128::u16x8::splat(a as u16)),
+            Instr::I32x4Splat => step!(|a:i32| -> v128 v128::u32x4::splat(a as u32)),
+            Instr::I64x2Splat => step!(|a:i64| -> v128 v128::u64x2::splat(a as u64)),
+            Instr::F32x4Splat => step!(|a:f32| -> v128 v128::u32x4::splat(a)),
+            Instr::F64x2Splat => step!(|a:f64| -> v128 v128::u64x2::splat(a)),
+            Instr::I8x16ExtractLaneS { lane } => {
+                step!(|a:v128| -> i32 v128::u8x16::lanes(a)[*lane as usize] as i8 as i32)
+            }
+            Instr::I8x16ExtractLaneU { lane } => {
+                step!(|a:v128| -> i32 v128::u8x16::lanes(a)[*lane as usize] as i32)
+            }
+            Instr::I16x8ExtractLaneS { lane } => {
+                step!(|a:v128| -> i32 v128::u16x8::lanes(a)[*lane as usize] as i16 as i32)
+            }
+            Instr::I16x8ExtractLaneU { lane } => {
+                step!(|a:v128| -> i32 v128::u16x8::lanes(a)[*lane as usize] as i32)
+            }
+            Instr::I32x4ExtractLane { lane } => {
+                step!(|a:v128| -> i32 v128::u32x4::lanes(a)[*lane as usize] as i32)
+            }
+            Instr::I64x2ExtractLane { lane } => {
+                step!(|a:v128| -> i64 v128::u64x2::lanes(a)[*lane as usize] as i64)
+            }
+            Instr::F32x4ExtractLane { lane } => {
+                step!(|a:v128| -> f32 v128::u32x4::lanes(a)[*lane as usize])
+            }
+            Instr::F64x2ExtractLane { lane } => {
+                step!(|a:v128| -> f64 v128::u64x2::lanes(a)[*lane as usize])
+            }
+            Instr::I8x16ReplaceLane { lane } => step!(|a: v128, b: i32| -> v128 {
+                let mut lanes = v128::u8x16::lanes(a);
+                lanes[*lane as usize] = b as u8;
+                v128::u8x16::from_lanes(lanes)
+            }),
+            Instr::I16x8ReplaceLane { lane } => step!(|a: v128, b: i32| -> v128 {
+                let mut lanes = v128::u16x8::lanes(a);
+                lanes[*lane as usize] = b as u16;
+                v128::u16x8::from_lanes(lanes)
+            }),
+            Instr::I32x4ReplaceLane { lane } => step!(|a: v128, b: i32| -> v128 {
+                let mut lanes = v128::u32x4::lanes(a);
+                lanes[*lane as usize] = b as u32;
+                v128::u32x4::from_lanes(lanes)
+            }),
+            Instr::I64x2ReplaceLane { lane } => step!(|a: v128, b: i64| -> v128 {
+                let mut lanes = v128::u64x2::lanes(a);
+                lanes[*lane as usize] = b as u64;
+                v128::u64x2::from_lanes(lanes)
+            }),
+            Instr::F32x4ReplaceLane { lane } => step!(|a: v128, b: f32| -> v128 {
+                let mut lanes = v128::u32x4::lanes(a);
+                lanes[*lane as usize] = b;
+                v128::u32x4::from_lanes(lanes)
+            }),
+            Instr::F64x2ReplaceLane { lane } => step!(|a: v128, b: f64| -> v128 {
+                let mut lanes = v128::u64x2::lanes(a);
+                lanes[*lane as usize] = b;
+                v128::u64x2::from_lanes(lanes)
+            }),
+            Instr::V8x16Swizzle => step!(|a:v128, b:v128| -> v128 v128::swizzle(a, b)),
+            Instr::V8x16Shuffle { index } => {
+                let lanes = bytecode.v128_constant(*index).to_le_bytes();
+                step!(|a:v128, b:v128| -> v128 v128::shuffle(a, b, &lanes))
+            }
+            Instr::I8x16Eq => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| a == b))
+            }
+            Instr::I8x16Ne => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| a != b))
+            }
+            Instr::I8x16LtS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| (a as i8) < b as i8))
+            }
+            Instr::I8x16LtU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| a < b))
+            }
+            Instr::I8x16GtS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| (a as i8) > b as i8))
+            }
+            Instr::I8x16GtU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| a > b))
+            }
+            Instr::I8x16LeS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| (a as i8) <= b as i8))
+            }
+            Instr::I8x16LeU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| a <= b))
+            }
+            Instr::I8x16GeS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| (a as i8) >= b as i8))
+            }
+            Instr::I8x16GeU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::cmp(a, b, |a, b| a >= b))
+            }
+            Instr::I16x8Eq => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| a == b))
+            }
+            Instr::I16x8Ne => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| a != b))
+            }
+            Instr::I16x8LtS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| (a as i16) < b as i16))
+            }
+            Instr::I16x8LtU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| a < b))
+            }
+            Instr::I16x8GtS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| (a as i16) > b as i16))
+            }
+            Instr::I16x8GtU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| a > b))
+            }
+            Instr::I16x8LeS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| {
+                    (a as i16) <= b as i16
+                }))
+            }
+            Instr::I16x8LeU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| a <= b))
+            }
+            Instr::I16x8GeS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| {
+                    (a as i16) >= b as i16
+                }))
+            }
+            Instr::I16x8GeU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::cmp(a, b, |a, b| a >= b))
+            }
+            Instr::I32x4Eq => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| a == b))
+            }
+            Instr::I32x4Ne => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| a != b))
+            }
+            Instr::I32x4LtS => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| (a as i32) < b as i32))
+            }
+            Instr::I32x4LtU => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| a < b))
+            }
+            Instr::I32x4GtS => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| (a as i32) > b as i32))
+            }
+            Instr::I32x4GtU => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| a > b))
+            }
+            Instr::I32x4LeS => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    (a as i32) <= b as i32
+                }))
+            }
+            Instr::I32x4LeU => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| a <= b))
+            }
+            Instr::I32x4GeS => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    (a as i32) >= b as i32
+                }))
+            }
+            Instr::I32x4GeU => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| a >= b))
+            }
+            Instr::F32x4Eq => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    wasm_f32::eq(a, b) != 0
+                }))
+            }
+            Instr::F32x4Ne => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    wasm_f32::ne(a, b) != 0
+                }))
+            }
+            Instr::F32x4Lt => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    wasm_f32::lt(a, b) != 0
+                }))
+            }
+            Instr::F32x4Gt => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    wasm_f32::gt(a, b) != 0
+                }))
+            }
+            Instr::F32x4Le => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    wasm_f32::le(a, b) != 0
+                }))
+            }
+            Instr::F32x4Ge => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::cmp(a, b, |a, b| {
+                    wasm_f32::ge(a, b) != 0
+                }))
+            }
+            Instr::F64x2Eq => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::cmp(a, b, |a, b| {
+                    wasm_f64::eq(a, b) != 0
+                }))
+            }
+            Instr::F64x2Ne => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::cmp(a, b, |a, b| {
+                    wasm_f64::ne(a, b) != 0
+                }))
+            }
+            Instr::F64x2Lt => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::cmp(a, b, |a, b| {
+                    wasm_f64::lt(a, b) != 0
+                }))
+            }
+            Instr::F64x2Gt => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::cmp(a, b, |a, b| {
+                    wasm_f64::gt(a, b) != 0
+                }))
+            }
+            Instr::F64x2Le => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::cmp(a, b, |a, b| {
+                    wasm_f64::le(a, b) != 0
+                }))
+            }
+            Instr::F64x2Ge => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::cmp(a, b, |a, b| {
+                    wasm_f64::ge(a, b) != 0
+                }))
+            }
+            Instr::V128Not => step!(|a:v128| -> v128 !a),
+            Instr::V128And => step!(|a:v128, b:v128| -> v128 a & b),
+            Instr::V128AndNot => step!(|a:v128, b:v128| -> v128 a & !b),
+            Instr::V128Or => step!(|a:v128, b:v128| -> v128 a | b),
+            Instr::V128Xor => step!(|a:v128, b:v128| -> v128 a ^ b),
+            Instr::V128Bitselect => {
+                let c = pop!(v128);
+                step!(|a:v128, b:v128| -> v128 v128::bitselect(a, b, c))
+            }
+            Instr::I8x16Abs => {
+                step!(|a:v128| -> v128 v128::u8x16::map(a, |a| (a as i8).wrapping_abs() as u8))
+            }
+            Instr::I8x16Neg => step!(|a:v128| -> v128 v128::u8x16::map(a, u8::wrapping_neg)),
+            Instr::I8x16AnyTrue => step!(|a:v128| -> i32 (a != 0) as i32),
+            Instr::I8x16AllTrue => step!(|a:v128| -> i32 v128::u8x16::all_true(a)),
+            Instr::I8x16Bitmask => step!(|a:v128| -> i32 v128::u8x16::bitmask(a)),
+            Instr::I8x16Shl => {
+                step!(|a:v128, b:i32| -> v128 v128::u8x16::map(a, |a| a << (b as u32 % 8)))
+            }
+            Instr::I8x16ShrS => {
+                step!(|a:v128, b:i32| -> v128 v128::u8x16::map(a, |a| {
+                    ((a as i8) >> (b as u32 % 8)) as u8
+                }))
+            }
+            Instr::I8x16ShrU => {
+                step!(|a:v128, b:i32| -> v128 v128::u8x16::map(a, |a| a >> (b as u32 % 8)))
+            }
+            Instr::I8x16Add => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, u8::wrapping_add))
+            }
+            Instr::I8x16AddSaturateS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, |a, b| {
+                    (a as i8).saturating_add(b as i8) as u8
+                }))
+            }
+            Instr::I8x16AddSaturateU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, u8::saturating_add))
+            }
+            Instr::I8x16Sub => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, u8::wrapping_sub))
+            }
+            Instr::I8x16SubSaturateS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, |a, b| {
+                    (a as i8).saturating_sub(b as i8) as u8
+                }))
+            }
+            Instr::I8x16SubSaturateU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, u8::saturating_sub))
+            }
+            Instr::I8x16MinS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, |a, b| {
+                    (a as i8).min(b as i8) as u8
+                }))
+            }
+            Instr::I8x16MinU => step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, u8::min)),
+            Instr::I8x16MaxS => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, |a, b| {
+                    (a as i8).max(b as i8) as u8
+                }))
+            }
+            Instr::I8x16MaxU => step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, u8::max)),
+            Instr::I8x16RoundingAverageU => {
+                step!(|a:v128, b:v128| -> v128 v128::u8x16::zip(a, b, |a, b| {
+                    (a as u16 + b as u16).div_ceil(2) as u8
+                }))
+            }
+            Instr::I16x8Abs => {
+                step!(|a:v128| -> v128 v128::u16x8::map(a, |a| (a as i16).wrapping_abs() as u16))
+            }
+            Instr::I16x8Neg => step!(|a:v128| -> v128 v128::u16x8::map(a, u16::wrapping_neg)),
+            Instr::I16x8AnyTrue => step!(|a:v128| -> i32 (a != 0) as i32),
+            Instr::I16x8AllTrue => step!(|a:v128| -> i32 v128::u16x8::all_true(a)),
+            Instr::I16x8Bitmask => step!(|a:v128| -> i32 v128::u16x8::bitmask(a)),
+            Instr::I16x8Shl => {
+                step!(|a:v128, b:i32| -> v128 v128::u16x8::map(a, |a| a << (b as u32 % 16)))
+            }
+            Instr::I16x8ShrS => {
+                step!(|a:v128, b:i32| -> v128 v128::u16x8::map(a, |a| {
+                    ((a as i16) >> (b as u32 % 16)) as u16
+                }))
+            }
+            Instr::I16x8ShrU => {
+                step!(|a:v128, b:i32| -> v128 v128::u16x8::map(a, |a| a >> (b as u32 % 16)))
+            }
+            Instr::I16x8Add => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::wrapping_add))
+            }
+            Instr::I16x8AddSaturateS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, |a, b| {
+                    (a as i16).saturating_add(b as i16) as u16
+                }))
+            }
+            Instr::I16x8AddSaturateU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::saturating_add))
+            }
+            Instr::I16x8Sub => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::wrapping_sub))
+            }
+            Instr::I16x8SubSaturateS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, |a, b| {
+                    (a as i16).saturating_sub(b as i16) as u16
+                }))
+            }
+            Instr::I16x8SubSaturateU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::saturating_sub))
+            }
+            Instr::I16x8Mul => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::wrapping_mul))
+            }
+            Instr::I16x8MinS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, |a, b| {
+                    (a as i16).min(b as i16) as u16
+                }))
+            }
+            Instr::I16x8MinU => step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::min)),
+            Instr::I16x8MaxS => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, |a, b| {
+                    (a as i16).max(b as i16) as u16
+                }))
+            }
+            Instr::I16x8MaxU => step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, u16::max)),
+            Instr::I16x8RoundingAverageU => {
+                step!(|a:v128, b:v128| -> v128 v128::u16x8::zip(a, b, |a, b| {
+                    (a as u32 + b as u32).div_ceil(2) as u16
+                }))
+            }
+            Instr::I32x4Abs => {
+                step!(|a:v128| -> v128 v128::u32x4::map(a, |a| (a as i32).wrapping_abs() as u32))
+            }
+            Instr::I32x4Neg => step!(|a:v128| -> v128 v128::u32x4::map(a, u32::wrapping_neg)),
+            Instr::I32x4AnyTrue => step!(|a:v128| -> i32 (a != 0) as i32),
+            Instr::I32x4AllTrue => step!(|a:v128| -> i32 v128::u32x4::all_true(a)),
+            Instr::I32x4Bitmask => step!(|a:v128| -> i32 v128::u32x4::bitmask(a)),
+            Instr::I32x4Shl => {
+                step!(|a:v128, b:i32| -> v128 v128::u32x4::map(a, |a| a << (b as u32 % 32)))
+            }
+            Instr::I32x4ShrS => {
+                step!(|a:v128, b:i32| -> v128 v128::u32x4::map(a, |a| {
+                    ((a as i32) >> (b as u32 % 32)) as u32
+                }))
+            }
+            Instr::I32x4ShrU => {
+                step!(|a:v128, b:i32| -> v128 v128::u32x4::map(a, |a| a >> (b as u32 % 32)))
+            }
+            Instr::I32x4Add => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, u32::wrapping_add))
+            }
+            Instr::I32x4Sub => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, u32::wrapping_sub))
+            }
+            Instr::I32x4Mul => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, u32::wrapping_mul))
+            }
+            Instr::I32x4MinS => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, |a, b| {
+                    (a as i32).min(b as i32) as u32
+                }))
+            }
+            Instr::I32x4MinU => step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, u32::min)),
+            Instr::I32x4MaxS => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, |a, b| {
+                    (a as i32).max(b as i32) as u32
+                }))
+            }
+            Instr::I32x4MaxU => step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, u32::max)),
+            Instr::I64x2Neg => step!(|a:v128| -> v128 v128::u64x2::map(a, u64::wrapping_neg)),
+            Instr::I64x2Shl => {
+                step!(|a:v128, b:i32| -> v128 v128::u64x2::map(a, |a| a << (b as u32 % 64)))
+            }
+            Instr::I64x2ShrS => {
+                step!(|a:v128, b:i32| -> v128 v128::u64x2::map(a, |a| {
+                    ((a as i64) >> (b as u32 % 64)) as u64
+                }))
+            }
+            Instr::I64x2ShrU => {
+                step!(|a:v128, b:i32| -> v128 v128::u64x2::map(a, |a| a >> (b as u32 % 64)))
+            }
+            Instr::I64x2Add => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, u64::wrapping_add))
+            }
+            Instr::I64x2Sub => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, u64::wrapping_sub))
+            }
+            Instr::I64x2Mul => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, u64::wrapping_mul))
+            }
+            Instr::F32x4Abs => step!(|a:v128| -> v128 v128::u32x4::map(a, wasm_f32::abs)),
+            Instr::F32x4Neg => step!(|a:v128| -> v128 v128::u32x4::map(a, wasm_f32::neg)),
+            Instr::F32x4Sqrt => step!(|a:v128| -> v128 v128::u32x4::map(a, wasm_f32::sqrt)),
+            Instr::F32x4Add => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, wasm_f32::add))
+            }
+            Instr::F32x4Sub => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, wasm_f32::sub))
+            }
+            Instr::F32x4Mul => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, wasm_f32::mul))
+            }
+            Instr::F32x4Div => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, wasm_f32::div))
+            }
+            Instr::F32x4Min => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, wasm_f32::min))
+            }
+            Instr::F32x4Max => {
+                step!(|a:v128, b:v128| -> v128 v128::u32x4::zip(a, b, wasm_f32::max))
+            }
+            Instr::F64x2Abs => step!(|a:v128| -> v128 v128::u64x2::map(a, wasm_f64::abs)),
+            Instr::F64x2Neg => step!(|a:v128| -> v128 v128::u64x2::map(a, wasm_f64::neg)),
+            Instr::F64x2Sqrt => step!(|a:v128| -> v128 v128::u64x2::map(a, wasm_f64::sqrt)),
+            Instr::F64x2Add => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, wasm_f64::add))
+            }
+            Instr::F64x2Sub => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, wasm_f64::sub))
+            }
+            Instr::F64x2Mul => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, wasm_f64::mul))
+            }
+            Instr::F64x2Div => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, wasm_f64::div))
+            }
+            Instr::F64x2Min => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, wasm_f64::min))
+            }
+            Instr::F64x2Max => {
+                step!(|a:v128, b:v128| -> v128 v128::u64x2::zip(a, b, wasm_f64::max))
+            }
+            Instr::I32x4TruncSatF32x4S => {
+                step!(|a:v128| -> v128 v128::u32x4::map(a, |a| wasm_f32::trunc_i32_sat(a) as u32))
+            }
+            Instr::I32x4TruncSatF32x4U => {
+                step!(|a:v128| -> v128 v128::u32x4::map(a, |a| wasm_f32::trunc_u32_sat(a) as u32))
+            }
+            Instr::F32x4ConvertI32x4S => {
+                step!(|a:v128| -> v128 v128::u32x4::map(a, |a| wasm_f32::from_i32(a as i32)))
+            }
+            Instr::F32x4ConvertI32x4U => {
+                step!(|a:v128| -> v128 v128::u32x4::map(a, |a| wasm_f32::from_u32(a as i32)))
+            }
+            Instr::I8x16NarrowI16x8S => {
+                step!(|a:v128, b:v128| -> v128 v128::i8x16_narrow_i16x8(a, b, true))
+            }
+            Instr::I8x16NarrowI16x8U => {
+                step!(|a:v128, b:v128| -> v128 v128::i8x16_narrow_i16x8(a, b, false))
+            }
+            Instr::I16x8NarrowI32x4S => {
+                step!(|a:v128, b:v128| -> v128 v128::i16x8_narrow_i32x4(a, b, true))
+            }
+            Instr::I16x8NarrowI32x4U => {
+                step!(|a:v128, b:v128| -> v128 v128::i16x8_narrow_i32x4(a, b, false))
+            }
+            Instr::I16x8WidenLowI8x16S => {
+                step!(|a:v128| -> v128 v128::i16x8_widen_i8x16(a, false, true))
+            }
+            Instr::I16x8WidenHighI8x16S => {
+                step!(|a:v128| -> v128 v128::i16x8_widen_i8x16(a, true, true))
+            }
+            Instr::I16x8WidenLowI8x16U => {
+                step!(|a:v128| -> v128 v128::i16x8_widen_i8x16(a, false, false))
+            }
+            Instr::I16x8WidenHighI8x16U => {
+                step!(|a:v128| -> v128 v128::i16x8_widen_i8x16(a, true, false))
+            }
+            Instr::I32x4WidenLowI16x8S => {
+                step!(|a:v128| -> v128 v128::i32x4_widen_i16x8(a, false, true))
+            }
+            Instr::I32x4WidenHighI16x8S => {
+                step!(|a:v128| -> v128 v128::i32x4_widen_i16x8(a, true, true))
+            }
+            Instr::I32x4WidenLowI16x8U => {
+                step!(|a:v128| -> v128 v128::i32x4_widen_i16x8(a, false, false))
+            }
+            Instr::I32x4WidenHighI16x8U => {
+                step!(|a:v128| -> v128 v128::i32x4_widen_i16x8(a, true, false))
+            }
+            Instr::LocalGetLocalGetI32Add { a, b } => {
+                // The stack is checked as if both locals were pushed.
+                if !stack.has_room(2) {
+                    trap!(TrapKind::StackOverflow);
+                }
+                let val = stack.local(*a).i32().wrapping_add(stack.local(*b).i32());
+                stack.push(Slot::from_i32(val));
+                i += 2;
+            }
+            Instr::I32ConstI32Add { value } => {
+                // The stack is checked as if the constant was pushed.
+                if stack.is_full() {
+                    trap!(TrapKind::StackOverflow);
+                }
+                let a = stack.last_mut();
+                *a = Slot::from_i32(a.i32().wrapping_add(*value));
+                i += 1;
+            }
+            Instr::LocalGetI32Load { local_index } => {
+                if stack.is_full() {
+                    trap!(TrapKind::StackOverflow);
+                }
+                let offset = stack.local(*local_index).address();
+                i += 1;
+                match &instrs[i] {
+                    Instr::I32Load { memarg } => load!(memarg, offset; i32),
+                    _ => unreachable!(),
+                }
+            }
+            Instr::I32EqzBrIf { branch } => {
+                let c = pop!(i32);
+                i += 1;
+                if c == 0 {
+                    branch!(branch);
+                }
+            }
+            Instr::I32EqBrIf { branch } => compare_br_if!(branch; |a, b| a == b),
+            Instr::I32NeBrIf { branch } => compare_br_if!(branch; |a, b| a != b),
+            Instr::I32LtSBrIf { branch } => compare_br_if!(branch; |a, b| a < b),
+            Instr::I32LtUBrIf { branch } => compare_br_if!(branch; |a, b| (a as u32) < b as u32),
+            Instr::I32GtSBrIf { branch } => compare_br_if!(branch; |a, b| a > b),
+            Instr::I32GtUBrIf { branch } => compare_br_if!(branch; |a, b| a as u32 > b as u32),
+            Instr::I32LeSBrIf { branch } => compare_br_if!(branch; |a, b| a <= b),
+            Instr::I32LeUBrIf { branch } => compare_br_if!(branch; |a, b| a as u32 <= b as u32),
+            Instr::I32GeSBrIf { branch } => compare_br_if!(branch; |a, b| a >= b),
+            Instr::I32GeUBrIf { branch } => compare_br_if!(branch; |a, b| a as u32 >= b as u32),
         }
         i += 1;
     }
-    stack.compress_stack_items(0, stack.len() - return_arity);
-    Ok(())
-}
-
-pub(crate) fn eval_const<'a>(context: &'a (dyn EvalContext + 'a), source: &dyn EvalSource) -> Val {
-    const MAX_CONST_EVAL_STACK: usize = 10;
-    let mut stack = vec![Default::default(); MAX_CONST_EVAL_STACK];
-    let result = eval(context, source, 1, &mut stack, 0);
-    match result {
-        Ok(()) => stack.into_iter().next().unwrap(),
-        Err(_) => {
-            panic!("trap duing eval_const");
-        }
-    }
+    Ok(Exit::End)
 }
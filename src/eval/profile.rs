@@ -0,0 +1,112 @@
+// Counts of the instruction sequences executed in `EvalMode::Profile`, to
+// find the sequences worth fusing into superinstructions.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem::{discriminant, Discriminant};
+
+use crate::eval::Instr;
+
+/// The length of the longest counted sequence.
+const MAX_SEQUENCE_LEN: usize = 3;
+
+/// The hasher of the instruction kinds: the profile is updated for every
+/// instruction, and the default hasher dominated the run time.
+#[derive(Default)]
+struct KindHasher(u64);
+
+impl Hasher for KindHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn write_isize(&mut self, n: isize) {
+        self.write_u64(n as u64);
+    }
+}
+
+type Counts =
+    HashMap<Box<[Discriminant<Instr>]>, (u64, Box<[&'static str]>), BuildHasherDefault<KindHasher>>;
+
+/// An executed sequence of the operators, by their names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSequence {
+    pub instrs: Box<[&'static str]>,
+    pub count: u64,
+}
+
+/// The sequences of two and three instructions executed one after another,
+/// without branches between them. The sequences are counted by the kinds
+/// of the instructions, and their names are kept for the report.
+#[derive(Default)]
+pub struct SequenceProfile {
+    counts: RefCell<Counts>,
+}
+
+/// The instructions executed before the current one.
+#[derive(Default)]
+pub(crate) struct Trace {
+    next_pc: usize,
+    kinds: Vec<Discriminant<Instr>>,
+    instrs: Vec<Instr>,
+}
+
+impl SequenceProfile {
+    pub(crate) fn record(&self, trace: &mut Trace, pc: usize, instr: &Instr) {
+        if trace.next_pc != pc {
+            trace.kinds.clear();
+            trace.instrs.clear();
+        } else if trace.kinds.len() == MAX_SEQUENCE_LEN {
+            trace.kinds.remove(0);
+            trace.instrs.remove(0);
+        }
+        trace.kinds.push(discriminant(instr));
+        trace.instrs.push(instr.clone());
+        trace.next_pc = pc + 1;
+
+        let mut counts = self.counts.borrow_mut();
+        for start in 0..trace.kinds.len().saturating_sub(1) {
+            match counts.get_mut(&trace.kinds[start..]) {
+                Some((count, _)) => *count += 1,
+                None => {
+                    let names = trace.instrs[start..].iter().map(Instr::name).collect();
+                    counts.insert(trace.kinds[start..].into(), (1, names));
+                }
+            }
+        }
+    }
+
+    /// Returns the counted sequences, the most executed first.
+    pub fn hot_sequences(&self) -> Vec<HotSequence> {
+        let mut sequences = self
+            .counts
+            .borrow()
+            .iter()
+            .map(|(_, (count, names))| HotSequence {
+                instrs: names.clone(),
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        sequences.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.instrs.cmp(&b.instrs)));
+        sequences
+    }
+
+    pub fn clear(&self) {
+        self.counts.borrow_mut().clear();
+    }
+}
@@ -0,0 +1,1024 @@
+// Register form of the function bodies. The operand stack heights are known
+// for every instruction, so the operand stack slots are registers of the frame
+// placed after the locals, and the instructions name the registers they read
+// and write. The values of `local.get` are not copied to the operand slots
+// until it is needed: the instructions read the locals directly, and the
+// result written to an operand slot before `local.set` is written to the
+// local instead.
+//
+// The instructions without the register form are executed on the stack,
+// starting at the height they have in the stack bytecode.
+
+use wasmparser::MemoryImmediate;
+
+use crate::eval::{
+    call_native, indirect_func, native_depth, run, Branch, BytecodeCache, EvalContext, EvalStack,
+    Exit, FrameState, Instr, NATIVE_CALLS_DEPTH,
+};
+use crate::values::{collect_roots, Slot, Trap, TrapKind};
+
+use super::f32 as wasm_f32;
+use super::f64 as wasm_f64;
+
+/// Branch that moves `len` values from the `src` registers to the `dst`
+/// ones before continuing at `pc`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RegBranch {
+    pc: u32,
+    dst: u32,
+    src: u32,
+    len: u32,
+}
+
+enum Operation {
+    Unary(fn(u32, u32) -> RegInstr),
+    Binary(fn(u32, u32, u32) -> RegInstr),
+    Load(MemoryImmediate, fn(u32, u32, u32) -> RegInstr),
+    Store(MemoryImmediate, fn(u32, u32, u32) -> RegInstr),
+}
+
+macro_rules! register_instructions {
+    (
+        unary { $($unary:ident,)* }
+        binary { $($binary:ident,)* }
+        load { $($load:ident,)* }
+        store { $($store:ident,)* }
+    ) => {
+        #[derive(Debug, Clone)]
+        pub(crate) enum RegInstr {
+            Copy { dst: u32, src: u32 },
+            I32Const { dst: u32, value: i32 },
+            /// Copies the value from the `constants`.
+            Const { dst: u32, index: u32 },
+            Jump { pc: u32 },
+            JumpIf { cond: u32, pc: u32 },
+            JumpIfZero { cond: u32, pc: u32 },
+            Br { branch: u32 },
+            BrIf { cond: u32, branch: u32 },
+            /// Selects one of the `len + 1` branches, starting at `targets`;
+            /// the last one is the default.
+            BrTable { index: u32, targets: u32, len: u32 },
+            /// Moves the results from the `src` registers to the frame start.
+            Return { src: u32 },
+            /// Calls the function with the arguments in the registers below
+            /// `sp`, the results replace them.
+            Call { function_index: u32, sp: u32 },
+            /// Same as `Call`, for the table entry at the `func` register
+            /// with the type and the table of the `indirect` entry.
+            CallIndirect { func: u32, indirect: u32, sp: u32 },
+            /// Runs the bytecode instructions `pc..end` on the stack that has
+            /// `sp` values.
+            Stack { pc: u32, end: u32, sp: u32 },
+            $($unary { dst: u32, a: u32 },)*
+            $($binary { dst: u32, a: u32, b: u32 },)*
+            $($load { dst: u32, addr: u32, memarg: u32 },)*
+            $($store { addr: u32, val: u32, memarg: u32 },)*
+        }
+
+        impl RegInstr {
+            /// Returns the register of the instruction result.
+            fn result_mut(&mut self) -> Option<&mut u32> {
+                match self {
+                    RegInstr::I32Const { dst, .. } | RegInstr::Const { dst, .. } => Some(dst),
+                    $(RegInstr::$unary { dst, .. } => Some(dst),)*
+                    $(RegInstr::$binary { dst, .. } => Some(dst),)*
+                    $(RegInstr::$load { dst, .. } => Some(dst),)*
+                    _ => None,
+                }
+            }
+        }
+
+        /// Returns the register form of the instructions that only access
+        /// their operands and the memory.
+        fn operation(instr: &Instr) -> Option<Operation> {
+            Some(match *instr {
+                $(Instr::$unary => Operation::Unary(|dst, a| RegInstr::$unary { dst, a }),)*
+                $(Instr::$binary => {
+                    Operation::Binary(|dst, a, b| RegInstr::$binary { dst, a, b })
+                })*
+                $(Instr::$load { memarg } => Operation::Load(memarg, |dst, addr, memarg| {
+                    RegInstr::$load { dst, addr, memarg }
+                }),)*
+                $(Instr::$store { memarg } => Operation::Store(memarg, |addr, val, memarg| {
+                    RegInstr::$store { addr, val, memarg }
+                }),)*
+                _ => return None,
+            })
+        }
+    };
+}
+
+register_instructions! {
+    unary {
+        I32Eqz,
+        I32Clz,
+        I32Ctz,
+        I32Popcnt,
+        I64Eqz,
+        I64Clz,
+        I64Ctz,
+        I64Popcnt,
+        F32Abs,
+        F32Neg,
+        F32Sqrt,
+        F64Abs,
+        F64Neg,
+        F64Sqrt,
+        I32WrapI64,
+        I64ExtendI32S,
+        I64ExtendI32U,
+        F32ConvertI32S,
+        F64ConvertI32S,
+        F64ConvertI64S,
+        F32DemoteF64,
+        F64PromoteF32,
+        I32Extend8S,
+        I32Extend16S,
+        I64Extend8S,
+        I64Extend16S,
+        I64Extend32S,
+    }
+    binary {
+        I32Eq,
+        I32Ne,
+        I32LtS,
+        I32LtU,
+        I32GtS,
+        I32GtU,
+        I32LeS,
+        I32LeU,
+        I32GeS,
+        I32GeU,
+        I64Eq,
+        I64Ne,
+        I64LtS,
+        I64LtU,
+        I64GtS,
+        I64GtU,
+        I64LeS,
+        I64LeU,
+        I64GeS,
+        I64GeU,
+        F32Eq,
+        F32Ne,
+        F32Lt,
+        F32Gt,
+        F32Le,
+        F32Ge,
+        F64Eq,
+        F64Ne,
+        F64Lt,
+        F64Gt,
+        F64Le,
+        F64Ge,
+        I32Add,
+        I32Sub,
+        I32Mul,
+        I32DivS,
+        I32DivU,
+        I32RemS,
+        I32RemU,
+        I32And,
+        I32Or,
+        I32Xor,
+        I32Shl,
+        I32ShrS,
+        I32ShrU,
+        I32Rotl,
+        I32Rotr,
+        I64Add,
+        I64Sub,
+        I64Mul,
+        I64DivS,
+        I64DivU,
+        I64RemS,
+        I64RemU,
+        I64And,
+        I64Or,
+        I64Xor,
+        I64Shl,
+        I64ShrS,
+        I64ShrU,
+        I64Rotl,
+        I64Rotr,
+        F32Add,
+        F32Sub,
+        F32Mul,
+        F32Div,
+        F32Min,
+        F32Max,
+        F64Add,
+        F64Sub,
+        F64Mul,
+        F64Div,
+        F64Min,
+        F64Max,
+    }
+    load {
+        I32Load,
+        I64Load,
+        F32Load,
+        F64Load,
+        I32Load8S,
+        I32Load8U,
+        I32Load16S,
+        I32Load16U,
+        I64Load8S,
+        I64Load8U,
+        I64Load16S,
+        I64Load16U,
+        I64Load32S,
+        I64Load32U,
+    }
+    store {
+        I32Store,
+        I64Store,
+        F32Store,
+        F64Store,
+        I32Store8,
+        I32Store16,
+        I64Store8,
+        I64Store16,
+        I64Store32,
+    }
+}
+
+pub(crate) struct RegisterCode {
+    instrs: Box<[RegInstr]>,
+    /// The bytecode instructions the register instructions come from.
+    positions: Box<[u32]>,
+    branches: Box<[RegBranch]>,
+    constants: Box<[Slot]>,
+    memargs: Box<[MemoryImmediate]>,
+    /// The type and the table indexes of the `call_indirect` instructions.
+    indirect: Box<[(u32, u32)]>,
+    /// The number of the locals and the operand stack slots.
+    frame_size: usize,
+}
+
+struct Builder<'a> {
+    bytecode: &'a BytecodeCache,
+    locals: u32,
+    instrs: Vec<RegInstr>,
+    positions: Vec<u32>,
+    branches: Vec<RegBranch>,
+    constants: Vec<Slot>,
+    memargs: Vec<MemoryImmediate>,
+    indirect: Vec<(u32, u32)>,
+    /// The operand stack: an operand is the local it was read from, until
+    /// the value is copied to its slot.
+    operands: Vec<Option<u32>>,
+    /// The operand slot written by the last instruction.
+    last_result: Option<u32>,
+    pc: usize,
+}
+
+impl<'a> Builder<'a> {
+    fn slot(&self, height: usize) -> u32 {
+        self.locals + height as u32
+    }
+
+    fn emit(&mut self, instr: RegInstr) {
+        self.instrs.push(instr);
+        self.positions.push(self.pc as u32);
+        self.last_result = None;
+    }
+
+    fn emit_result(&mut self, instr: RegInstr, dst: u32) {
+        self.emit(instr);
+        self.last_result = Some(dst);
+    }
+
+    fn pop(&mut self) -> u32 {
+        let height = self.operands.len() - 1;
+        let operand = self.operands.pop().unwrap();
+        operand.unwrap_or_else(|| self.slot(height))
+    }
+
+    fn push(&mut self) -> u32 {
+        let dst = self.slot(self.operands.len());
+        self.operands.push(None);
+        dst
+    }
+
+    /// Copies the locals matching the predicate to their operand slots.
+    fn materialize(&mut self, f: impl Fn(u32) -> bool) {
+        for height in 0..self.operands.len() {
+            match self.operands[height] {
+                Some(local) if f(local) => {
+                    let dst = self.slot(height);
+                    self.emit(RegInstr::Copy { dst, src: local });
+                    self.operands[height] = None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Leaves the operands in their slots, as the stack code expects them
+    /// at the branch targets.
+    fn materialize_all(&mut self) {
+        self.materialize(|_| true);
+        self.last_result = None;
+    }
+
+    fn reset(&mut self, height: usize) {
+        self.operands.clear();
+        self.operands.resize(height, None);
+        self.last_result = None;
+    }
+
+    fn branch(&self, branch: &Branch) -> RegBranch {
+        let src = self.slot(self.operands.len()) - branch.keep;
+        RegBranch {
+            pc: branch.pc,
+            dst: src - branch.drop,
+            src,
+            len: if branch.drop == 0 { 0 } else { branch.keep },
+        }
+    }
+
+    fn set_local(&mut self, local: u32) {
+        let value = self.operands.pop().unwrap();
+        if value == Some(local) {
+            return;
+        }
+        let src = value.unwrap_or_else(|| self.slot(self.operands.len()));
+        let read = self.operands.contains(&Some(local));
+        if !read && self.last_result == Some(src) {
+            *self.instrs.last_mut().unwrap().result_mut().unwrap() = local;
+            self.last_result = None;
+            return;
+        }
+        self.materialize(|operand| operand == local);
+        self.emit(RegInstr::Copy { dst: local, src });
+    }
+
+    fn stack(&mut self) {
+        self.materialize_all();
+        let pc = self.pc as u32;
+        match self.instrs.last_mut() {
+            Some(RegInstr::Stack { end, .. }) if *end == pc => *end += 1,
+            _ => {
+                let sp = self.slot(self.operands.len());
+                self.emit(RegInstr::Stack {
+                    pc,
+                    end: pc + 1,
+                    sp,
+                })
+            }
+        }
+        self.reset(self.bytecode.height(self.pc + 1));
+    }
+}
+
+impl RegisterCode {
+    pub fn new(bytecode: &BytecodeCache, locals: usize, return_arity: usize) -> RegisterCode {
+        let instrs = bytecode.instrs();
+        let mut b = Builder {
+            bytecode,
+            locals: locals as u32,
+            instrs: Vec::new(),
+            positions: Vec::new(),
+            branches: Vec::new(),
+            constants: Vec::new(),
+            memargs: Vec::new(),
+            indirect: Vec::new(),
+            operands: Vec::new(),
+            last_result: None,
+            pc: 0,
+        };
+        let mut labels = Vec::with_capacity(instrs.len() + 1);
+        let mut max_height = 0;
+        // The depth of the blocks in the unreachable code.
+        let mut unreachable = None;
+        for (pc, instr) in instrs.iter().enumerate() {
+            // The superinstructions fuse only instructions that have the
+            // register form, so the stack ranges never split them.
+            let instr = &instr.unfused();
+            labels.push(b.instrs.len() as u32);
+            b.pc = pc;
+            if let Some(depth) = unreachable {
+                unreachable = match *instr {
+                    Instr::Block | Instr::Loop | Instr::If { .. } => Some(depth + 1),
+                    Instr::Else { .. } | Instr::End if depth == 0 => {
+                        b.reset(bytecode.height(pc + 1));
+                        None
+                    }
+                    Instr::End => Some(depth - 1),
+                    _ => Some(depth),
+                };
+                continue;
+            }
+            debug_assert_eq!(b.operands.len(), bytecode.height(pc));
+            max_height = max_height.max(bytecode.height(pc));
+            match *instr {
+                Instr::Nop | Instr::Block => b.last_result = None,
+                Instr::Loop | Instr::End => {
+                    b.materialize_all();
+                    b.reset(bytecode.height(pc + 1));
+                }
+                Instr::If { else_pc } => {
+                    let cond = b.pop();
+                    b.materialize_all();
+                    b.emit(RegInstr::JumpIfZero { cond, pc: else_pc });
+                }
+                Instr::Else { end_pc } => {
+                    b.materialize_all();
+                    b.emit(RegInstr::Jump { pc: end_pc });
+                    b.reset(bytecode.height(pc + 1));
+                }
+                Instr::Br { ref branch } => {
+                    b.materialize_all();
+                    let branch = b.branch(branch);
+                    if branch.len == 0 {
+                        b.emit(RegInstr::Jump { pc: branch.pc });
+                    } else {
+                        b.branches.push(branch);
+                        let branch = b.branches.len() as u32 - 1;
+                        b.emit(RegInstr::Br { branch });
+                    }
+                    unreachable = Some(0);
+                }
+                Instr::BrIf { ref branch } => {
+                    let cond = b.pop();
+                    b.materialize_all();
+                    let branch = b.branch(branch);
+                    if branch.len == 0 {
+                        b.emit(RegInstr::JumpIf {
+                            cond,
+                            pc: branch.pc,
+                        });
+                    } else {
+                        b.branches.push(branch);
+                        let branch = b.branches.len() as u32 - 1;
+                        b.emit(RegInstr::BrIf { cond, branch });
+                    }
+                }
+                Instr::BrTable { targets, len } => {
+                    let index = b.pop();
+                    b.materialize_all();
+                    let start = b.branches.len() as u32;
+                    for target in targets..=targets + len {
+                        let branch = b.branch(bytecode.br_table_entry(target));
+                        b.branches.push(branch);
+                    }
+                    b.emit(RegInstr::BrTable {
+                        index,
+                        targets: start,
+                        len,
+                    });
+                    unreachable = Some(0);
+                }
+                Instr::Return => {
+                    b.materialize_all();
+                    let src = b.slot(b.operands.len() - return_arity);
+                    b.emit(RegInstr::Return { src });
+                    unreachable = Some(0);
+                }
+                Instr::Drop => {
+                    b.operands.pop();
+                }
+                Instr::LocalGet { local_index } => b.operands.push(Some(local_index)),
+                Instr::LocalSet { local_index } => b.set_local(local_index),
+                Instr::LocalTee { local_index } => {
+                    b.set_local(local_index);
+                    b.operands.push(Some(local_index));
+                }
+                Instr::I32Const { value } => {
+                    let dst = b.push();
+                    b.emit_result(RegInstr::I32Const { dst, value }, dst);
+                }
+                Instr::I64Const { .. } | Instr::F32Const { .. } | Instr::F64Const { .. } => {
+                    let index = b.constants.len() as u32;
+                    b.constants.push(match *instr {
+                        Instr::I64Const { value } => Slot::from_i64(value),
+                        Instr::F32Const { value } => Slot::from_f32(value.bits()),
+                        Instr::F64Const { value } => Slot::from_f64(value.bits()),
+                        _ => unreachable!(),
+                    });
+                    let dst = b.push();
+                    b.emit_result(RegInstr::Const { dst, index }, dst);
+                }
+                Instr::Call { function_index } => {
+                    b.materialize_all();
+                    let sp = b.slot(b.operands.len());
+                    b.emit(RegInstr::Call { function_index, sp });
+                    b.reset(bytecode.height(pc + 1));
+                }
+                Instr::CallIndirect { index, table_index } => {
+                    let func = b.pop();
+                    b.materialize_all();
+                    let sp = b.slot(b.operands.len());
+                    b.indirect.push((index, table_index));
+                    let indirect = b.indirect.len() as u32 - 1;
+                    b.emit(RegInstr::CallIndirect { func, indirect, sp });
+                    b.reset(bytecode.height(pc + 1));
+                }
+                Instr::Unreachable
+                | Instr::ReturnCall { .. }
+                | Instr::ReturnCallIndirect { .. } => {
+                    b.stack();
+                    unreachable = Some(0);
+                }
+                _ => match operation(instr) {
+                    Some(Operation::Unary(f)) => {
+                        let a = b.pop();
+                        let dst = b.push();
+                        b.emit_result(f(dst, a), dst);
+                    }
+                    Some(Operation::Binary(f)) => {
+                        let rhs = b.pop();
+                        let lhs = b.pop();
+                        let dst = b.push();
+                        b.emit_result(f(dst, lhs, rhs), dst);
+                    }
+                    Some(Operation::Load(memarg, f)) => {
+                        let addr = b.pop();
+                        let dst = b.push();
+                        b.memargs.push(memarg);
+                        let memarg = b.memargs.len() as u32 - 1;
+                        b.emit_result(f(dst, addr, memarg), dst);
+                    }
+                    Some(Operation::Store(memarg, f)) => {
+                        let val = b.pop();
+                        let addr = b.pop();
+                        b.memargs.push(memarg);
+                        let memarg = b.memargs.len() as u32 - 1;
+                        b.emit(f(addr, val, memarg));
+                    }
+                    None => b.stack(),
+                },
+            }
+        }
+        // The branches to the function block end here.
+        labels.push(b.instrs.len() as u32);
+        b.pc = instrs.len();
+        let src = b.slot(bytecode.height(instrs.len()) - return_arity);
+        b.emit(RegInstr::Return { src });
+
+        for instr in b.instrs.iter_mut() {
+            match instr {
+                RegInstr::Jump { pc }
+                | RegInstr::JumpIf { pc, .. }
+                | RegInstr::JumpIfZero { pc, .. } => *pc = labels[*pc as usize],
+                _ => (),
+            }
+        }
+        for branch in b.branches.iter_mut() {
+            branch.pc = labels[branch.pc as usize];
+        }
+
+        RegisterCode {
+            instrs: b.instrs.into_boxed_slice(),
+            positions: b.positions.into_boxed_slice(),
+            branches: b.branches.into_boxed_slice(),
+            constants: b.constants.into_boxed_slice(),
+            memargs: b.memargs.into_boxed_slice(),
+            indirect: b.indirect.into_boxed_slice(),
+            frame_size: locals + max_height,
+        }
+    }
+}
+
+/// Evaluates the register code of the function from the `state`, the same
+/// way as `resume`. The frame starts with the initialized locals.
+pub(super) fn eval(
+    context: &dyn EvalContext,
+    bytecode: &BytecodeCache,
+    code: &RegisterCode,
+    return_arity: usize,
+    frame: &mut [Slot],
+    state: &mut FrameState,
+) -> Result<Exit, Trap> {
+    let instrs = &code.instrs;
+    let mut i = state.register_pc;
+    let memory_cache = &mut state.memory_cache;
+
+    macro_rules! trap {
+        ($kind:expr) => {{
+            let pc = code.positions[i] as usize;
+            return Err(Trap::new($kind, bytecode.position(pc)));
+        }};
+    }
+    macro_rules! get {
+        ($r:expr; $ty:ident) => {
+            frame[*$r as usize].$ty()
+        };
+    }
+    macro_rules! set {
+        ($r:expr; i32; $e:expr) => {
+            frame[*$r as usize] = Slot::from_i32($e)
+        };
+        ($r:expr; i64; $e:expr) => {
+            frame[*$r as usize] = Slot::from_i64($e)
+        };
+        ($r:expr; f32; $e:expr) => {
+            frame[*$r as usize] = Slot::from_f32($e)
+        };
+        ($r:expr; f64; $e:expr) => {
+            frame[*$r as usize] = Slot::from_f64($e)
+        };
+    }
+    macro_rules! rust_ty {
+        (i32) => {
+            i32
+        };
+        (i64) => {
+            i64
+        };
+        (f32) => {
+            u32
+        };
+        (f64) => {
+            u64
+        };
+    }
+    macro_rules! op {
+        ($dst:ident; |$a:ident: $ty_a:ident| -> $ty:ident $e:expr) => {{
+            let $a = get!($a; $ty_a);
+            set!($dst; $ty; $e);
+        }};
+        ($dst:ident; |$a:ident: $ty_a:ident, $b:ident: $ty_b:ident| -> $ty:ident $e:expr) => {{
+            let $a = get!($a; $ty_a);
+            let $b = get!($b; $ty_b);
+            set!($dst; $ty; $e);
+        }};
+    }
+    macro_rules! memory {
+        ($index:expr) => {{
+            let index = $index;
+            if memory_cache
+                .as_ref()
+                .map_or(true, |(cached, _)| *cached != index)
+            {
+                *memory_cache = Some((index, context.get_memory(index)));
+            }
+            &memory_cache.as_ref().unwrap().1
+        }};
+    }
+    macro_rules! address {
+        ($r:expr) => {
+            // Validated to be `i64` for memory64 memories, and `i32` otherwise.
+            frame[*$r as usize].address()
+        };
+    }
+    macro_rules! load {
+        ($dst:ident, $addr:ident, $memarg:ident; $ty:ident as $tt:ident) => {{
+            let memarg = &code.memargs[*$memarg as usize];
+            let offset = address!($addr);
+            let size = std::mem::size_of::<$tt>() as u32;
+            let ptr = memory!(memarg.memory).content_ptr(memarg, offset, size);
+            if ptr.is_null() {
+                trap!(TrapKind::OutOfBounds);
+            }
+            let val = unsafe { (ptr as *const $tt).read_unaligned() } as rust_ty!($ty);
+            set!($dst; $ty; val);
+        }};
+    }
+    macro_rules! store {
+        ($addr:ident, $val:ident, $memarg:ident; $ty:ident as $tt:ident) => {{
+            let memarg = &code.memargs[*$memarg as usize];
+            let val = get!($val; $ty) as $tt;
+            let offset = address!($addr);
+            let size = std::mem::size_of::<$tt>() as u32;
+            let ptr = memory!(memarg.memory).content_ptr_mut(memarg, offset, size);
+            if ptr.is_null() {
+                trap!(TrapKind::OutOfBounds);
+            }
+            unsafe {
+                (ptr as *mut $tt).write_unaligned(val);
+            }
+        }};
+    }
+    macro_rules! jump {
+        ($pc:expr) => {{
+            let pc = $pc as usize;
+            // Only loops are entered from below.
+            if pc <= i {
+                if context.take_interrupt() {
+                    trap!(TrapKind::Interrupted);
+                }
+                collect_roots(&frame[..code.frame_size]);
+            }
+            i = pc;
+            continue;
+        }};
+    }
+    macro_rules! branch {
+        ($branch:expr) => {{
+            let RegBranch { pc, dst, src, len } = *$branch;
+            let (dst, src) = (dst as usize, src as usize);
+            frame.copy_within(src..src + len as usize, dst);
+            jump!(pc)
+        }};
+    }
+
+    macro_rules! call {
+        ($f:expr, $sp:expr) => {{
+            let f = $f;
+            if context.take_interrupt() {
+                trap!(TrapKind::Interrupted);
+            }
+            let sp = *$sp as usize;
+            let (params_len, returns_len) = (f.ty().params.len(), f.ty().returns.len());
+            if sp - params_len + returns_len > frame.len() {
+                trap!(TrapKind::StackOverflow);
+            }
+            if native_depth() >= NATIVE_CALLS_DEPTH {
+                // The evaluation continues at `register_pc` after the call.
+                state.register_pc = i + 1;
+                state.sp = sp;
+                return Ok(Exit::Call(f, 0));
+            }
+            call_native(&f, &mut frame[sp - params_len..])?;
+        }};
+    }
+
+    if frame.len() < code.frame_size {
+        trap!(TrapKind::StackOverflow);
+    }
+    loop {
+        match &instrs[i] {
+            RegInstr::Copy { dst, src } => {
+                frame[*dst as usize] = frame[*src as usize];
+            }
+            RegInstr::I32Const { dst, value } => set!(dst; i32; *value),
+            RegInstr::Const { dst, index } => {
+                frame[*dst as usize] = code.constants[*index as usize];
+            }
+            RegInstr::Jump { pc } => jump!(*pc),
+            RegInstr::JumpIf { cond, pc } => {
+                if get!(cond; i32) != 0 {
+                    jump!(*pc);
+                }
+            }
+            RegInstr::JumpIfZero { cond, pc } => {
+                if get!(cond; i32) == 0 {
+                    jump!(*pc);
+                }
+            }
+            RegInstr::Br { branch } => branch!(&code.branches[*branch as usize]),
+            RegInstr::BrIf { cond, branch } => {
+                if get!(cond; i32) != 0 {
+                    branch!(&code.branches[*branch as usize]);
+                }
+            }
+            RegInstr::BrTable {
+                index,
+                targets,
+                len,
+            } => {
+                let index = (get!(index; i32) as u32).min(*len);
+                branch!(&code.branches[(targets + index) as usize]);
+            }
+            RegInstr::Return { src } => {
+                let src = *src as usize;
+                frame.copy_within(src..src + return_arity, 0);
+                return Ok(Exit::End);
+            }
+            RegInstr::Call { function_index, sp } => {
+                call!(context.get_function(*function_index), sp)
+            }
+            RegInstr::CallIndirect { func, indirect, sp } => {
+                let (type_index, table_index) = code.indirect[*indirect as usize];
+                let func_index = get!(func; i32) as u32;
+                match indirect_func(context, type_index, table_index, func_index) {
+                    Ok(f) => call!(f, sp),
+                    Err(kind) => trap!(kind),
+                }
+            }
+            RegInstr::Stack { pc, end, sp } => {
+                // Continues after the call the instructions stopped at.
+                let (start, sp) = match state.pc {
+                    0 => (*pc as usize, *sp as usize),
+                    pc => (pc, state.sp),
+                };
+                let mut stack = EvalStack {
+                    stack: &mut *frame,
+                    sp,
+                };
+                let trace = &mut state.trace;
+                match run(
+                    context,
+                    bytecode,
+                    &mut stack,
+                    memory_cache,
+                    trace,
+                    start,
+                    *end as usize,
+                )? {
+                    Exit::End => state.pc = 0,
+                    exit => {
+                        state.register_pc = i;
+                        state.sp = stack.sp;
+                        return Ok(exit);
+                    }
+                }
+            }
+            RegInstr::I32Eqz { dst, a } => op!(dst; |a: i32| -> i32 (a == 0) as i32),
+            RegInstr::I32Clz { dst, a } => op!(dst; |a: i32| -> i32 a.leading_zeros() as i32),
+            RegInstr::I32Ctz { dst, a } => op!(dst; |a: i32| -> i32 a.trailing_zeros() as i32),
+            RegInstr::I32Popcnt { dst, a } => op!(dst; |a: i32| -> i32 a.count_ones() as i32),
+            RegInstr::I64Eqz { dst, a } => op!(dst; |a: i64| -> i32 (a == 0) as i32),
+            RegInstr::I64Clz { dst, a } => op!(dst; |a: i64| -> i64 a.leading_zeros() as i64),
+            RegInstr::I64Ctz { dst, a } => op!(dst; |a: i64| -> i64 a.trailing_zeros() as i64),
+            RegInstr::I64Popcnt { dst, a } => op!(dst; |a: i64| -> i64 a.count_ones() as i64),
+            RegInstr::F32Abs { dst, a } => op!(dst; |a: f32| -> f32 wasm_f32::abs(a)),
+            RegInstr::F32Neg { dst, a } => op!(dst; |a: f32| -> f32 wasm_f32::neg(a)),
+            RegInstr::F32Sqrt { dst, a } => op!(dst; |a: f32| -> f32 wasm_f32::sqrt(a)),
+            RegInstr::F64Abs { dst, a } => op!(dst; |a: f64| -> f64 wasm_f64::abs(a)),
+            RegInstr::F64Neg { dst, a } => op!(dst; |a: f64| -> f64 wasm_f64::neg(a)),
+            RegInstr::F64Sqrt { dst, a } => op!(dst; |a: f64| -> f64 wasm_f64::sqrt(a)),
+            RegInstr::I32WrapI64 { dst, a } => op!(dst; |a: i64| -> i32 a as i32),
+            RegInstr::I64ExtendI32S { dst, a } => op!(dst; |a: i32| -> i64 a as i64),
+            RegInstr::I64ExtendI32U { dst, a } => op!(dst; |a: i32| -> i64 a as u32 as i64),
+            RegInstr::F32ConvertI32S { dst, a } => op!(dst; |a: i32| -> f32 wasm_f32::from_i32(a)),
+            RegInstr::F64ConvertI32S { dst, a } => op!(dst; |a: i32| -> f64 wasm_f64::from_i32(a)),
+            RegInstr::F64ConvertI64S { dst, a } => op!(dst; |a: i64| -> f64 wasm_f64::from_i64(a)),
+            RegInstr::F32DemoteF64 { dst, a } => op!(dst; |a: f64| -> f32 wasm_f32::from_f64(a)),
+            RegInstr::F64PromoteF32 { dst, a } => op!(dst; |a: f32| -> f64 wasm_f64::from_f32(a)),
+            RegInstr::I32Extend8S { dst, a } => op!(dst; |a: i32| -> i32 (a as i8) as i32),
+            RegInstr::I32Extend16S { dst, a } => op!(dst; |a: i32| -> i32 (a as i16) as i32),
+            RegInstr::I64Extend8S { dst, a } => op!(dst; |a: i64| -> i64 (a as i8) as i64),
+            RegInstr::I64Extend16S { dst, a } => op!(dst; |a: i64| -> i64 (a as i16) as i64),
+            RegInstr::I64Extend32S { dst, a } => op!(dst; |a: i64| -> i64 (a as i32) as i64),
+            RegInstr::I32Eq { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 (a == b) as i32),
+            RegInstr::I32Ne { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 (a != b) as i32),
+            RegInstr::I32LtS { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 (a < b) as i32),
+            RegInstr::I32LtU { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 ((a as u32) < b as u32) as i32)
+            }
+            RegInstr::I32GtS { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 (a > b) as i32),
+            RegInstr::I32GtU { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 (a as u32 > b as u32) as i32)
+            }
+            RegInstr::I32LeS { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 (a <= b) as i32),
+            RegInstr::I32LeU { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 (a as u32 <= b as u32) as i32)
+            }
+            RegInstr::I32GeS { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 (a >= b) as i32),
+            RegInstr::I32GeU { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 (a as u32 >= b as u32) as i32)
+            }
+            RegInstr::I64Eq { dst, a, b } => op!(dst; |a: i64, b: i64| -> i32 (a == b) as i32),
+            RegInstr::I64Ne { dst, a, b } => op!(dst; |a: i64, b: i64| -> i32 (a != b) as i32),
+            RegInstr::I64LtS { dst, a, b } => op!(dst; |a: i64, b: i64| -> i32 (a < b) as i32),
+            RegInstr::I64LtU { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i32 ((a as u64) < b as u64) as i32)
+            }
+            RegInstr::I64GtS { dst, a, b } => op!(dst; |a: i64, b: i64| -> i32 (a > b) as i32),
+            RegInstr::I64GtU { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i32 (a as u64 > b as u64) as i32)
+            }
+            RegInstr::I64LeS { dst, a, b } => op!(dst; |a: i64, b: i64| -> i32 (a <= b) as i32),
+            RegInstr::I64LeU { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i32 (a as u64 <= b as u64) as i32)
+            }
+            RegInstr::I64GeS { dst, a, b } => op!(dst; |a: i64, b: i64| -> i32 (a >= b) as i32),
+            RegInstr::I64GeU { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i32 (a as u64 >= b as u64) as i32)
+            }
+            RegInstr::F32Eq { dst, a, b } => op!(dst; |a: f32, b: f32| -> i32 wasm_f32::eq(a, b)),
+            RegInstr::F32Ne { dst, a, b } => op!(dst; |a: f32, b: f32| -> i32 wasm_f32::ne(a, b)),
+            RegInstr::F32Lt { dst, a, b } => op!(dst; |a: f32, b: f32| -> i32 wasm_f32::lt(a, b)),
+            RegInstr::F32Gt { dst, a, b } => op!(dst; |a: f32, b: f32| -> i32 wasm_f32::gt(a, b)),
+            RegInstr::F32Le { dst, a, b } => op!(dst; |a: f32, b: f32| -> i32 wasm_f32::le(a, b)),
+            RegInstr::F32Ge { dst, a, b } => op!(dst; |a: f32, b: f32| -> i32 wasm_f32::ge(a, b)),
+            RegInstr::F64Eq { dst, a, b } => op!(dst; |a: f64, b: f64| -> i32 wasm_f64::eq(a, b)),
+            RegInstr::F64Ne { dst, a, b } => op!(dst; |a: f64, b: f64| -> i32 wasm_f64::ne(a, b)),
+            RegInstr::F64Lt { dst, a, b } => op!(dst; |a: f64, b: f64| -> i32 wasm_f64::lt(a, b)),
+            RegInstr::F64Gt { dst, a, b } => op!(dst; |a: f64, b: f64| -> i32 wasm_f64::gt(a, b)),
+            RegInstr::F64Le { dst, a, b } => op!(dst; |a: f64, b: f64| -> i32 wasm_f64::le(a, b)),
+            RegInstr::F64Ge { dst, a, b } => op!(dst; |a: f64, b: f64| -> i32 wasm_f64::ge(a, b)),
+            RegInstr::I32Add { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 a.wrapping_add(b)),
+            RegInstr::I32Sub { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 a.wrapping_sub(b)),
+            RegInstr::I32Mul { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 a.wrapping_mul(b)),
+            RegInstr::I32DivS { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 {
+                match a.checked_div(b) {
+                    Some(c) => c,
+                    None if b == 0 => trap!(TrapKind::DivisionByZero),
+                    None => trap!(TrapKind::Overflow),
+                }
+            }),
+            RegInstr::I32DivU { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 {
+                match (a as u32).checked_div(b as u32) {
+                    Some(c) => c as i32,
+                    None => trap!(TrapKind::DivisionByZero),
+                }
+            }),
+            RegInstr::I32RemS { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 {
+                match b {
+                    0 => trap!(TrapKind::DivisionByZero),
+                    _ => a.wrapping_rem(b),
+                }
+            }),
+            RegInstr::I32RemU { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 {
+                match (a as u32).checked_rem(b as u32) {
+                    Some(c) => c as i32,
+                    None => trap!(TrapKind::DivisionByZero),
+                }
+            }),
+            RegInstr::I32And { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 a & b),
+            RegInstr::I32Or { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 a | b),
+            RegInstr::I32Xor { dst, a, b } => op!(dst; |a: i32, b: i32| -> i32 a ^ b),
+            RegInstr::I32Shl { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 a.wrapping_shl(b as u32))
+            }
+            RegInstr::I32ShrS { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 a.wrapping_shr(b as u32))
+            }
+            RegInstr::I32ShrU { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 (a as u32).wrapping_shr(b as u32) as i32)
+            }
+            RegInstr::I32Rotl { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 a.rotate_left(b as u32))
+            }
+            RegInstr::I32Rotr { dst, a, b } => {
+                op!(dst; |a: i32, b: i32| -> i32 a.rotate_right(b as u32))
+            }
+            RegInstr::I64Add { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 a.wrapping_add(b)),
+            RegInstr::I64Sub { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 a.wrapping_sub(b)),
+            RegInstr::I64Mul { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 a.wrapping_mul(b)),
+            RegInstr::I64DivS { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 {
+                match a.checked_div(b) {
+                    Some(c) => c,
+                    None if b == 0 => trap!(TrapKind::DivisionByZero),
+                    None => trap!(TrapKind::Overflow),
+                }
+            }),
+            RegInstr::I64DivU { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 {
+                match (a as u64).checked_div(b as u64) {
+                    Some(c) => c as i64,
+                    None => trap!(TrapKind::DivisionByZero),
+                }
+            }),
+            RegInstr::I64RemS { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 {
+                match b {
+                    0 => trap!(TrapKind::DivisionByZero),
+                    _ => a.wrapping_rem(b),
+                }
+            }),
+            RegInstr::I64RemU { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 {
+                match (a as u64).checked_rem(b as u64) {
+                    Some(c) => c as i64,
+                    None => trap!(TrapKind::DivisionByZero),
+                }
+            }),
+            RegInstr::I64And { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 a & b),
+            RegInstr::I64Or { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 a | b),
+            RegInstr::I64Xor { dst, a, b } => op!(dst; |a: i64, b: i64| -> i64 a ^ b),
+            RegInstr::I64Shl { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i64 a.wrapping_shl(b as u32))
+            }
+            RegInstr::I64ShrS { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i64 a.wrapping_shr(b as u32))
+            }
+            RegInstr::I64ShrU { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i64 (a as u64).wrapping_shr(b as u32) as i64)
+            }
+            RegInstr::I64Rotl { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i64 a.rotate_left(b as u32))
+            }
+            RegInstr::I64Rotr { dst, a, b } => {
+                op!(dst; |a: i64, b: i64| -> i64 a.rotate_right(b as u32))
+            }
+            RegInstr::F32Add { dst, a, b } => op!(dst; |a: f32, b: f32| -> f32 wasm_f32::add(a, b)),
+            RegInstr::F32Sub { dst, a, b } => op!(dst; |a: f32, b: f32| -> f32 wasm_f32::sub(a, b)),
+            RegInstr::F32Mul { dst, a, b } => op!(dst; |a: f32, b: f32| -> f32 wasm_f32::mul(a, b)),
+            RegInstr::F32Div { dst, a, b } => op!(dst; |a: f32, b: f32| -> f32 wasm_f32::div(a, b)),
+            RegInstr::F32Min { dst, a, b } => op!(dst; |a: f32, b: f32| -> f32 wasm_f32::min(a, b)),
+            RegInstr::F32Max { dst, a, b } => op!(dst; |a: f32, b: f32| -> f32 wasm_f32::max(a, b)),
+            RegInstr::F64Add { dst, a, b } => op!(dst; |a: f64, b: f64| -> f64 wasm_f64::add(a, b)),
+            RegInstr::F64Sub { dst, a, b } => op!(dst; |a: f64, b: f64| -> f64 wasm_f64::sub(a, b)),
+            RegInstr::F64Mul { dst, a, b } => op!(dst; |a: f64, b: f64| -> f64 wasm_f64::mul(a, b)),
+            RegInstr::F64Div { dst, a, b } => op!(dst; |a: f64, b: f64| -> f64 wasm_f64::div(a, b)),
+            RegInstr::F64Min { dst, a, b } => op!(dst; |a: f64, b: f64| -> f64 wasm_f64::min(a, b)),
+            RegInstr::F64Max { dst, a, b } => op!(dst; |a: f64, b: f64| -> f64 wasm_f64::max(a, b)),
+            RegInstr::I32Load { dst, addr, memarg } => load!(dst, addr, memarg; i32 as i32),
+            RegInstr::I64Load { dst, addr, memarg } => load!(dst, addr, memarg; i64 as i64),
+            RegInstr::F32Load { dst, addr, memarg } => load!(dst, addr, memarg; f32 as u32),
+            RegInstr::F64Load { dst, addr, memarg } => load!(dst, addr, memarg; f64 as u64),
+            RegInstr::I32Load8S { dst, addr, memarg } => load!(dst, addr, memarg; i32 as i8),
+            RegInstr::I32Load8U { dst, addr, memarg } => load!(dst, addr, memarg; i32 as u8),
+            RegInstr::I32Load16S { dst, addr, memarg } => load!(dst, addr, memarg; i32 as i16),
+            RegInstr::I32Load16U { dst, addr, memarg } => load!(dst, addr, memarg; i32 as u16),
+            RegInstr::I64Load8S { dst, addr, memarg } => load!(dst, addr, memarg; i64 as i8),
+            RegInstr::I64Load8U { dst, addr, memarg } => load!(dst, addr, memarg; i64 as u8),
+            RegInstr::I64Load16S { dst, addr, memarg } => load!(dst, addr, memarg; i64 as i16),
+            RegInstr::I64Load16U { dst, addr, memarg } => load!(dst, addr, memarg; i64 as u16),
+            RegInstr::I64Load32S { dst, addr, memarg } => load!(dst, addr, memarg; i64 as i32),
+            RegInstr::I64Load32U { dst, addr, memarg } => load!(dst, addr, memarg; i64 as u32),
+            RegInstr::I32Store { addr, val, memarg } => store!(addr, val, memarg; i32 as i32),
+            RegInstr::I64Store { addr, val, memarg } => store!(addr, val, memarg; i64 as i64),
+            RegInstr::F32Store { addr, val, memarg } => store!(addr, val, memarg; f32 as u32),
+            RegInstr::F64Store { addr, val, memarg } => store!(addr, val, memarg; f64 as u64),
+            RegInstr::I32Store8 { addr, val, memarg } => store!(addr, val, memarg; i32 as u8),
+            RegInstr::I32Store16 { addr, val, memarg } => store!(addr, val, memarg; i32 as u16),
+            RegInstr::I64Store8 { addr, val, memarg } => store!(addr, val, memarg; i64 as u8),
+            RegInstr::I64Store16 { addr, val, memarg } => store!(addr, val, memarg; i64 as u16),
+            RegInstr::I64Store32 { addr, val, memarg } => store!(addr, val, memarg; i64 as u32),
+        }
+        i += 1;
+    }
+}
@@ -0,0 +1,175 @@
+// Lanes of the v128 values are stored in the little-endian order:
+// the lane 0 occupies the lowest bits of the u128.
+
+macro_rules! shape {
+    ($name:ident, $ty:ty, $count:expr) => {
+        // Not every operation is defined for all shapes.
+        #[allow(dead_code)]
+        pub mod $name {
+            const LANE_BITS: usize = std::mem::size_of::<$ty>() * 8;
+
+            #[inline]
+            pub fn lanes(a: u128) -> [$ty; $count] {
+                let mut lanes = [0; $count];
+                for (i, lane) in lanes.iter_mut().enumerate() {
+                    *lane = (a >> (i * LANE_BITS)) as $ty;
+                }
+                lanes
+            }
+
+            #[inline]
+            pub fn from_lanes(lanes: [$ty; $count]) -> u128 {
+                lanes
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, lane)| acc | (*lane as u128) << (i * LANE_BITS))
+            }
+
+            #[inline]
+            pub fn splat(a: $ty) -> u128 {
+                from_lanes([a; $count])
+            }
+
+            #[inline]
+            pub fn map(a: u128, f: impl Fn($ty) -> $ty) -> u128 {
+                let mut lanes = lanes(a);
+                lanes.iter_mut().for_each(|lane| *lane = f(*lane));
+                from_lanes(lanes)
+            }
+
+            #[inline]
+            pub fn zip(a: u128, b: u128, f: impl Fn($ty, $ty) -> $ty) -> u128 {
+                let mut lanes = lanes(a);
+                let b = self::lanes(b);
+                lanes
+                    .iter_mut()
+                    .zip(b.iter())
+                    .for_each(|(lane, b)| *lane = f(*lane, *b));
+                from_lanes(lanes)
+            }
+
+            /// Sets all bits of the result lanes for which `f` returns true.
+            #[inline]
+            pub fn cmp(a: u128, b: u128, f: impl Fn($ty, $ty) -> bool) -> u128 {
+                zip(a, b, |a, b| if f(a, b) { !0 } else { 0 })
+            }
+
+            #[inline]
+            pub fn all_true(a: u128) -> i32 {
+                lanes(a).iter().all(|lane| *lane != 0) as i32
+            }
+
+            #[inline]
+            pub fn bitmask(a: u128) -> i32 {
+                lanes(a).iter().enumerate().fold(0, |mask, (i, lane)| {
+                    mask | ((*lane >> (LANE_BITS - 1)) as i32) << i
+                })
+            }
+        }
+    };
+}
+
+shape!(u8x16, u8, 16);
+shape!(u16x8, u16, 8);
+shape!(u32x4, u32, 4);
+shape!(u64x2, u64, 2);
+
+#[inline]
+pub fn bitselect(a: u128, b: u128, c: u128) -> u128 {
+    (a & c) | (b & !c)
+}
+
+#[inline]
+pub fn swizzle(a: u128, s: u128) -> u128 {
+    let a = u8x16::lanes(a);
+    let mut lanes = u8x16::lanes(s);
+    for lane in lanes.iter_mut() {
+        *lane = if *lane < 16 { a[*lane as usize] } else { 0 };
+    }
+    u8x16::from_lanes(lanes)
+}
+
+#[inline]
+pub fn shuffle(a: u128, b: u128, indices: &[u8; 16]) -> u128 {
+    let a = u8x16::lanes(a);
+    let b = u8x16::lanes(b);
+    let mut lanes = [0; 16];
+    for (lane, i) in lanes.iter_mut().zip(indices.iter()) {
+        let i = *i as usize;
+        *lane = if i < 16 { a[i] } else { b[i - 16] };
+    }
+    u8x16::from_lanes(lanes)
+}
+
+#[inline]
+pub fn i8x16_narrow_i16x8(a: u128, b: u128, signed: bool) -> u128 {
+    let (min, max) = if signed { (-0x80, 0x7f) } else { (0, 0xff) };
+    let a = u16x8::lanes(a);
+    let b = u16x8::lanes(b);
+    let mut lanes = [0; 16];
+    for (lane, val) in lanes.iter_mut().zip(a.iter().chain(b.iter())) {
+        *lane = (*val as i16).max(min).min(max) as u8;
+    }
+    u8x16::from_lanes(lanes)
+}
+
+#[inline]
+pub fn i16x8_narrow_i32x4(a: u128, b: u128, signed: bool) -> u128 {
+    let (min, max) = if signed {
+        (-0x8000, 0x7fff)
+    } else {
+        (0, 0xffff)
+    };
+    let a = u32x4::lanes(a);
+    let b = u32x4::lanes(b);
+    let mut lanes = [0; 8];
+    for (lane, val) in lanes.iter_mut().zip(a.iter().chain(b.iter())) {
+        *lane = (*val as i32).max(min).min(max) as u16;
+    }
+    u16x8::from_lanes(lanes)
+}
+
+#[inline]
+pub fn i16x8_widen_i8x16(a: u128, high: bool, signed: bool) -> u128 {
+    let a = u8x16::lanes(a);
+    let start = if high { 8 } else { 0 };
+    let mut lanes = [0; 8];
+    for (lane, val) in lanes.iter_mut().zip(a[start..].iter()) {
+        *lane = if signed {
+            *val as i8 as u16
+        } else {
+            *val as u16
+        };
+    }
+    u16x8::from_lanes(lanes)
+}
+
+#[inline]
+pub fn i32x4_widen_i16x8(a: u128, high: bool, signed: bool) -> u128 {
+    let a = u16x8::lanes(a);
+    let start = if high { 4 } else { 0 };
+    let mut lanes = [0; 4];
+    for (lane, val) in lanes.iter_mut().zip(a[start..].iter()) {
+        *lane = if signed {
+            *val as i16 as u32
+        } else {
+            *val as u32
+        };
+    }
+    u32x4::from_lanes(lanes)
+}
+
+#[inline]
+pub fn i64x2_widen_i32x4(a: u128, high: bool, signed: bool) -> u128 {
+    let a = u32x4::lanes(a);
+    let start = if high { 2 } else { 0 };
+    let mut lanes = [0; 2];
+    for (lane, val) in lanes.iter_mut().zip(a[start..].iter()) {
+        *lane = if signed {
+            *val as i32 as u64
+        } else {
+            *val as u64
+        };
+    }
+    u64x2::from_lanes(lanes)
+}
@@ -1,8 +1,12 @@
+use std::fmt;
 use std::rc::Rc;
 use std::sync::Arc;
 pub use wasmparser::MemoryImmediate;
 
-use crate::values::{Trap, Val, ValType};
+use crate::eval::{Activation, DEFAULT_VALUE_STACK_SIZE};
+use crate::global::InstanceGlobal;
+use crate::memory::{SharedMemory, PAGE_SIZE};
+use crate::values::{RootScope, Slot, Trap, TrapKind, Val, ValType};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FuncType {
@@ -33,9 +37,80 @@ impl From<wasmparser::ResizableLimits> for Limits {
     }
 }
 
+impl Limits {
+    pub fn is_subtype_of(&self, other: &Limits) -> bool {
+        if self.initial < other.initial {
+            return false;
+        }
+        match (self.maximum, other.maximum) {
+            (_, None) => true,
+            (Some(max), Some(other_max)) => max <= other_max,
+            (None, Some(_)) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Limits64 {
+    pub initial: u64,
+    pub maximum: Option<u64>,
+}
+
+impl From<wasmparser::ResizableLimits> for Limits64 {
+    fn from(l: wasmparser::ResizableLimits) -> Self {
+        Self {
+            initial: l.initial as u64,
+            maximum: l.maximum.map(|max| max as u64),
+        }
+    }
+}
+
+impl From<wasmparser::ResizableLimits64> for Limits64 {
+    fn from(l: wasmparser::ResizableLimits64) -> Self {
+        Self {
+            initial: l.initial,
+            maximum: l.maximum,
+        }
+    }
+}
+
+impl Limits64 {
+    pub fn is_subtype_of(&self, other: &Limits64) -> bool {
+        if self.initial < other.initial {
+            return false;
+        }
+        match (self.maximum, other.maximum) {
+            (_, None) => true,
+            (Some(max), Some(other_max)) => max <= other_max,
+            (None, Some(_)) => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MemoryType {
-    pub limits: Limits,
+    /// The memory size limits, in pages.
+    pub limits: Limits64,
+    pub shared: bool,
+    /// The memory is addressed by `i64` values instead of `i32`.
+    pub memory64: bool,
+}
+
+impl From<&wasmparser::MemoryType> for MemoryType {
+    fn from(ty: &wasmparser::MemoryType) -> Self {
+        match ty {
+            wasmparser::MemoryType::M32 { limits, shared } => Self {
+                limits: (*limits).into(),
+                shared: *shared,
+                memory64: false,
+            },
+            wasmparser::MemoryType::M64 { limits } => Self {
+                limits: (*limits).into(),
+                shared: false,
+                memory64: true,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -44,9 +119,29 @@ pub struct TableType {
     pub limits: Limits,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mutability {
+    Const,
+    Var,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GlobalType {
     pub ty: ValType,
+    pub mutability: Mutability,
+}
+
+impl From<&wasmparser::GlobalType> for GlobalType {
+    fn from(g: &wasmparser::GlobalType) -> Self {
+        GlobalType {
+            ty: g.content_type.into(),
+            mutability: if g.mutable {
+                Mutability::Var
+            } else {
+                Mutability::Const
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -57,36 +152,280 @@ pub enum ExternType {
     Table(TableType),
 }
 
+impl ExternType {
+    pub fn is_subtype_of(&self, other: &ExternType) -> bool {
+        match (self, other) {
+            (ExternType::Func(a), ExternType::Func(b)) => a == b,
+            (ExternType::Memory(a), ExternType::Memory(b)) => {
+                a.shared == b.shared
+                    && a.memory64 == b.memory64
+                    && a.limits.is_subtype_of(&b.limits)
+            }
+            (ExternType::Global(a), ExternType::Global(b)) => a == b,
+            (ExternType::Table(a), ExternType::Table(b)) => {
+                a.element == b.element && a.limits.is_subtype_of(&b.limits)
+            }
+            _ => false,
+        }
+    }
+}
+
 pub trait Func {
     fn ty(&self) -> &Arc<FuncType>;
     fn call(&self, stack: &mut [Val]) -> Result<(), Trap>;
+    /// Same as `call`, except that the function may end with a tail call:
+    /// then its callee is returned, with the arguments left on the `stack`,
+    /// and it is up to the caller to continue with the callee. This way
+    /// chains of tail calls do not grow the native stack.
+    fn call_tail(&self, stack: &mut [Val]) -> Result<Option<Rc<dyn Func>>, Trap> {
+        self.call(stack).map(|()| None)
+    }
     fn call_wrapped(&self, args: &[Val], results: &mut [Val]) -> Result<(), Trap> {
-        let mut stack = vec![Default::default(); 10000];
-        stack[..args.len()].clone_from_slice(args);
-        self.call(&mut stack)?;
-        results.clone_from_slice(&stack[..results.len()]);
-        Ok(())
+        call_with_stack_size(self, DEFAULT_VALUE_STACK_SIZE, args, results)
     }
+    /// Same as `call_tail`, except that the arguments and the results are
+    /// the untagged slots of the interpreter stack. The instance functions
+    /// run on the slots, and other functions get them converted to values.
+    #[doc(hidden)]
+    fn call_slots(&self, stack: &mut [Slot]) -> Result<Option<Rc<dyn Func>>, Trap> {
+        call_host(self, stack)
+    }
+    /// Initializes the frame of an instance function on the `stack`, for
+    /// the interpreter to evaluate it without `call_slots`.
+    #[doc(hidden)]
+    fn activate(&self, _stack: &mut [Slot]) -> Result<Option<Activation>, Trap> {
+        Ok(None)
+    }
+}
+
+pub(crate) fn call_with_stack_size<F: Func + ?Sized>(
+    f: &F,
+    stack_size: usize,
+    args: &[Val],
+    results: &mut [Val],
+) -> Result<(), Trap> {
+    let ty = f.ty();
+    if args.len() != ty.params.len()
+        || args
+            .iter()
+            .zip(ty.params.iter())
+            .any(|(arg, ty)| arg.ty() != *ty)
+        || results.len() != ty.returns.len()
+    {
+        return Err(Trap::new(TrapKind::ArgumentMismatch, 0));
+    }
+    if args.len() > stack_size || results.len() > stack_size {
+        return Err(Trap::new(TrapKind::StackOverflow, 0));
+    }
+    let _scope = RootScope::enter();
+    let mut stack = vec![Slot::default(); stack_size];
+    for (slot, arg) in stack.iter_mut().zip(args) {
+        *slot = Slot::from_val(arg);
+    }
+    let mut next = f.call_slots(&mut stack)?;
+    while let Some(f) = next {
+        next = f.call_slots(&mut stack)?;
+    }
+    for ((result, slot), ty) in results.iter_mut().zip(&stack).zip(ty.returns.iter()) {
+        *result = slot.to_val(*ty);
+    }
+    Ok(())
 }
 
+/// Calls the function with the slots converted to values: the function
+/// gets only the space for its arguments and results.
+fn call_host<F: Func + ?Sized>(f: &F, stack: &mut [Slot]) -> Result<Option<Rc<dyn Func>>, Trap> {
+    let ty = f.ty();
+    let mut vals = ty
+        .params
+        .iter()
+        .zip(stack.iter())
+        .map(|(ty, slot)| slot.to_val(*ty))
+        .collect::<Vec<_>>();
+    vals.resize(ty.params.len().max(ty.returns.len()), Default::default());
+    let next = f.call_tail(&mut vals)?;
+    // The tail callee continues with its arguments instead of the results.
+    let returns = match next {
+        Some(ref callee) => &callee.ty().params,
+        None => &ty.returns,
+    };
+    if returns.len() > vals.len()
+        || vals
+            .iter()
+            .zip(returns.iter())
+            .any(|(val, ty)| val.ty() != *ty)
+    {
+        return Err(Trap::new(TrapKind::ArgumentMismatch, 0));
+    }
+    for (slot, val) in stack.iter_mut().zip(&vals[..returns.len()]) {
+        *slot = Slot::from_val(val);
+    }
+    Ok(next)
+}
+
+#[derive(Debug)]
+pub struct MemoryOutOfBounds;
+
+/// Linear memory. Sizes are in pages, and addresses are 64-bit to be able
+/// to express memory64 memories; 32-bit memories never exceed 4GiB.
 pub trait Memory {
-    fn current(&self) -> u32;
-    fn grow(&self, delta: u32) -> u32;
-    fn content_ptr(&self, memarg: &MemoryImmediate, offset: u32, size: u32) -> *const u8;
-    fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u32, size: u32) -> *mut u8;
-    fn clone_from_slice(&self, offset: u32, chunk: &[u8]);
+    fn ty(&self) -> MemoryType;
+    fn current(&self) -> u64;
+    /// Returns the previous size, or `!0` if the memory cannot grow.
+    fn grow(&self, delta: u64) -> u64;
+    fn content_ptr(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *const u8;
+    /// The atomic operators trap when the returned pointer is not aligned
+    /// to the access size.
+    fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *mut u8;
+    fn clone_from_slice(&self, offset: u64, chunk: &[u8]);
+    /// Returns the memory as `SharedMemory` if it can be shared between threads.
+    fn as_shared(&self) -> Option<&SharedMemory> {
+        None
+    }
+    /// Copies `len` bytes from `src` to `dst`, the ranges may overlap.
+    fn copy_within(&self, dst: u64, src: u64, len: u64) -> Result<(), MemoryOutOfBounds> {
+        let src_ptr = memory_range_ptr(self, src, len)?;
+        let dst_ptr = memory_range_ptr(self, dst, len)?;
+        if len > 0 {
+            unsafe {
+                std::ptr::copy(src_ptr, dst_ptr, len as usize);
+            }
+        }
+        Ok(())
+    }
+    fn fill(&self, offset: u64, val: u8, len: u64) -> Result<(), MemoryOutOfBounds> {
+        let ptr = memory_range_ptr(self, offset, len)?;
+        if len > 0 {
+            unsafe {
+                std::ptr::write_bytes(ptr, val, len as usize);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn memory_range_ptr<M: Memory + ?Sized>(
+    memory: &M,
+    offset: u64,
+    len: u64,
+) -> Result<*mut u8, MemoryOutOfBounds> {
+    let memory_len = memory.current() as u128 * PAGE_SIZE as u128;
+    if offset as u128 + len as u128 > memory_len {
+        return Err(MemoryOutOfBounds);
+    }
+    if len == 0 {
+        return Ok(std::ptr::null_mut());
+    }
+    let memarg = MemoryImmediate {
+        align: 0,
+        offset: 0,
+        memory: 0,
+    };
+    // The range is checked above, only its first byte is requested.
+    Ok(memory.content_ptr_mut(&memarg, offset, 1))
+}
+
+/// Copies `len` bytes from the `src` memory to the different `dst` memory.
+pub(crate) fn copy_between_memories(
+    dst: &dyn Memory,
+    dst_offset: u64,
+    src: &dyn Memory,
+    src_offset: u64,
+    len: u64,
+) -> Result<(), MemoryOutOfBounds> {
+    let src_ptr = memory_range_ptr(src, src_offset, len)?;
+    let dst_ptr = memory_range_ptr(dst, dst_offset, len)?;
+    if len > 0 {
+        unsafe {
+            std::ptr::copy(src_ptr, dst_ptr, len as usize);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum GlobalError {
+    Immutable,
+    TypeMismatch { expected: ValType, actual: ValType },
+}
+
+impl fmt::Display for GlobalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GlobalError::Immutable => write!(f, "global is immutable"),
+            GlobalError::TypeMismatch { expected, actual } => write!(
+                f,
+                "global type mismatch: expected {:?}, found {:?}",
+                expected, actual
+            ),
+        }
+    }
 }
 
+impl std::error::Error for GlobalError {}
+
 pub trait Global {
+    fn ty(&self) -> GlobalType;
     fn content(&self) -> Val;
+    /// Stores the value without checks. The instances check the type and
+    /// the mutability of the imported globals, and `global.set` only stores
+    /// the values of the global type. Hosts shall use `set`.
     fn set_content(&self, val: &Val);
+
+    fn get(&self) -> Val {
+        self.content()
+    }
+
+    fn set(&self, val: Val) -> Result<(), GlobalError> {
+        let ty = self.ty();
+        if ty.mutability != Mutability::Var {
+            return Err(GlobalError::Immutable);
+        }
+        if val.ty() != ty.ty {
+            return Err(GlobalError::TypeMismatch {
+                expected: ty.ty,
+                actual: val.ty(),
+            });
+        }
+        self.set_content(&val);
+        Ok(())
+    }
+}
+
+impl dyn Global {
+    /// Creates a host global of the `ty` type, initialized with `init`.
+    pub fn new(
+        ty: ValType,
+        mutability: Mutability,
+        init: Val,
+    ) -> Result<Rc<dyn Global>, GlobalError> {
+        if init.ty() != ty {
+            return Err(GlobalError::TypeMismatch {
+                expected: ty,
+                actual: init.ty(),
+            });
+        }
+        let global = InstanceGlobal::new(GlobalType { ty, mutability }, init);
+        Ok(Rc::new(global))
+    }
 }
 
 #[derive(Debug)]
 pub struct TableOutOfBounds;
 
 pub trait Table {
-    fn get_func(&self, index: u32) -> Result<Option<Rc<dyn Func>>, TableOutOfBounds>;
+    fn ty(&self) -> TableType;
+    fn size(&self) -> u32;
+    fn get(&self, index: u32) -> Result<Val, TableOutOfBounds>;
+    fn set(&self, index: u32, val: Val) -> Result<(), TableOutOfBounds>;
+    /// Returns the previous size, or `!0` if the table cannot grow.
+    fn grow(&self, delta: u32, init: Val) -> u32;
+    fn get_func(&self, index: u32) -> Result<Option<Rc<dyn Func>>, TableOutOfBounds> {
+        match self.get(index)? {
+            Val::Func(f) => Ok(f),
+            _ => Ok(None),
+        }
+    }
     fn get_func_with_type(
         &self,
         index: u32,
@@ -95,7 +434,40 @@ pub trait Table {
         // TODO really check type
         self.get_func(index)
     }
-    fn set_func(&self, index: u32, f: Option<Rc<dyn Func>>) -> Result<(), TableOutOfBounds>;
+    fn set_func(&self, index: u32, f: Option<Rc<dyn Func>>) -> Result<(), TableOutOfBounds> {
+        self.set(index, Val::Func(f))
+    }
+    /// Writes `items` starting at `offset`. Nothing is written if the range
+    /// is out of bounds.
+    fn init(&self, offset: u32, items: &[Val]) -> Result<(), TableOutOfBounds> {
+        if offset as u64 + items.len() as u64 > self.size() as u64 {
+            return Err(TableOutOfBounds);
+        }
+        for (i, item) in items.iter().enumerate() {
+            self.set(offset + i as u32, item.clone())?;
+        }
+        Ok(())
+    }
+    fn fill(&self, offset: u32, val: Val, len: u32) -> Result<(), TableOutOfBounds> {
+        if offset as u64 + len as u64 > self.size() as u64 {
+            return Err(TableOutOfBounds);
+        }
+        for i in offset..offset + len {
+            self.set(i, val.clone())?;
+        }
+        Ok(())
+    }
+    /// Copies `len` entries from `src` to `dst`, the ranges may overlap.
+    fn copy_within(&self, dst: u32, src: u32, len: u32) -> Result<(), TableOutOfBounds> {
+        let size = self.size() as u64;
+        if src as u64 + len as u64 > size || dst as u64 + len as u64 > size {
+            return Err(TableOutOfBounds);
+        }
+        let items = (src..src + len)
+            .map(|i| self.get(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.init(dst, &items)
+    }
 }
 
 #[derive(Clone)]
@@ -107,6 +479,15 @@ pub enum External {
 }
 
 impl<'a> External {
+    pub fn ty(&self) -> ExternType {
+        match self {
+            External::Func(f) => ExternType::Func((**f.ty()).clone()),
+            External::Memory(m) => ExternType::Memory(m.ty()),
+            External::Global(g) => ExternType::Global(g.ty()),
+            External::Table(t) => ExternType::Table(t.ty()),
+        }
+    }
+
     pub fn func(&self) -> Option<&Rc<dyn Func>> {
         if let External::Func(f) = self {
             Some(f)
@@ -123,6 +504,14 @@ impl<'a> External {
         }
     }
 
+    pub fn global(&self) -> Option<&Rc<dyn Global>> {
+        if let External::Global(g) = self {
+            Some(g)
+        } else {
+            None
+        }
+    }
+
     pub fn table(&self) -> Option<&Rc<dyn Table>> {
         if let External::Table(t) = self {
             Some(t)
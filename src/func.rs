@@ -3,26 +3,26 @@ use std::rc::{Rc, Weak};
 use std::sync::Arc;
 
 use crate::eval::BytecodeCache;
-use crate::eval::{eval, EvalContext, EvalSource};
-use crate::externals::{Func, FuncType};
+use crate::eval::{eval, Activation, EvalContext, EvalSource};
+use crate::externals::{call_with_stack_size, Func, FuncType};
 use crate::instance::InstanceData;
-use crate::module::ModuleData;
-use crate::values::{get_default_value, Trap, Val};
+use crate::values::{get_default_value, RootScope, Slot, Trap, TrapKind, Val};
 
 pub(crate) trait InstanceFunctionSource {
-    fn instance_data(&self) -> Rc<InstanceData>;
+    /// Returns `None` when the instance was dropped.
+    fn instance_data(&self) -> Option<Rc<InstanceData>>;
 }
 
 impl InstanceFunctionSource for Rc<RefCell<Weak<InstanceData>>> {
-    fn instance_data(&self) -> Rc<InstanceData> {
-        self.borrow().upgrade().unwrap()
+    fn instance_data(&self) -> Option<Rc<InstanceData>> {
+        self.borrow().upgrade()
     }
 }
 
 pub(crate) struct InstanceFunction {
     source: Box<dyn InstanceFunctionSource>,
     defined_index: usize,
-    cache: RefCell<Option<InstanceFunctionBody>>,
+    cache: RefCell<Option<Rc<InstanceFunctionBody>>>,
     func_type: Arc<FuncType>,
 }
 
@@ -42,17 +42,15 @@ impl InstanceFunction {
 }
 
 struct InstanceFunctionBody {
-    _module_data: Arc<ModuleData>,
     bytecode: BytecodeCache,
-    locals: Vec<(u32, Val)>,
+    locals: Vec<(u32, Slot)>,
     params_arity: usize,
     frame_size: usize,
 }
 
 impl InstanceFunctionBody {
     pub fn new(
-        module_data: Arc<ModuleData>,
-        body: &wasmparser::FunctionBody<'static>,
+        body: &wasmparser::FunctionBody,
         params_arity: usize,
         results_arity: usize,
         ctx: &dyn EvalContext,
@@ -61,16 +59,14 @@ impl InstanceFunctionBody {
         let mut frame_size = params_arity;
         for local in body.get_locals_reader().expect("reader").into_iter() {
             let (count, ty) = local.expect("local def");
-            let local_val = get_default_value(ty.into());
+            let local_val = Slot::from_val(&get_default_value(ty.into()));
             locals.push((count, local_val));
             frame_size += count as usize;
         }
 
-        let reader = body.get_operators_reader().expect("operators reader");
-        let bytecode = BytecodeCache::new(reader, ctx, results_arity);
+        let bytecode = BytecodeCache::new(body, ctx, results_arity);
 
         InstanceFunctionBody {
-            _module_data: module_data,
             bytecode,
             locals,
             params_arity,
@@ -78,15 +74,18 @@ impl InstanceFunctionBody {
         }
     }
 
-    pub fn init_frame<'a>(&self, stack: &'a mut [Val]) -> usize {
+    pub fn init_frame<'a>(&self, stack: &'a mut [Slot]) -> Result<usize, Trap> {
+        if stack.len() < self.frame_size {
+            return Err(Trap::new(TrapKind::StackOverflow, 0));
+        }
         let mut j = self.params_arity;
         for (count, val) in self.locals.iter() {
             for _ in 0..*count {
-                stack[j] = val.clone();
+                stack[j] = *val;
                 j += 1;
             }
         }
-        self.frame_size
+        Ok(self.frame_size)
     }
 }
 
@@ -97,8 +96,23 @@ impl EvalSource for InstanceFunctionBody {
 }
 
 impl InstanceFunction {
-    fn instance_data(&self) -> Rc<InstanceData> {
-        self.source.instance_data()
+    fn init_body(&self, instance_data: &Rc<InstanceData>) {
+        if self.cache.borrow().is_none() {
+            let ty = self.ty();
+            let module_data = &instance_data.module_data;
+            let body = &module_data.func_bodies[self.defined_index];
+            let body =
+                InstanceFunctionBody::new(&body, ty.params.len(), ty.returns.len(), instance_data);
+            *self.cache.borrow_mut() = Some(Rc::new(body));
+        }
+    }
+
+    /// The function may outlive its instance in a table or in a global
+    /// of another instance, and it traps when called after that.
+    fn instance_data(&self) -> Result<Rc<InstanceData>, Trap> {
+        self.source
+            .instance_data()
+            .ok_or_else(|| Trap::new(TrapKind::DroppedInstance, 0))
     }
 }
 
@@ -109,27 +123,52 @@ impl Func for InstanceFunction {
 
     fn call(&self, stack: &mut [Val]) -> Result<(), Trap> {
         let ty = self.ty();
-        let instance_data = self.instance_data();
-        if self.cache.borrow().is_none() {
-            let module_data = &instance_data.module_data;
-            let body = &module_data.func_bodies[self.defined_index];
-            let body = InstanceFunctionBody::new(
-                module_data.clone(),
-                &body,
-                ty.params.len(),
-                ty.returns.len(),
-                &instance_data,
-            );
-            *self.cache.borrow_mut() = Some(body);
+        let _scope = RootScope::enter();
+        let mut slots = vec![Slot::default(); stack.len()];
+        for (slot, val) in slots.iter_mut().zip(stack.iter()) {
+            *slot = Slot::from_val(val);
         }
+        let mut next = self.call_slots(&mut slots)?;
+        while let Some(f) = next {
+            next = f.call_slots(&mut slots)?;
+        }
+        for ((val, slot), ty) in stack.iter_mut().zip(&slots).zip(ty.returns.iter()) {
+            *val = slot.to_val(*ty);
+        }
+        Ok(())
+    }
+
+    fn call_wrapped(&self, args: &[Val], results: &mut [Val]) -> Result<(), Trap> {
+        let stack_size = self.instance_data()?.stack_limits.get().value_stack_size;
+        call_with_stack_size(self, stack_size, args, results)
+    }
+
+    fn call_slots(&self, stack: &mut [Slot]) -> Result<Option<Rc<dyn Func>>, Trap> {
+        let ty = self.ty();
+        let instance_data = self.instance_data()?;
+        self.init_body(&instance_data);
         let body = self.cache.borrow();
-        let sp = body.as_ref().unwrap().init_frame(stack);
+        let sp = body.as_ref().unwrap().init_frame(stack)?;
         eval(
             &instance_data,
-            body.as_ref().unwrap(),
+            &**body.as_ref().unwrap(),
             ty.returns.len(),
             stack,
             sp,
-        )
+        )?;
+        Ok(None)
+    }
+
+    fn activate(&self, stack: &mut [Slot]) -> Result<Option<Activation>, Trap> {
+        let instance_data = self.instance_data()?;
+        self.init_body(&instance_data);
+        let body = self.cache.borrow().clone().unwrap();
+        let sp = body.init_frame(stack)?;
+        Ok(Some(Activation {
+            context: instance_data,
+            source: body,
+            return_arity: self.ty().returns.len(),
+            sp,
+        }))
     }
 }
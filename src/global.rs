@@ -1,20 +1,30 @@
-use crate::externals::Global;
+use crate::externals::{Global, GlobalType};
 use crate::values::Val;
 use std::cell::RefCell;
 
-pub struct InstanceGlobal(RefCell<Val>);
+pub struct InstanceGlobal {
+    ty: GlobalType,
+    val: RefCell<Val>,
+}
 
 impl InstanceGlobal {
-    pub fn new(val: Val) -> InstanceGlobal {
-        InstanceGlobal(RefCell::new(val))
+    pub fn new(ty: GlobalType, val: Val) -> InstanceGlobal {
+        debug_assert!(ty.ty == val.ty());
+        InstanceGlobal {
+            ty,
+            val: RefCell::new(val),
+        }
     }
 }
 
 impl Global for InstanceGlobal {
+    fn ty(&self) -> GlobalType {
+        self.ty.clone()
+    }
     fn content(&self) -> Val {
-        self.0.borrow().clone()
+        self.val.borrow().clone()
     }
     fn set_content(&self, val: &Val) {
-        *self.0.borrow_mut() = val.clone();
+        *self.val.borrow_mut() = val.clone();
     }
 }
@@ -1,19 +1,27 @@
-use anyhow::{bail, Error};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::rc::{Rc, Weak};
 use std::sync::Arc;
-use wasmparser::{
-    DataKind, ElementItem, ElementKind, ExternalKind, ImportSectionEntryType, InitExpr, MemoryType,
-};
+use wasmparser::{DataKind, ElementItem, ElementKind, ExternalKind, MemoryType};
 
-use crate::eval::{eval_const, BytecodeCache, EvalSource};
-use crate::externals::{External, Func, Global, Memory, Table};
+use crate::const_expr::eval_const_expr;
+use crate::error::{LinkError, ModuleError};
+use crate::eval::{
+    Activation, EvalContext, EvalMode, FuelConfig, HotSequence, InterruptHandle, SequenceProfile,
+    StackLimits,
+};
+use crate::externals::{
+    call_with_stack_size, External, Func, FuncType, Global, GlobalType, Memory, Table,
+    TableOutOfBounds, TableType,
+};
 use crate::func::InstanceFunction;
 use crate::global::InstanceGlobal;
-use crate::memory::InstanceMemory;
+use crate::memory::{InstanceMemory, SharedMemory, PAGE_SIZE};
 use crate::module::{Module, ModuleData};
 use crate::table::InstanceTable;
-use crate::values::Val;
+use crate::values::{get_default_value, Slot, Trap, TrapKind, Val};
+
+pub(crate) type ElementItems = Rc<[Val]>;
 
 pub(crate) struct InstanceData {
     pub module_data: Arc<ModuleData>,
@@ -21,150 +29,132 @@ pub(crate) struct InstanceData {
     pub globals: Vec<Rc<dyn Global>>,
     pub funcs: Vec<Rc<dyn Func>>,
     pub tables: Vec<Rc<dyn Table>>,
+    pub stack_limits: Cell<StackLimits>,
+    pub eval_mode: Cell<EvalMode>,
+    pub profile: SequenceProfile,
+    pub fuel_config: Cell<Option<FuelConfig>>,
+    pub fuel: Cell<u64>,
+    pub interrupt_handle: InterruptHandle,
+    pub dropped_data: Box<[Cell<bool>]>,
+    pub elements: Box<[RefCell<ElementItems>]>,
 }
 
 pub struct Instance {
-    #[allow(dead_code)]
     data: Rc<InstanceData>,
     exports: Vec<External>,
 }
 
 impl Instance {
-    pub fn new(module: &Module, externals: &[External]) -> Result<Instance, Error> {
+    pub fn new(module: &Module, externals: &[External]) -> Result<Instance, ModuleError> {
         let module_data = module.data();
         if module_data.imports.len() != externals.len() {
-            bail!("incompatible number of imports");
+            return Err(LinkError::ImportCount {
+                expected: module_data.imports.len(),
+                actual: externals.len(),
+            }
+            .into());
         }
         let mut memories = Vec::new();
         let mut funcs = Vec::new();
         let mut globals = Vec::new();
         let mut tables = Vec::new();
-        for (import, external) in module_data.imports.iter().zip(externals) {
-            match import.ty {
-                ImportSectionEntryType::Function(_sig) => {
-                    if let External::Func(f) = external {
-                        funcs.push(f.clone());
-                    } else {
-                        bail!("incompatible func import");
-                    }
+        let imports = module_data
+            .imports
+            .iter()
+            .zip(module_data.import_types.iter());
+        for ((import, expected), external) in imports.zip(externals) {
+            let expected = expected.clone();
+            let actual = external.ty();
+            if !actual.is_subtype_of(&expected) {
+                return Err(LinkError::IncompatibleImport {
+                    module: import.module.to_string(),
+                    field: import.field.unwrap_or_default().to_string(),
+                    expected,
+                    actual,
                 }
-                ImportSectionEntryType::Memory(_mt) => {
-                    if let External::Memory(m) = external {
-                        memories.push(m.clone());
-                    } else {
-                        bail!("incompatible memory import");
-                    }
-                }
-                ImportSectionEntryType::Global(_gt) => {
-                    if let External::Global(g) = external {
-                        globals.push(g.clone());
-                    } else {
-                        bail!("incompatible global import");
-                    }
-                }
-                ImportSectionEntryType::Table(_tt) => {
-                    if let External::Table(t) = external {
-                        tables.push(t.clone());
-                    } else {
-                        bail!("incompatible table import");
-                    }
-                }
-                i => unreachable!("unsupported: {:?}", i),
+                .into());
+            }
+            match external {
+                External::Func(f) => funcs.push(f.clone()),
+                External::Memory(m) => memories.push(m.clone()),
+                External::Global(g) => globals.push(g.clone()),
+                External::Table(t) => tables.push(t.clone()),
             }
         }
 
         for m in module_data.memories.iter() {
-            let limits = match m {
+            let memory: Option<Rc<dyn Memory>> = match *m {
                 MemoryType::M32 {
                     ref limits,
                     shared: false,
-                } => limits,
-                x => {
-                    bail!("unsupported memory type {:?}", x);
+                } => InstanceMemory::try_new(
+                    limits.initial as u64,
+                    limits.maximum.map(|max| max as u64),
+                    false,
+                )
+                .map(|memory| Rc::new(memory) as Rc<dyn Memory>),
+                MemoryType::M32 {
+                    ref limits,
+                    shared: true,
+                } => SharedMemory::new(
+                    limits.initial as usize,
+                    limits.maximum.expect("validated shared memory maximum") as usize,
+                )
+                .map(|memory| Rc::new(memory) as Rc<dyn Memory>),
+                MemoryType::M64 { ref limits } => {
+                    InstanceMemory::try_new(limits.initial, limits.maximum, true)
+                        .map(|memory| Rc::new(memory) as Rc<dyn Memory>)
                 }
             };
-            let memory = InstanceMemory::new(
-                limits.initial as usize,
-                limits.maximum.unwrap_or(65535) as usize,
-            );
-            memories.push(Rc::new(memory));
+            let memory = match memory {
+                Some(memory) => memory,
+                None => {
+                    return Err(ModuleError::Unsupported(format!("memory type {:?}", m)));
+                }
+            };
+            memories.push(memory);
         }
         for t in module_data.tables.iter() {
             let limits = &t.limits;
             let table = InstanceTable::new(
+                t.element_type.into(),
                 limits.initial as usize,
-                limits.maximum.unwrap_or(0xffff_ffff) as usize,
+                limits.maximum.map(|max| max as usize),
             );
             tables.push(Rc::new(table));
         }
 
+        let source: Rc<RefCell<Weak<InstanceData>>> = Rc::new(RefCell::new(Weak::new()));
+
+        for (i, ft) in module_data.func_types.iter().enumerate() {
+            let ty = module_data.types[*ft as usize].clone();
+            let f: InstanceFunction = InstanceFunction::new(Box::new(source.clone()), i, ty);
+            funcs.push(Rc::new(f));
+        }
+
+        // Global initializers may refer to the imported globals and functions.
         let mut instance_data = Rc::new(InstanceData {
             module_data: module_data.clone(),
             memories: vec![],
             globals,
-            funcs: vec![],
+            funcs: funcs.clone(),
             tables: vec![],
+            stack_limits: Default::default(),
+            eval_mode: Default::default(),
+            profile: Default::default(),
+            fuel_config: Cell::new(None),
+            fuel: Cell::new(0),
+            interrupt_handle: InterruptHandle::new(),
+            dropped_data: vec![Cell::new(false); module_data.data.len()].into_boxed_slice(),
+            elements: vec![].into_boxed_slice(),
         });
         for g in module_data.globals.iter() {
-            let init_val = eval_init_expr(&instance_data, &g.init_expr);
-            let global = InstanceGlobal::new(init_val);
+            let init_val = eval_const_expr(&instance_data, &g.init_expr)?;
+            let global = InstanceGlobal::new((&g.ty).into(), init_val);
             let data = Rc::get_mut(&mut instance_data).unwrap();
             data.globals.push(Rc::new(global));
         }
 
-        let source: Rc<RefCell<Weak<InstanceData>>> = Rc::new(RefCell::new(Weak::new()));
-
-        for (i, ft) in module_data.func_types.iter().enumerate() {
-            let ty = module_data.types[*ft as usize].clone();
-            let f: InstanceFunction = InstanceFunction::new(Box::new(source.clone()), i, ty);
-            funcs.push(Rc::new(f));
-        }
-
-        for chunk in module_data.data.iter() {
-            match chunk.kind {
-                DataKind::Active {
-                    memory_index,
-                    ref init_expr,
-                } => {
-                    let start = eval_init_expr(&instance_data, init_expr).i32().unwrap() as u32;
-                    // TODO check boundaries
-                    memories[memory_index as usize].clone_from_slice(start, chunk.data);
-                }
-                DataKind::Passive => (),
-            }
-        }
-
-        for element in module_data.elements.iter() {
-            match element.kind {
-                ElementKind::Active {
-                    table_index,
-                    ref init_expr,
-                } => {
-                    let start = eval_init_expr(&instance_data, init_expr).i32().unwrap() as u32;
-                    for (i, item) in element
-                        .items
-                        .get_items_reader()
-                        .expect("reader")
-                        .into_iter()
-                        .enumerate()
-                    {
-                        let index = item
-                            .ok()
-                            .and_then(|item| match item {
-                                ElementItem::Func(index) => Some(index),
-                                _ => None,
-                            })
-                            .expect("func_index");
-                        let f = funcs[index as usize].clone();
-                        tables[table_index as usize]
-                            .set_func(start + i as u32, Some(f))
-                            .expect("element set out-of-bounds");
-                    }
-                }
-                ElementKind::Passive => (),
-                ElementKind::Declared => (),
-            }
-        }
         let globals = Rc::try_unwrap(instance_data).ok().unwrap().globals;
 
         let mut exports = Vec::new();
@@ -175,27 +165,53 @@ impl Instance {
                 ExternalKind::Memory => External::Memory(memories[index].clone()),
                 ExternalKind::Global => External::Global(globals[index].clone()),
                 ExternalKind::Table => External::Table(tables[index].clone()),
-                _ => {
-                    panic!("TODO");
-                }
+                kind => return Err(ModuleError::Unsupported(format!("export {:?}", kind))),
             });
         }
 
+        let elements = module_data
+            .elements
+            .iter()
+            .map(|element| {
+                let items = element
+                    .items
+                    .get_items_reader()
+                    .and_then(|reader| reader.into_iter().collect::<Result<Vec<_>, _>>())
+                    .expect("validated element items");
+                let items: ElementItems = items
+                    .into_iter()
+                    .map(|item| match item {
+                        ElementItem::Func(index) => Val::Func(Some(funcs[index as usize].clone())),
+                        ElementItem::Null(ty) => get_default_value(ty.into()),
+                    })
+                    .collect();
+                RefCell::new(items)
+            })
+            .collect();
+
         let instance_data = Rc::new(InstanceData {
             module_data: module_data.clone(),
             memories,
             globals,
             funcs,
             tables,
+            stack_limits: Default::default(),
+            eval_mode: Default::default(),
+            profile: Default::default(),
+            fuel_config: Cell::new(None),
+            fuel: Cell::new(0),
+            interrupt_handle: InterruptHandle::new(),
+            dropped_data: vec![Cell::new(false); module_data.data.len()].into_boxed_slice(),
+            elements,
         });
         *source.borrow_mut() = Rc::downgrade(&instance_data);
 
-        // Call start
-        if let Some(start_func) = module_data.start_func {
-            let f = instance_data.funcs[start_func as usize].clone();
-            debug_assert!(f.ty().params.len() == 0 && f.ty().returns.len() == 0);
-            let mut stack = vec![Default::default(); 10000];
-            f.call(&mut stack)?;
+        let result = init_elements(&instance_data)
+            .and_then(|()| init_data(&instance_data))
+            .and_then(|()| call_start(&instance_data));
+        if let Err(err) = result {
+            keep_escaped_functions(instance_data, &source);
+            return Err(err);
         }
 
         Ok(Instance {
@@ -204,19 +220,326 @@ impl Instance {
         })
     }
 
+    pub fn stack_limits(&self) -> StackLimits {
+        self.data.stack_limits.get()
+    }
+
+    pub fn set_stack_limits(&self, limits: StackLimits) {
+        self.data.stack_limits.set(limits);
+    }
+
+    pub fn eval_mode(&self) -> EvalMode {
+        self.data.eval_mode.get()
+    }
+
+    pub fn set_eval_mode(&self, mode: EvalMode) {
+        self.data.eval_mode.set(mode);
+    }
+
+    /// Returns the instruction sequences executed in `EvalMode::Profile`
+    /// by the instance functions, the most executed first.
+    pub fn hot_sequences(&self) -> Vec<HotSequence> {
+        self.data.profile.hot_sequences()
+    }
+
+    pub fn clear_profile(&self) {
+        self.data.profile.clear();
+    }
+
+    /// Enables fuel metering for the instance functions, or disables it
+    /// when `None` is passed.
+    pub fn set_fuel_config(&self, config: Option<FuelConfig>) {
+        self.data.fuel_config.set(config);
+    }
+
+    pub fn set_fuel(&self, fuel: u64) {
+        self.data.fuel.set(fuel);
+    }
+
+    pub fn add_fuel(&self, fuel: u64) {
+        let current = self.data.fuel.get();
+        self.data.fuel.set(current.saturating_add(fuel));
+    }
+
+    pub fn remaining_fuel(&self) -> u64 {
+        self.data.fuel.get()
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.data.interrupt_handle.clone()
+    }
+
     pub fn exports(&self) -> &[External] {
         &self.exports
     }
+
+    pub fn export_names(&self) -> impl Iterator<Item = &str> {
+        self.data.module_data.exports.iter().map(|e| e.field)
+    }
+
+    pub fn iter_exports(&self) -> impl Iterator<Item = (&str, &External)> {
+        self.export_names().zip(self.exports.iter())
+    }
+
+    pub fn get_export(&self, name: &str) -> Option<&External> {
+        self.iter_exports()
+            .find(|(field, _)| *field == name)
+            .map(|(_, export)| export)
+    }
+
+    pub fn get_func(&self, name: &str) -> Option<Rc<dyn Func>> {
+        self.get_export(name).and_then(|e| e.func()).cloned()
+    }
+
+    pub fn get_memory(&self, name: &str) -> Option<Rc<dyn Memory>> {
+        self.get_export(name).and_then(|e| e.memory()).cloned()
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Rc<dyn Global>> {
+        self.get_export(name).and_then(|e| e.global()).cloned()
+    }
+
+    pub fn get_table(&self, name: &str) -> Option<Rc<dyn Table>> {
+        self.get_export(name).and_then(|e| e.table()).cloned()
+    }
+}
+
+fn init_elements(data: &Rc<InstanceData>) -> Result<(), ModuleError> {
+    let module_data = &data.module_data;
+    for (index, element) in module_data.elements.iter().enumerate() {
+        let (table_index, init_expr) = match element.kind {
+            ElementKind::Active {
+                table_index,
+                ref init_expr,
+            } => (table_index, init_expr),
+            ElementKind::Declared => {
+                data.drop_element(index as u32);
+                continue;
+            }
+            ElementKind::Passive => continue,
+        };
+        let start = eval_const_expr(data, init_expr)?.i32().unwrap() as u32;
+        let items = data.get_element(index as u32);
+        data.tables[table_index as usize]
+            .init(start, &items)
+            .map_err(|_| Trap::new(TrapKind::TableOutOfBounds, 0))?;
+        data.drop_element(index as u32);
+    }
+    Ok(())
+}
+
+/// A function of a failed instantiation, stored outside of its instance.
+/// The instance functions refer to their instance weakly, so the entry
+/// keeps the instance alive instead.
+struct EscapedFunction {
+    func: Rc<dyn Func>,
+    _instance_data: Rc<InstanceData>,
+}
+
+impl Func for EscapedFunction {
+    fn ty(&self) -> &Arc<FuncType> {
+        self.func.ty()
+    }
+
+    fn call(&self, stack: &mut [Val]) -> Result<(), Trap> {
+        self.func.call(stack)
+    }
+
+    fn call_tail(&self, stack: &mut [Val]) -> Result<Option<Rc<dyn Func>>, Trap> {
+        self.func.call_tail(stack)
+    }
+
+    fn call_wrapped(&self, args: &[Val], results: &mut [Val]) -> Result<(), Trap> {
+        self.func.call_wrapped(args, results)
+    }
+
+    fn call_slots(&self, stack: &mut [Slot]) -> Result<Option<Rc<dyn Func>>, Trap> {
+        self.func.call_slots(stack)
+    }
+
+    fn activate(&self, stack: &mut [Slot]) -> Result<Option<Activation>, Trap> {
+        self.func.activate(stack)
+    }
+}
+
+/// An imported table holding escaped functions, which their instance
+/// refers to weakly: the table is released with its last outside owner.
+/// Once released, it is seen empty.
+struct WeakTable {
+    ty: TableType,
+    table: Weak<dyn Table>,
 }
 
-fn eval_init_expr(data: &Rc<InstanceData>, init_expr: &InitExpr<'static>) -> Val {
-    struct S(BytecodeCache);
-    impl EvalSource for S {
-        fn bytecode(&self) -> &BytecodeCache {
-            &self.0
+impl Table for WeakTable {
+    fn ty(&self) -> TableType {
+        match self.table.upgrade() {
+            Some(table) => table.ty(),
+            None => self.ty.clone(),
         }
     }
-    let bytecode = BytecodeCache::new(init_expr.get_operators_reader(), data, 1);
-    let init_expr_source = S(bytecode);
-    eval_const(data, &init_expr_source)
+
+    fn size(&self) -> u32 {
+        self.table.upgrade().map_or(0, |table| table.size())
+    }
+
+    fn get(&self, index: u32) -> Result<Val, TableOutOfBounds> {
+        self.table.upgrade().ok_or(TableOutOfBounds)?.get(index)
+    }
+
+    fn set(&self, index: u32, val: Val) -> Result<(), TableOutOfBounds> {
+        self.table
+            .upgrade()
+            .ok_or(TableOutOfBounds)?
+            .set(index, val)
+    }
+
+    fn grow(&self, delta: u32, init: Val) -> u32 {
+        self.table
+            .upgrade()
+            .map_or(!0, |table| table.grow(delta, init))
+    }
+
+    fn init(&self, offset: u32, items: &[Val]) -> Result<(), TableOutOfBounds> {
+        self.table
+            .upgrade()
+            .ok_or(TableOutOfBounds)?
+            .init(offset, items)
+    }
+
+    fn fill(&self, offset: u32, val: Val, len: u32) -> Result<(), TableOutOfBounds> {
+        self.table
+            .upgrade()
+            .ok_or(TableOutOfBounds)?
+            .fill(offset, val, len)
+    }
+
+    fn copy_within(&self, dst: u32, src: u32, len: u32) -> Result<(), TableOutOfBounds> {
+        self.table
+            .upgrade()
+            .ok_or(TableOutOfBounds)?
+            .copy_within(dst, src, len)
+    }
+}
+
+/// Same as `WeakTable` for an imported global. Once released, the global
+/// holds the default value of its type.
+struct WeakGlobal {
+    ty: GlobalType,
+    global: Weak<dyn Global>,
+}
+
+impl Global for WeakGlobal {
+    fn ty(&self) -> GlobalType {
+        self.ty.clone()
+    }
+
+    fn content(&self) -> Val {
+        match self.global.upgrade() {
+            Some(global) => global.content(),
+            None => get_default_value(self.ty.ty),
+        }
+    }
+
+    fn set_content(&self, val: &Val) {
+        if let Some(global) = self.global.upgrade() {
+            global.set_content(val);
+        }
+    }
+}
+
+/// Element segments and the start function may have stored the instance
+/// functions into the imported tables and globals before the instantiation
+/// failed. These functions stay callable: their entries are replaced by
+/// ones that own the instance, which is released with the last of them.
+/// The instance refers to these tables and globals weakly, or they would
+/// keep themselves alive through their own entries.
+fn keep_escaped_functions(data: Rc<InstanceData>, source: &RefCell<Weak<InstanceData>>) {
+    // The start function has returned, nothing else owns the instance.
+    let mut data = Rc::try_unwrap(data).ok().unwrap();
+    let module_data = data.module_data.clone();
+    let own_funcs = data.funcs[module_data.imported_func_map.len()..]
+        .iter()
+        .map(|f| Rc::as_ptr(f) as *const u8)
+        .collect::<HashSet<_>>();
+    let is_own = |val: &Val| match val {
+        Val::Func(Some(func)) => own_funcs.contains(&(Rc::as_ptr(func) as *const u8)),
+        _ => false,
+    };
+
+    let mut escaped_tables = Vec::new();
+    for table in data.tables[..module_data.imported_tables_map.len()].iter_mut() {
+        let entries = (0..table.size())
+            .filter(|&index| matches!(table.get(index), Ok(val) if is_own(&val)))
+            .collect::<Vec<_>>();
+        if !entries.is_empty() {
+            let weak = Rc::new(WeakTable {
+                ty: table.ty(),
+                table: Rc::downgrade(table),
+            });
+            escaped_tables.push((std::mem::replace(table, weak), entries));
+        }
+    }
+    let mut escaped_globals = Vec::new();
+    for global in data.globals[..module_data.imported_globals_map.len()].iter_mut() {
+        if is_own(&global.content()) {
+            let weak = Rc::new(WeakGlobal {
+                ty: global.ty(),
+                global: Rc::downgrade(global),
+            });
+            escaped_globals.push(std::mem::replace(global, weak));
+        }
+    }
+
+    let data = Rc::new(data);
+    *source.borrow_mut() = Rc::downgrade(&data);
+    let escaped = |val: Val| match val {
+        Val::Func(Some(func)) => Val::Func(Some(Rc::new(EscapedFunction {
+            func,
+            _instance_data: data.clone(),
+        }))),
+        val => val,
+    };
+    for (table, entries) in escaped_tables {
+        for index in entries {
+            let val = table.get(index).expect("table entry");
+            table.set(index, escaped(val)).expect("table entry");
+        }
+    }
+    for global in escaped_globals {
+        global.set_content(&escaped(global.content()));
+    }
+}
+
+fn init_data(data: &Rc<InstanceData>) -> Result<(), ModuleError> {
+    for (index, chunk) in data.module_data.data.iter().enumerate() {
+        let (memory_index, init_expr) = match chunk.kind {
+            DataKind::Active {
+                memory_index,
+                ref init_expr,
+            } => (memory_index, init_expr),
+            DataKind::Passive => continue,
+        };
+        let start = match eval_const_expr(data, init_expr)? {
+            Val::I64(start) => start as u64,
+            start => start.i32().unwrap() as u32 as u64,
+        };
+        let memory = &data.memories[memory_index as usize];
+        let end = start as u128 + chunk.data.len() as u128;
+        if end > memory.current() as u128 * PAGE_SIZE as u128 {
+            return Err(Trap::new(TrapKind::OutOfBounds, 0).into());
+        }
+        memory.clone_from_slice(start, chunk.data);
+        data.drop_data(index as u32);
+    }
+    Ok(())
+}
+
+fn call_start(data: &Rc<InstanceData>) -> Result<(), ModuleError> {
+    if let Some(start_func) = data.module_data.start_func {
+        let f = data.funcs[start_func as usize].clone();
+        debug_assert!(f.ty().params.len() == 0 && f.ty().returns.len() == 0);
+        let stack_size = data.stack_limits.get().value_stack_size;
+        call_with_stack_size(f.as_ref(), stack_size, &[], &mut [])?;
+    }
+    Ok(())
 }
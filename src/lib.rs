@@ -1,25 +1,34 @@
-pub use crate::eval::EvalContext;
+pub use crate::error::{LinkError, ModuleError};
+pub use crate::eval::{
+    EvalContext, EvalMode, FuelConfig, FuelGranularity, HotSequence, InterruptHandle,
+    SequenceProfile, StackLimits,
+};
 pub use crate::externals::{
-    ExternType, External, Func, FuncType, Global, GlobalType, Limits, Memory, MemoryImmediate,
-    MemoryType, Table, TableOutOfBounds, TableType,
+    ExternType, External, Func, FuncType, Global, GlobalError, GlobalType, Limits, Limits64,
+    Memory, MemoryImmediate, MemoryOutOfBounds, MemoryType, Mutability, Table, TableOutOfBounds,
+    TableType,
 };
 pub use crate::instance::Instance;
-pub use crate::memory::InstanceMemory;
+pub use crate::linker::Linker;
+pub use crate::memory::{InstanceMemory, SharedMemory};
 pub use crate::module::Module;
-pub use crate::values::{Trap, Val, ValType};
+pub use crate::values::{ExternRef, Trap, Val, ValType};
 
 pub mod data {
-    pub use wasmparser::{FuncType, Type};
+    pub use wasmparser::{FuncType, Operator, Type};
 }
 
 use crate::eval::{eval as eval_internal, BytecodeCache, EvalSource};
-use crate::values::get_default_value;
+use crate::values::{get_default_value, RootScope, Slot};
 
+mod const_expr;
+mod error;
 mod eval;
 mod externals;
 mod func;
 mod global;
 mod instance;
+mod linker;
 mod memory;
 mod module;
 mod table;
@@ -33,6 +42,8 @@ pub enum TrapOrParserError {
     ParserError(wasmparser::BinaryReaderError),
 }
 
+/// Evaluates the function body `code`. The `returns` shall be initialized
+/// with values of the result types, which are replaced by the results.
 pub fn eval(
     ctx: &dyn EvalContext,
     params: &[Val],
@@ -41,9 +52,9 @@ pub fn eval(
     eval_stack_size: usize,
 ) -> Result<(), TrapOrParserError> {
     use wasmparser::FunctionBody;
-    let code: &'static [u8] = unsafe { std::slice::from_raw_parts(code.as_ptr(), code.len()) };
     let body = FunctionBody::new(0, code);
-    let mut stack = vec![Default::default(); eval_stack_size];
+    let _scope = RootScope::enter();
+    let mut stack = vec![Slot::default(); eval_stack_size];
 
     let mut non_params = Vec::new();
     for i in body
@@ -51,9 +62,9 @@ pub fn eval(
         .map_err(|e| TrapOrParserError::ParserError(e))?
     {
         let (count, ty) = i.map_err(|e| TrapOrParserError::ParserError(e))?;
-        let val = get_default_value(ValType::from(ty));
+        let val = Slot::from_val(&get_default_value(ValType::from(ty)));
         for _ in 0..count {
-            non_params.push(val.clone());
+            non_params.push(val);
         }
     }
 
@@ -64,18 +75,19 @@ pub fn eval(
         }
     }
 
-    let code_reader = body
-        .get_operators_reader()
-        .map_err(|e| TrapOrParserError::ParserError(e))?;
-    let bytecode_cache = BytecodeCache::new(code_reader, ctx, returns.len());
+    let bytecode_cache = BytecodeCache::new(&body, ctx, returns.len());
     let source = S(bytecode_cache);
 
     let locals_len = params.len() + non_params.len();
-    stack[..params.len()].clone_from_slice(params);
-    stack[params.len()..locals_len].clone_from_slice(&non_params);
+    for (slot, param) in stack.iter_mut().zip(params) {
+        *slot = Slot::from_val(param);
+    }
+    stack[params.len()..locals_len].copy_from_slice(&non_params);
     eval_internal(ctx, &source, returns.len(), &mut stack, locals_len)
         .map_err(|e| TrapOrParserError::Trap(e))?;
 
-    returns.clone_from_slice(&stack[0..returns.len()]);
+    for (result, slot) in returns.iter_mut().zip(&stack) {
+        *result = slot.to_val(result.ty());
+    }
     Ok(())
 }
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use crate::error::{LinkError, ModuleError};
+use crate::externals::External;
+use crate::instance::Instance;
+use crate::module::Module;
+
+/// Resolves module imports by their `(module, field)` names.
+pub struct Linker {
+    definitions: HashMap<(String, String), External>,
+    allow_shadowing: bool,
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker {
+            definitions: HashMap::new(),
+            allow_shadowing: false,
+        }
+    }
+
+    /// When enabled, defining an already registered name replaces the
+    /// previous definition instead of failing.
+    pub fn allow_shadowing(&mut self, allow: bool) -> &mut Linker {
+        self.allow_shadowing = allow;
+        self
+    }
+
+    pub fn define(
+        &mut self,
+        module: &str,
+        field: &str,
+        external: External,
+    ) -> Result<&mut Linker, ModuleError> {
+        let key = (module.to_string(), field.to_string());
+        if !self.allow_shadowing && self.definitions.contains_key(&key) {
+            return Err(LinkError::DuplicateDefinition {
+                module: key.0,
+                field: key.1,
+            }
+            .into());
+        }
+        self.definitions.insert(key, external);
+        Ok(self)
+    }
+
+    /// Defines all exports of the instance under the `module` name.
+    pub fn define_instance(
+        &mut self,
+        module: &str,
+        instance: &Instance,
+    ) -> Result<&mut Linker, ModuleError> {
+        for (field, external) in instance.iter_exports() {
+            self.define(module, field, external.clone())?;
+        }
+        Ok(self)
+    }
+
+    pub fn get(&self, module: &str, field: &str) -> Option<&External> {
+        self.definitions
+            .get(&(module.to_string(), field.to_string()))
+    }
+
+    pub fn resolve_imports(&self, module: &Module) -> Result<Vec<External>, ModuleError> {
+        module
+            .imports()
+            .into_iter()
+            .map(
+                |(module_name, field, ty)| match self.get(&module_name, &field) {
+                    Some(external) => Ok(external.clone()),
+                    None => Err(LinkError::UnknownImport {
+                        module: module_name,
+                        field,
+                        expected: ty,
+                    }
+                    .into()),
+                },
+            )
+            .collect()
+    }
+
+    pub fn instantiate(&self, module: &Module) -> Result<Instance, ModuleError> {
+        let imports = self.resolve_imports(module)?;
+        Instance::new(module, &imports)
+    }
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Linker::new()
+    }
+}
@@ -1,58 +1,405 @@
-use crate::externals::Memory;
+use crate::externals::{Limits64, Memory, MemoryOutOfBounds, MemoryType};
+use std::alloc::{alloc_zeroed, dealloc, Layout};
 use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut, Range};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use wasmparser::MemoryImmediate;
 
-const PAGE_SIZE: usize = 0x10000;
+pub(crate) const PAGE_SIZE: usize = 0x10000;
+const MAX_PAGES: u64 = 0x10000;
+const MAX_PAGES64: u64 = 1 << 48;
+
+/// The content of `InstanceMemory`. It is kept in words, so the aligned
+/// addresses of the atomic accesses are aligned pointers.
+struct Buffer(Vec<u64>);
+
+const WORD_SIZE: usize = size_of::<u64>();
+
+impl Buffer {
+    fn new(len: usize) -> Buffer {
+        Buffer(vec![0; len / WORD_SIZE])
+    }
+
+    /// Resizes the buffer to `len` bytes, returns false when it cannot be
+    /// allocated.
+    fn try_resize(&mut self, len: usize) -> bool {
+        let len = len / WORD_SIZE;
+        if len > self.0.len() && self.0.try_reserve_exact(len - self.0.len()).is_err() {
+            return false;
+        }
+        self.0.resize(len, 0);
+        true
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(self.0.as_ptr() as *const u8, self.0.len() * WORD_SIZE)
+        }
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, self.0.len() * WORD_SIZE)
+        }
+    }
+}
 
 pub struct InstanceMemory {
-    buffer: RefCell<Vec<u8>>,
-    max: usize,
+    buffer: RefCell<Buffer>,
+    max: Option<usize>,
+    memory64: bool,
 }
 
 impl InstanceMemory {
-    pub fn new(min: usize, max: usize) -> InstanceMemory {
+    pub fn new(min: usize, max: Option<usize>) -> InstanceMemory {
         InstanceMemory {
-            buffer: RefCell::new(vec![0; min * PAGE_SIZE]),
+            buffer: RefCell::new(Buffer::new(min * PAGE_SIZE)),
             max,
+            memory64: false,
+        }
+    }
+
+    /// Creates a memory addressed by `i64` values.
+    pub fn new64(min: usize, max: Option<usize>) -> InstanceMemory {
+        InstanceMemory {
+            memory64: true,
+            ..InstanceMemory::new(min, max)
         }
     }
+
+    /// Same as `new`/`new64`, but returns `None` when the initial
+    /// content cannot be allocated.
+    pub(crate) fn try_new(min: u64, max: Option<u64>, memory64: bool) -> Option<InstanceMemory> {
+        let len = usize::try_from(min.checked_mul(PAGE_SIZE as u64)?).ok()?;
+        let mut buffer = Buffer(Vec::new());
+        if !buffer.try_resize(len) {
+            return None;
+        }
+        Some(InstanceMemory {
+            buffer: RefCell::new(buffer),
+            max: max.map(|max| max.min(usize::MAX as u64) as usize),
+            memory64,
+        })
+    }
+
+    fn max_pages(&self) -> u64 {
+        let limit = if self.memory64 {
+            MAX_PAGES64
+        } else {
+            MAX_PAGES
+        };
+        self.max.map_or(limit, |max| limit.min(max as u64))
+    }
 }
 
+/// Returns the start of `size` bytes at the effective address, if they fit
+/// into the memory of `len` bytes.
 #[inline]
-fn combine_offsets(memarg: &MemoryImmediate, offset: u32) -> usize {
-    memarg.offset as usize + offset as usize
+fn effective_address(
+    memarg: &MemoryImmediate,
+    offset: u64,
+    size: u32,
+    len: usize,
+) -> Option<usize> {
+    let start = offset.checked_add(memarg.offset as u64)?;
+    if start.checked_add(size as u64)? > len as u64 {
+        return None;
+    }
+    Some(start as usize)
+}
+
+/// Returns the `offset..offset + len` range, if it fits into `len` bytes.
+#[inline]
+fn byte_range(offset: u64, len: u64, memory_len: usize) -> Result<Range<usize>, MemoryOutOfBounds> {
+    match offset.checked_add(len) {
+        Some(end) if end <= memory_len as u64 => Ok(offset as usize..end as usize),
+        _ => Err(MemoryOutOfBounds),
+    }
 }
 
 impl Memory for InstanceMemory {
-    fn current(&self) -> u32 {
-        (self.buffer.borrow().len() / PAGE_SIZE) as u32
+    fn ty(&self) -> MemoryType {
+        MemoryType {
+            limits: Limits64 {
+                initial: self.current(),
+                maximum: self.max.map(|max| max as u64),
+            },
+            shared: false,
+            memory64: self.memory64,
+        }
+    }
+    fn current(&self) -> u64 {
+        (self.buffer.borrow().len() / PAGE_SIZE) as u64
     }
-    fn grow(&self, delta: u32) -> u32 {
+    fn grow(&self, delta: u64) -> u64 {
         let old_len = self.current();
-        let new_len = old_len.checked_add(delta);
-        if new_len.is_none() || new_len.unwrap() as usize > self.max {
+        let new_len = match old_len.checked_add(delta) {
+            Some(new_len) if new_len <= self.max_pages() => new_len,
+            _ => return !0,
+        };
+        let new_len = match usize::try_from(new_len)
+            .ok()
+            .and_then(|n| n.checked_mul(PAGE_SIZE))
+        {
+            Some(new_len) => new_len,
+            None => return !0,
+        };
+        if !self.buffer.borrow_mut().try_resize(new_len) {
             return !0;
         }
-        let new_len = (new_len.unwrap() as usize) * PAGE_SIZE;
-        self.buffer.borrow_mut().resize(new_len, 0);
         old_len
     }
-    fn content_ptr(&self, memarg: &MemoryImmediate, offset: u32, size: u32) -> *const u8 {
-        let offset = combine_offsets(memarg, offset);
-        if offset + size as usize > self.buffer.borrow().len() {
-            return std::ptr::null();
+    fn content_ptr(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *const u8 {
+        let buffer = self.buffer.borrow();
+        match effective_address(memarg, offset, size, buffer.len()) {
+            Some(start) => &buffer[start],
+            None => std::ptr::null(),
         }
-        &self.buffer.borrow()[offset]
     }
-    fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u32, size: u32) -> *mut u8 {
-        let offset = combine_offsets(memarg, offset);
-        if offset + size as usize > self.buffer.borrow().len() {
-            return std::ptr::null_mut();
+    fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *mut u8 {
+        let mut buffer = self.buffer.borrow_mut();
+        let len = buffer.len();
+        match effective_address(memarg, offset, size, len) {
+            Some(start) => &mut buffer[start],
+            None => std::ptr::null_mut(),
         }
-        &mut self.buffer.borrow_mut()[offset]
     }
-    fn clone_from_slice(&self, offset: u32, chunk: &[u8]) {
+    fn clone_from_slice(&self, offset: u64, chunk: &[u8]) {
         let offset = offset as usize;
         self.buffer.borrow_mut()[offset..(offset + chunk.len())].clone_from_slice(chunk);
     }
+    fn copy_within(&self, dst: u64, src: u64, len: u64) -> Result<(), MemoryOutOfBounds> {
+        let mut buffer = self.buffer.borrow_mut();
+        let src = byte_range(src, len, buffer.len())?;
+        let dst = byte_range(dst, len, buffer.len())?;
+        buffer.copy_within(src, dst.start);
+        Ok(())
+    }
+    fn fill(&self, offset: u64, val: u8, len: u64) -> Result<(), MemoryOutOfBounds> {
+        let mut buffer = self.buffer.borrow_mut();
+        let range = byte_range(offset, len, buffer.len())?;
+        buffer[range].iter_mut().for_each(|b| *b = val);
+        Ok(())
+    }
+}
+
+/// Linear memory that can be used by instances on different threads.
+/// The maximum size is reserved up front, so the memory content does not
+/// move when the memory grows.
+#[derive(Clone)]
+pub struct SharedMemory(Arc<SharedMemoryData>);
+
+struct SharedMemoryData {
+    base: NonNull<u8>,
+    capacity: usize,
+    max: usize,
+    pages: AtomicUsize,
+    grow_lock: Mutex<()>,
+    waiters: Mutex<Vec<Waiter>>,
+}
+
+// The memory content is accessed through raw pointers, the same way
+// as the wasm code running on several threads does.
+unsafe impl Send for SharedMemoryData {}
+unsafe impl Sync for SharedMemoryData {}
+
+struct Waiter {
+    address: u64,
+    signal: Arc<(Mutex<bool>, Condvar)>,
+}
+
+/// Result of the `SharedMemory::wait`, as returned by `memory.atomic.wait`.
+pub(crate) enum WaitResult {
+    Ok = 0,
+    NotEqual = 1,
+    TimedOut = 2,
+    /// The wait was interrupted, and the evaluation traps.
+    Interrupted,
+}
+
+/// How long a waiting thread sleeps before it checks for the interrupt.
+const WAIT_INTERRUPT_INTERVAL: Duration = Duration::from_millis(10);
+
+const SHARED_MEMORY_ALIGN: usize = 16;
+
+impl SharedMemory {
+    /// Creates a memory of `min` pages that can grow up to `max` pages.
+    /// Returns `None` when `min` is above `max`, when `max` is above the
+    /// 65536 pages of the 32-bit memories, or when the maximum size cannot
+    /// be reserved.
+    pub fn new(min: usize, max: usize) -> Option<SharedMemory> {
+        if min > max || max as u64 > MAX_PAGES {
+            return None;
+        }
+        let capacity = max.checked_mul(PAGE_SIZE)?;
+        let base = if capacity == 0 {
+            NonNull::dangling()
+        } else {
+            let layout = Layout::from_size_align(capacity, SHARED_MEMORY_ALIGN).ok()?;
+            NonNull::new(unsafe { alloc_zeroed(layout) })?
+        };
+        Some(SharedMemory(Arc::new(SharedMemoryData {
+            base,
+            capacity,
+            max,
+            pages: AtomicUsize::new(min),
+            grow_lock: Mutex::new(()),
+            waiters: Mutex::new(Vec::new()),
+        })))
+    }
+
+    fn len(&self) -> usize {
+        self.0.pages.load(Ordering::SeqCst) * PAGE_SIZE
+    }
+
+    fn ptr(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *mut u8 {
+        match effective_address(memarg, offset, size, self.len()) {
+            Some(start) => unsafe { self.0.base.as_ptr().add(start) },
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    /// Suspends the thread until it is notified at the `address`, until
+    /// the `timeout` expires, or until `interrupted` returns true. It is
+    /// checked every `WAIT_INTERRUPT_INTERVAL`. The `is_expected` check is
+    /// performed atomically with respect to `notify`.
+    pub(crate) fn wait(
+        &self,
+        address: u64,
+        is_expected: impl FnOnce() -> bool,
+        timeout: Option<Duration>,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> WaitResult {
+        let signal = {
+            let mut waiters = self.0.waiters.lock().unwrap();
+            if !is_expected() {
+                return WaitResult::NotEqual;
+            }
+            let signal = Arc::new((Mutex::new(false), Condvar::new()));
+            waiters.push(Waiter {
+                address,
+                signal: signal.clone(),
+            });
+            signal
+        };
+
+        let (lock, condvar) = &*signal;
+        let mut notified = lock.lock().unwrap();
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut result = WaitResult::TimedOut;
+        while !*notified {
+            let now = Instant::now();
+            let slice = match deadline {
+                None => WAIT_INTERRUPT_INTERVAL,
+                Some(deadline) if now >= deadline => break,
+                Some(deadline) => (deadline - now).min(WAIT_INTERRUPT_INTERVAL),
+            };
+            notified = condvar.wait_timeout(notified, slice).unwrap().0;
+            if !*notified && interrupted() {
+                result = WaitResult::Interrupted;
+                break;
+            }
+        }
+        if *notified {
+            return WaitResult::Ok;
+        }
+        drop(notified);
+
+        // The waiter can still be notified before it is removed from the list.
+        let mut waiters = self.0.waiters.lock().unwrap();
+        match waiters
+            .iter()
+            .position(|waiter| Arc::ptr_eq(&waiter.signal, &signal))
+        {
+            Some(index) => {
+                waiters.remove(index);
+                result
+            }
+            None => WaitResult::Ok,
+        }
+    }
+
+    /// Wakes up to `count` threads waiting at the `address`, and returns
+    /// the number of woken threads.
+    pub(crate) fn notify(&self, address: u64, count: u32) -> u32 {
+        let mut waiters = self.0.waiters.lock().unwrap();
+        let mut woken = 0;
+        waiters.retain(|waiter| {
+            if woken == count || waiter.address != address {
+                return true;
+            }
+            let (lock, condvar) = &*waiter.signal;
+            *lock.lock().unwrap() = true;
+            condvar.notify_one();
+            woken += 1;
+            false
+        });
+        woken
+    }
+}
+
+impl Drop for SharedMemoryData {
+    fn drop(&mut self) {
+        if self.capacity > 0 {
+            let layout = Layout::from_size_align(self.capacity, SHARED_MEMORY_ALIGN).unwrap();
+            unsafe { dealloc(self.base.as_ptr(), layout) };
+        }
+    }
+}
+
+impl Memory for SharedMemory {
+    fn ty(&self) -> MemoryType {
+        MemoryType {
+            limits: Limits64 {
+                initial: self.current(),
+                maximum: Some(self.0.max as u64),
+            },
+            shared: true,
+            memory64: false,
+        }
+    }
+    fn current(&self) -> u64 {
+        self.0.pages.load(Ordering::SeqCst) as u64
+    }
+    fn grow(&self, delta: u64) -> u64 {
+        let _guard = self.0.grow_lock.lock().unwrap();
+        let old_len = self.0.pages.load(Ordering::SeqCst) as u64;
+        match old_len.checked_add(delta) {
+            Some(new_len) if new_len <= self.0.max as u64 => {
+                self.0.pages.store(new_len as usize, Ordering::SeqCst);
+                old_len
+            }
+            _ => !0,
+        }
+    }
+    fn content_ptr(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *const u8 {
+        self.ptr(memarg, offset, size)
+    }
+    fn content_ptr_mut(&self, memarg: &MemoryImmediate, offset: u64, size: u32) -> *mut u8 {
+        self.ptr(memarg, offset, size)
+    }
+    fn clone_from_slice(&self, offset: u64, chunk: &[u8]) {
+        let offset = offset as usize;
+        assert!(offset + chunk.len() <= self.len());
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                chunk.as_ptr(),
+                self.0.base.as_ptr().add(offset),
+                chunk.len(),
+            );
+        }
+    }
+    fn as_shared(&self) -> Option<&SharedMemory> {
+        Some(self)
+    }
 }
@@ -1,11 +1,12 @@
-use anyhow::{bail, Error};
 use std::pin::Pin;
 use std::sync::Arc;
 use wasmparser::{
     Data, Element, Export, FunctionBody, Global, Import, ImportSectionEntryType, MemoryType, Name,
-    NameSectionReader, Parser, Payload, TableType, TypeDef,
+    NameSectionReader, Parser, Payload, TableType, TypeDef, Validator, WasmFeatures,
 };
 
+use crate::const_expr::lower_const_exprs;
+use crate::error::ModuleError;
 use crate::externals::{self, ExternType, FuncType};
 
 pub(crate) struct ModuleData {
@@ -26,13 +27,64 @@ pub(crate) struct ModuleData {
     pub func_bodies: Box<[FunctionBody<'static>]>,
     pub start_func: Option<u32>,
     pub module_name: Option<String>,
+    pub import_types: Box<[ExternType]>,
+    pub export_types: Box<[ExternType]>,
 }
 
 pub struct Module {
     data: Arc<ModuleData>,
 }
 
-fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, Error> {
+fn supported_features() -> WasmFeatures {
+    WasmFeatures {
+        multi_value: true,
+        threads: true,
+        tail_call: true,
+        memory64: true,
+        multi_memory: true,
+        reference_types: true,
+        simd: true,
+        bulk_memory: true,
+        ..Default::default()
+    }
+}
+
+fn validate_module_data(buf: &[u8]) -> Result<(), ModuleError> {
+    let mut validator = Validator::new();
+    validator.wasm_features(supported_features());
+    let lowered = lower_const_exprs(buf).map_err(|err| exception_handling_error(buf, err))?;
+    validator.validate_all(&lowered.buf).map_err(|err| {
+        let offset = lowered.original_offset(err.offset());
+        exception_handling_error(buf, ModuleError::invalid(err, offset))
+    })
+}
+
+/// The exception handling proposal is not supported: wasmparser does not
+/// decode the tag section, the tag imports and exports and the
+/// try/catch/catch_all/throw/rethrow/delegate opcodes. The modules that
+/// use them are reported as such rather than as malformed or invalid.
+fn exception_handling_error(buf: &[u8], err: ModuleError) -> ModuleError {
+    const TAG_EXTERNAL_KIND: u8 = 4;
+    const EXCEPTION_OPCODES: [u8; 6] = [0x06, 0x07, 0x08, 0x09, 0x18, 0x19];
+    let uses_exception_handling = match &err {
+        ModuleError::Malformed { message, offset } | ModuleError::Invalid { message, offset } => {
+            let byte = buf.get(*offset).copied();
+            message == "invalid section code: 13"
+                || (message == "Invalid external kind" && byte == Some(TAG_EXTERNAL_KIND))
+                || (message.starts_with("Unknown opcode")
+                    && matches!(byte, Some(byte) if EXCEPTION_OPCODES.contains(&byte)))
+        }
+        _ => false,
+    };
+    if uses_exception_handling {
+        ModuleError::Unsupported("exception handling".to_string())
+    } else {
+        err
+    }
+}
+
+fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, ModuleError> {
+    validate_module_data(&buf)?;
     let it = {
         let buf = unsafe { &std::slice::from_raw_parts(buf.as_ptr(), buf.len()) };
         Parser::new(0).parse_all(buf)
@@ -62,8 +114,8 @@ fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, Error> {
                         .into_iter()
                         .map(|ty| match ty {
                             Ok(TypeDef::Func(f)) => Ok(Arc::new(f.into())),
-                            Err(e) => bail!("type error: {:?}", e),
-                            _ => bail!("unsupported typedef"),
+                            Err(e) => Err(e.into()),
+                            _ => Err(ModuleError::Unsupported("typedef".to_string())),
                         })
                         .collect::<Result<Vec<_>, _>>()?,
                 );
@@ -118,8 +170,8 @@ fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, Error> {
                                 imported_globals_map.push(index);
                                 Ok(i)
                             }
-                            Err(e) => bail!("import error: {:?}", e),
-                            _ => bail!("unsupported import"),
+                            Err(e) => Err(e.into()),
+                            _ => Err(ModuleError::Unsupported("import".to_string())),
                         })
                         .collect::<Result<Vec<_>, _>>()?,
                 );
@@ -171,6 +223,7 @@ fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, Error> {
             _ => (),
         }
     }
+
     let types = types.unwrap_or_else(|| vec![]).into_boxed_slice();
     let imports = imports.unwrap_or_else(|| vec![]).into_boxed_slice();
     let exports = exports.unwrap_or_else(|| vec![]).into_boxed_slice();
@@ -185,7 +238,7 @@ fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, Error> {
     let imported_tables_map = imported_tables_map.into_boxed_slice();
     let imported_globals_map = imported_globals_map.into_boxed_slice();
     let imported_func_map = imported_func_map.into_boxed_slice();
-    Ok(ModuleData {
+    let mut module_data = ModuleData {
         buf,
         types,
         imports,
@@ -203,11 +256,81 @@ fn read_module_data(buf: Pin<Box<[u8]>>) -> Result<ModuleData, Error> {
         func_bodies,
         start_func,
         module_name,
-    })
+        import_types: Box::new([]),
+        export_types: Box::new([]),
+    };
+    module_data.import_types = module_data
+        .imports
+        .iter()
+        .map(|import| module_data.import_type(&import.ty))
+        .collect::<Result<_, _>>()?;
+    module_data.export_types = module_data
+        .exports
+        .iter()
+        .map(|export| module_data.export_type(export))
+        .collect::<Result<_, _>>()?;
+    Ok(module_data)
+}
+
+impl ModuleData {
+    fn import_type(&self, import: &ImportSectionEntryType) -> Result<ExternType, ModuleError> {
+        Ok(match import {
+            ImportSectionEntryType::Function(index) => {
+                ExternType::Func((*self.types[*index as usize]).clone())
+            }
+            ImportSectionEntryType::Memory(m) => ExternType::Memory(m.into()),
+            ImportSectionEntryType::Global(g) => ExternType::Global(g.into()),
+            ImportSectionEntryType::Table(t) => ExternType::Table(externals::TableType {
+                limits: t.limits.clone().into(),
+                element: t.element_type.into(),
+            }),
+            _ => return Err(ModuleError::Unsupported(format!("import {:?}", import))),
+        })
+    }
+
+    fn export_type(&self, export: &Export) -> Result<ExternType, ModuleError> {
+        use crate::externals as ext;
+        use wasmparser::ExternalKind::*;
+        let index = export.index as usize;
+        Ok(match export.kind {
+            Function => {
+                if index < self.imported_func_map.len() {
+                    return self.import_type(&self.imports[self.imported_func_map[index]].ty);
+                }
+                let ty = self.func_types[index - self.imported_func_map.len()];
+                ExternType::Func((*self.types[ty as usize]).clone())
+            }
+            Global => {
+                if index < self.imported_globals_map.len() {
+                    return self.import_type(&self.imports[self.imported_globals_map[index]].ty);
+                }
+                let g = &self.globals[index - self.imported_globals_map.len()];
+                ExternType::Global((&g.ty).into())
+            }
+            Table => {
+                if index < self.imported_tables_map.len() {
+                    return self.import_type(&self.imports[self.imported_tables_map[index]].ty);
+                }
+                let t = &self.tables[index - self.imported_tables_map.len()];
+                ExternType::Table(ext::TableType {
+                    limits: t.limits.clone().into(),
+                    element: t.element_type.into(),
+                })
+            }
+            Memory => {
+                if index < self.imported_memories_map.len() {
+                    return self.import_type(&self.imports[self.imported_memories_map[index]].ty);
+                }
+                let m = &self.memories[index - self.imported_memories_map.len()];
+                ExternType::Memory(m.into())
+            }
+            kind => return Err(ModuleError::Unsupported(format!("export {:?}", kind))),
+        })
+    }
 }
 
 impl Module {
-    pub fn new(buf: Box<[u8]>) -> Result<Module, Error> {
+    pub fn new(buf: Box<[u8]>) -> Result<Module, ModuleError> {
         Ok(Module {
             data: Arc::new(read_module_data(Pin::new(buf))?),
         })
@@ -221,11 +344,12 @@ impl Module {
         self.data
             .imports
             .iter()
-            .map(|e| {
+            .zip(self.data.import_types.iter())
+            .map(|(e, ty)| {
                 (
                     e.module.to_string(),
-                    e.field.unwrap().to_string(),
-                    self.from_import_type(&e.ty),
+                    e.field.unwrap_or_default().to_string(),
+                    ty.clone(),
                 )
             })
             .collect::<Vec<_>>()
@@ -235,7 +359,8 @@ impl Module {
         self.data
             .exports
             .iter()
-            .map(|e| (e.field.to_string(), self.from_export_type(e)))
+            .zip(self.data.export_types.iter())
+            .map(|(e, ty)| (e.field.to_string(), ty.clone()))
             .collect::<Vec<_>>()
     }
 
@@ -244,12 +369,7 @@ impl Module {
     }
 
     pub fn memories<'a>(&'a self) -> impl Iterator<Item = externals::MemoryType> + 'a {
-        self.data.memories.iter().map(|m| externals::MemoryType {
-            limits: match m {
-                MemoryType::M32 { limits, .. } => limits.clone().into(),
-                _ => panic!(),
-            },
-        })
+        self.data.memories.iter().map(|m| m.into())
     }
 
     pub fn tables<'a>(&'a self) -> impl Iterator<Item = externals::TableType> + 'a {
@@ -260,98 +380,10 @@ impl Module {
     }
 
     pub fn globals<'a>(&'a self) -> impl Iterator<Item = externals::GlobalType> + 'a {
-        self.data.globals.iter().map(|g| externals::GlobalType {
-            ty: g.ty.content_type.into(),
-        })
+        self.data.globals.iter().map(|g| (&g.ty).into())
     }
 
     pub fn name(&self) -> Option<String> {
         self.data.module_name.clone()
     }
-
-    fn from_import_type(&self, import: &ImportSectionEntryType) -> ExternType {
-        match import {
-            ImportSectionEntryType::Function(index) => {
-                ExternType::Func((*self.data.types[*index as usize]).clone())
-            }
-            ImportSectionEntryType::Memory(m) => ExternType::Memory(externals::MemoryType {
-                limits: match m {
-                    MemoryType::M32 { limits, .. } => limits.clone().into(),
-                    _ => panic!(),
-                },
-            }),
-            ImportSectionEntryType::Global(g) => ExternType::Global(externals::GlobalType {
-                ty: g.content_type.into(),
-            }),
-            ImportSectionEntryType::Table(t) => ExternType::Table(externals::TableType {
-                limits: t.limits.clone().into(),
-                element: t.element_type.into(),
-            }),
-            _ => panic!(),
-        }
-    }
-
-    fn from_export_type(&self, export: &Export) -> ExternType {
-        use crate::externals as ext;
-        use wasmparser::ExternalKind::*;
-        match export.kind {
-            Function => {
-                if (export.index as usize) < self.data.imported_func_map.len() {
-                    self.from_import_type(
-                        &self.data.imports[self.data.imported_func_map[export.index as usize]].ty,
-                    )
-                } else {
-                    let ty = self.data.func_types
-                        [export.index as usize - self.data.imported_func_map.len()];
-                    ExternType::Func((*self.data.types[ty as usize]).clone())
-                }
-            }
-            Global => {
-                if (export.index as usize) < self.data.imported_globals_map.len() {
-                    self.from_import_type(
-                        &self.data.imports[self.data.imported_globals_map[export.index as usize]]
-                            .ty,
-                    )
-                } else {
-                    let g = &self.data.globals
-                        [export.index as usize - self.data.imported_globals_map.len()];
-                    ExternType::Global(ext::GlobalType {
-                        ty: g.ty.content_type.into(),
-                    })
-                }
-            }
-            Table => {
-                if (export.index as usize) < self.data.imported_tables_map.len() {
-                    self.from_import_type(
-                        &self.data.imports[self.data.imported_tables_map[export.index as usize]].ty,
-                    )
-                } else {
-                    let t = &self.data.tables
-                        [export.index as usize - self.data.imported_tables_map.len()];
-                    ExternType::Table(ext::TableType {
-                        limits: t.limits.clone().into(),
-                        element: t.element_type.into(),
-                    })
-                }
-            }
-            Memory => {
-                if (export.index as usize) < self.data.imported_memories_map.len() {
-                    self.from_import_type(
-                        &self.data.imports[self.data.imported_memories_map[export.index as usize]]
-                            .ty,
-                    )
-                } else {
-                    let m = &self.data.memories
-                        [export.index as usize - self.data.imported_memories_map.len()];
-                    ExternType::Memory(ext::MemoryType {
-                        limits: match m {
-                            MemoryType::M32 { limits, .. } => limits.clone().into(),
-                            _ => panic!(),
-                        },
-                    })
-                }
-            }
-            _ => panic!(),
-        }
-    }
 }
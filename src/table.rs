@@ -1,37 +1,104 @@
-use crate::externals::{Func, Table, TableOutOfBounds};
+use crate::externals::{Limits, Table, TableOutOfBounds, TableType};
+use crate::values::{get_default_value, Val, ValType};
 use std::cell::RefCell;
-use std::rc::Rc;
+
+const MAX_TABLE_SIZE: usize = 10_000_000;
 
 pub struct InstanceTable {
-    entries: RefCell<Vec<Option<Rc<dyn Func>>>>,
-    #[allow(dead_code)]
-    max: usize,
+    entries: RefCell<Vec<Val>>,
+    element: ValType,
+    max: Option<usize>,
 }
 
 impl InstanceTable {
-    pub fn new(min: usize, max: usize) -> InstanceTable {
+    pub fn new(element: ValType, min: usize, max: Option<usize>) -> InstanceTable {
         InstanceTable {
-            entries: RefCell::new(vec![None; min]),
+            entries: RefCell::new(vec![get_default_value(element); min]),
+            element,
             max,
         }
     }
 }
 
 impl Table for InstanceTable {
-    fn get_func(&self, index: u32) -> Result<Option<Rc<dyn Func>>, TableOutOfBounds> {
-        if (index as usize) < self.entries.borrow().len() {
-            Ok(self.entries.borrow()[index as usize].clone())
-        } else {
-            Err(TableOutOfBounds)
+    fn ty(&self) -> TableType {
+        TableType {
+            element: self.element,
+            limits: Limits {
+                initial: self.size(),
+                maximum: self.max.map(|max| max as u32),
+            },
+        }
+    }
+
+    fn size(&self) -> u32 {
+        self.entries.borrow().len() as u32
+    }
+
+    fn get(&self, index: u32) -> Result<Val, TableOutOfBounds> {
+        match self.entries.borrow().get(index as usize) {
+            Some(val) => Ok(val.clone()),
+            None => Err(TableOutOfBounds),
+        }
+    }
+
+    fn set(&self, index: u32, val: Val) -> Result<(), TableOutOfBounds> {
+        match self.entries.borrow_mut().get_mut(index as usize) {
+            Some(entry) => {
+                *entry = val;
+                Ok(())
+            }
+            None => Err(TableOutOfBounds),
         }
     }
 
-    fn set_func(&self, index: u32, f: Option<Rc<dyn Func>>) -> Result<(), TableOutOfBounds> {
-        if (index as usize) < self.entries.borrow().len() {
-            self.entries.borrow_mut()[index as usize] = f;
-            Ok(())
+    fn grow(&self, delta: u32, init: Val) -> u32 {
+        let old_len = self.size();
+        let new_len = old_len as usize + delta as usize;
+        if new_len > self.max.unwrap_or(MAX_TABLE_SIZE) {
+            return !0;
+        }
+        self.entries.borrow_mut().resize(new_len, init);
+        old_len
+    }
+
+    fn init(&self, offset: u32, items: &[Val]) -> Result<(), TableOutOfBounds> {
+        let mut entries = self.entries.borrow_mut();
+        let offset = offset as usize;
+        if offset + items.len() > entries.len() {
+            return Err(TableOutOfBounds);
+        }
+        entries[offset..offset + items.len()].clone_from_slice(items);
+        Ok(())
+    }
+
+    fn fill(&self, offset: u32, val: Val, len: u32) -> Result<(), TableOutOfBounds> {
+        let mut entries = self.entries.borrow_mut();
+        let (offset, len) = (offset as usize, len as usize);
+        if offset + len > entries.len() {
+            return Err(TableOutOfBounds);
+        }
+        entries[offset..offset + len]
+            .iter_mut()
+            .for_each(|entry| *entry = val.clone());
+        Ok(())
+    }
+
+    fn copy_within(&self, dst: u32, src: u32, len: u32) -> Result<(), TableOutOfBounds> {
+        let mut entries = self.entries.borrow_mut();
+        let (dst, src, len) = (dst as usize, src as usize, len as usize);
+        if src + len > entries.len() || dst + len > entries.len() {
+            return Err(TableOutOfBounds);
+        }
+        if dst <= src {
+            for i in 0..len {
+                entries[dst + i] = entries[src + i].clone();
+            }
         } else {
-            Err(TableOutOfBounds)
+            for i in (0..len).rev() {
+                entries[dst + i] = entries[src + i].clone();
+            }
         }
+        Ok(())
     }
 }
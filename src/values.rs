@@ -1,10 +1,37 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::externals::Func;
+
+/// Opaque host data referenced by `externref` values.
+#[derive(Clone)]
+pub struct ExternRef(Rc<dyn Any>);
+
+impl ExternRef {
+    pub fn new<T: Any>(data: T) -> Self {
+        ExternRef(Rc::new(data))
+    }
+
+    pub fn data(&self) -> &dyn Any {
+        &*self.0
+    }
+
+    pub fn ptr_eq(a: &ExternRef, b: &ExternRef) -> bool {
+        Rc::ptr_eq(&a.0, &b.0)
+    }
+}
+
 #[derive(Clone)]
 pub enum Val {
     I32(i32),
     I64(i64),
     F32(u32),
     F64(u64),
-    Func(Option<std::sync::Arc<dyn crate::externals::Func>>),
+    V128(u128),
+    Func(Option<Rc<dyn Func>>),
+    ExternRef(Option<ExternRef>),
 }
 
 impl std::fmt::Debug for Val {
@@ -14,7 +41,10 @@ impl std::fmt::Debug for Val {
             Val::I64(i) => write!(f, "I64({})", i),
             Val::F32(u) => write!(f, "F32({:08x})", u),
             Val::F64(u) => write!(f, "F64({:016x})", u),
-            Val::Func(_) => write!(f, "Func"),
+            Val::V128(u) => write!(f, "V128({:032x})", u),
+            Val::Func(None) | Val::ExternRef(None) => write!(f, "Null"),
+            Val::Func(Some(_)) => write!(f, "Func"),
+            Val::ExternRef(Some(_)) => write!(f, "ExternRef"),
         }
     }
 }
@@ -26,10 +56,16 @@ impl Val {
             Val::I64(_) => ValType::I64,
             Val::F32(_) => ValType::F32,
             Val::F64(_) => ValType::F64,
+            Val::V128(_) => ValType::V128,
             Val::Func(_) => ValType::FuncRef,
+            Val::ExternRef(_) => ValType::ExternRef,
         }
     }
 
+    pub fn is_null(&self) -> bool {
+        matches!(self, Val::Func(None) | Val::ExternRef(None))
+    }
+
     pub fn i32(self) -> Option<i32> {
         if let Val::I32(val) = self {
             Some(val)
@@ -61,6 +97,14 @@ impl Val {
             None
         }
     }
+
+    pub fn v128(self) -> Option<u128> {
+        if let Val::V128(val) = self {
+            Some(val)
+        } else {
+            None
+        }
+    }
 }
 
 impl Default for Val {
@@ -69,13 +113,320 @@ impl Default for Val {
     }
 }
 
+/// An untagged value of the interpreter stack. The module is validated,
+/// so the value types are known statically and only the host boundaries
+/// need them to convert the slots from and to `Val`. Integers are stored
+/// zero-extended, floats as their bits, and references as an index in
+/// the roots of the thread, where `0` is null.
+#[derive(Clone, Copy, Default)]
+pub struct Slot(u128);
+
+impl Slot {
+    #[inline]
+    pub(crate) fn from_i32(val: i32) -> Slot {
+        Slot(val as u32 as u128)
+    }
+
+    #[inline]
+    pub(crate) fn from_i64(val: i64) -> Slot {
+        Slot(val as u64 as u128)
+    }
+
+    #[inline]
+    pub(crate) fn from_f32(val: u32) -> Slot {
+        Slot(val as u128)
+    }
+
+    #[inline]
+    pub(crate) fn from_f64(val: u64) -> Slot {
+        Slot(val as u128)
+    }
+
+    #[inline]
+    pub(crate) fn from_v128(val: u128) -> Slot {
+        Slot(val)
+    }
+
+    #[inline]
+    pub(crate) fn i32(self) -> i32 {
+        self.0 as i32
+    }
+
+    #[inline]
+    pub(crate) fn i64(self) -> i64 {
+        self.0 as i64
+    }
+
+    #[inline]
+    pub(crate) fn f32(self) -> u32 {
+        self.0 as u32
+    }
+
+    #[inline]
+    pub(crate) fn f64(self) -> u64 {
+        self.0 as u64
+    }
+
+    #[inline]
+    pub(crate) fn v128(self) -> u128 {
+        self.0
+    }
+
+    /// Returns the memory address: `i32` addresses are zero-extended, so
+    /// the same bits work for the `i64` addresses of memory64.
+    #[inline]
+    pub(crate) fn address(self) -> u64 {
+        self.0 as u64
+    }
+
+    #[inline]
+    pub(crate) fn is_null(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Stores the value, the references are added to the roots of the
+    /// innermost `RootScope`.
+    pub(crate) fn from_val(val: &Val) -> Slot {
+        match *val {
+            Val::I32(val) => Slot::from_i32(val),
+            Val::I64(val) => Slot::from_i64(val),
+            Val::F32(val) => Slot::from_f32(val),
+            Val::F64(val) => Slot::from_f64(val),
+            Val::V128(val) => Slot::from_v128(val),
+            Val::Func(None) | Val::ExternRef(None) => Slot(0),
+            Val::Func(Some(ref f)) => Slot::root(Rc::as_ptr(f) as *const u8 as usize, val),
+            Val::ExternRef(Some(ref r)) => Slot::root(Rc::as_ptr(&r.0) as *const u8 as usize, val),
+        }
+    }
+
+    fn root(key: usize, val: &Val) -> Slot {
+        let level = ROOT_LEVEL.with(Cell::get);
+        debug_assert!(level > 0, "references outside of a root scope");
+        ROOTS.with(|roots| Slot(roots.borrow_mut().add(key, val, level) as u128))
+    }
+
+    pub(crate) fn to_val(self, ty: ValType) -> Val {
+        match ty {
+            ValType::I32 => Val::I32(self.i32()),
+            ValType::I64 => Val::I64(self.i64()),
+            ValType::F32 => Val::F32(self.f32()),
+            ValType::F64 => Val::F64(self.f64()),
+            ValType::V128 => Val::V128(self.v128()),
+            ValType::FuncRef | ValType::ExternRef if self.is_null() => get_default_value(ty),
+            ValType::FuncRef | ValType::ExternRef => {
+                ROOTS.with(|roots| match roots.borrow().refs[self.0 as usize - 1] {
+                    Some(ref root) => root.val.clone(),
+                    None => unreachable!("released root"),
+                })
+            }
+        }
+    }
+}
+
+/// A reference of the slots, kept alive by a `RootScope`.
+struct Root {
+    val: Val,
+    key: usize,
+    /// The level of the scope it belongs to.
+    level: usize,
+    marked: bool,
+}
+
+/// The roots that belong to a scope.
+struct Level {
+    level: usize,
+    indices: Vec<u32>,
+    /// The number of the roots above which the unused ones are collected.
+    limit: usize,
+}
+
+const MIN_ROOTS_LIMIT: usize = 64;
+
+/// The references of the slots, deduplicated by their address. A slot of
+/// a reference holds its index in `refs`, plus one.
+#[derive(Default)]
+struct Roots {
+    refs: Vec<Option<Root>>,
+    free: Vec<u32>,
+    indices: HashMap<usize, u32>,
+    levels: Vec<Level>,
+}
+
+impl Roots {
+    fn add(&mut self, key: usize, val: &Val, level: usize) -> u32 {
+        if let Some(&index) = self.indices.get(&key) {
+            return index;
+        }
+        let root = Root {
+            val: val.clone(),
+            key,
+            level,
+            marked: false,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.refs[index as usize - 1] = Some(root);
+                index
+            }
+            None => {
+                self.refs.push(Some(root));
+                self.refs.len() as u32
+            }
+        };
+        self.indices.insert(key, index);
+        self.level_mut(level).indices.push(index);
+        index
+    }
+
+    /// Returns the roots of the `level`, the innermost one with roots.
+    fn level_mut(&mut self, level: usize) -> &mut Level {
+        if self.levels.last().map(|last| last.level) != Some(level) {
+            self.levels.push(Level {
+                level,
+                indices: vec![],
+                limit: MIN_ROOTS_LIMIT,
+            });
+            OWNER_LEVEL.with(|owner| owner.set(level));
+        }
+        let last = self.levels.last_mut().unwrap();
+        if last.indices.len() > last.limit {
+            COLLECT_ROOTS.with(|collect| collect.set(true));
+        }
+        last
+    }
+
+    /// Marks the roots of the `level` the `slots` may refer to. The slots
+    /// of the numbers are not told apart from the references.
+    fn mark(&mut self, level: usize, slots: &[Slot]) {
+        for slot in slots {
+            if slot.0 == 0 || slot.0 > self.refs.len() as u128 {
+                continue;
+            }
+            if let Some(ref mut root) = self.refs[slot.0 as usize - 1] {
+                if root.level == level {
+                    root.marked = true;
+                }
+            }
+        }
+    }
+
+    /// Releases the unmarked roots of the innermost level with roots. The
+    /// marked ones are moved to the `keep` level, and their marks cleared.
+    fn sweep(&mut self, keep: usize) -> (Vec<u32>, Vec<Val>) {
+        let level = self.levels.pop().unwrap();
+        let mut kept = vec![];
+        let mut released = vec![];
+        for index in level.indices {
+            let slot = &mut self.refs[index as usize - 1];
+            let root = slot.as_mut().unwrap();
+            if root.marked {
+                root.marked = false;
+                root.level = keep;
+                kept.push(index);
+            } else {
+                let root = slot.take().unwrap();
+                self.indices.remove(&root.key);
+                self.free.push(index);
+                released.push(root.val);
+            }
+        }
+        OWNER_LEVEL.with(|owner| owner.set(self.levels.last().map_or(0, |last| last.level)));
+        (kept, released)
+    }
+
+    /// Releases the roots of the `level`, except the ones of the `slots`,
+    /// which are moved to the `keep` level.
+    fn release(&mut self, level: usize, slots: &[Slot], keep: usize) -> Vec<Val> {
+        self.mark(level, slots);
+        let (kept, released) = self.sweep(keep);
+        if !kept.is_empty() {
+            let level = self.level_mut(keep);
+            level.indices.extend(kept);
+            // The limit follows the roots in use, so that the collections
+            // stay rare.
+            level.limit = level.limit.max(2 * level.indices.len());
+        }
+        released
+    }
+}
+
+thread_local! {
+    static ROOTS: RefCell<Roots> = RefCell::new(Roots::default());
+    /// The level of the innermost scope.
+    static ROOT_LEVEL: Cell<usize> = const { Cell::new(0) };
+    /// The level of the innermost scope with roots, `0` without roots.
+    static OWNER_LEVEL: Cell<usize> = const { Cell::new(0) };
+    /// Set when a scope has more roots than its limit.
+    static COLLECT_ROOTS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Releases the roots of the innermost scope, except the ones of the
+/// slots, outside of the borrow, since dropping a reference may run the
+/// host code.
+fn release_roots(slots: &[Slot], keep: usize) {
+    let level = ROOT_LEVEL.with(Cell::get);
+    if OWNER_LEVEL.with(Cell::get) != level {
+        return;
+    }
+    let released = ROOTS.with(|roots| roots.borrow_mut().release(level, slots, keep));
+    drop(released);
+}
+
+/// Keeps the references stored in the slots alive. The scopes are entered
+/// where the host values are converted to slots, and for the frames of the
+/// instance functions. The roots of a scope are released when it ends,
+/// except the ones of its results, which move to the enclosing scope.
+pub(crate) struct RootScope;
+
+impl RootScope {
+    pub(crate) fn enter() -> RootScope {
+        ROOT_LEVEL.with(|level| level.set(level.get() + 1));
+        RootScope
+    }
+
+    /// Ends the scope, the roots of the `results` are moved to the
+    /// enclosing scope.
+    pub(crate) fn leave(self, results: &[Slot]) {
+        let level = ROOT_LEVEL.with(Cell::get);
+        release_roots(results, level - 1);
+    }
+
+    /// Releases the roots of the scope, except the ones of the `live` slots.
+    pub(crate) fn retain(&self, live: &[Slot]) {
+        let level = ROOT_LEVEL.with(Cell::get);
+        release_roots(live, level);
+    }
+}
+
+impl Drop for RootScope {
+    fn drop(&mut self) {
+        release_roots(&[], 0);
+        ROOT_LEVEL.with(|level| level.set(level.get() - 1));
+    }
+}
+
+/// Releases the roots of the innermost scope the `live` slots do not refer
+/// to, once there are more of them than its limit. The interpreter calls it
+/// in the loops, the scopes of the instance functions would keep the roots
+/// of the repeated host calls until their end otherwise.
+#[inline]
+pub(crate) fn collect_roots(live: &[Slot]) {
+    if COLLECT_ROOTS.with(Cell::get) {
+        COLLECT_ROOTS.with(|collect| collect.set(false));
+        let level = ROOT_LEVEL.with(Cell::get);
+        release_roots(live, level);
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ValType {
     I32,
     I64,
     F32,
     F64,
+    V128,
     FuncRef,
+    ExternRef,
 }
 
 impl From<wasmparser::Type> for ValType {
@@ -86,7 +437,9 @@ impl From<wasmparser::Type> for ValType {
             I64 => ValType::I64,
             F32 => ValType::F32,
             F64 => ValType::F64,
+            V128 => ValType::V128,
             FuncRef => ValType::FuncRef,
+            ExternRef => ValType::ExternRef,
             _ => unimplemented!("From<wasmparser::Type>"),
         }
     }
@@ -103,6 +456,14 @@ pub enum TrapKind {
     IntegerOverflow,
     Uninitialized,
     UndefinedElement,
+    TableOutOfBounds,
+    StackOverflow,
+    OutOfFuel,
+    Interrupted,
+    UnalignedAtomic,
+    ExpectedSharedMemory,
+    ArgumentMismatch,
+    DroppedInstance,
     User(String),
 }
 
@@ -133,6 +494,18 @@ impl std::fmt::Display for Trap {
                 TrapKind::IntegerOverflow => "integer overflow".to_string(),
                 TrapKind::Uninitialized => "uninitialized element".to_string(),
                 TrapKind::UndefinedElement => "undefined element".to_string(),
+                TrapKind::TableOutOfBounds => "out of bounds table access".to_string(),
+                TrapKind::StackOverflow => "call stack exhausted".to_string(),
+                TrapKind::OutOfFuel => "all fuel consumed".to_string(),
+                TrapKind::Interrupted => "interrupted".to_string(),
+                TrapKind::UnalignedAtomic => "unaligned atomic".to_string(),
+                TrapKind::ExpectedSharedMemory => "expected shared memory".to_string(),
+                TrapKind::ArgumentMismatch => {
+                    "arguments or results do not match the function type".to_string()
+                }
+                TrapKind::DroppedInstance => {
+                    "function of a dropped instance was called".to_string()
+                }
                 TrapKind::User(ref msg) => format!("user trap: {}", msg),
             }
         )
@@ -147,6 +520,8 @@ pub fn get_default_value(ty: ValType) -> Val {
         ValType::I64 => Val::I64(0),
         ValType::F32 => Val::F32(0),
         ValType::F64 => Val::F64(0),
+        ValType::V128 => Val::V128(0),
         ValType::FuncRef => Val::Func(None),
+        ValType::ExternRef => Val::ExternRef(None),
     }
 }
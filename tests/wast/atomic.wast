@@ -0,0 +1,65 @@
+(module
+  (memory (export "mem") 1 1 shared)
+  (func (export "load8") (param i32) (result i32) (i32.atomic.load8_u (local.get 0)))
+  (func (export "load") (param i32) (result i32) (i32.atomic.load (local.get 0)))
+  (func (export "load64") (param i32) (result i64) (i64.atomic.load (local.get 0)))
+  (func (export "store") (param i32 i32) (i32.atomic.store (local.get 0) (local.get 1)))
+  (func (export "store64") (param i32 i64) (i64.atomic.store (local.get 0) (local.get 1)))
+  (func (export "add") (param i32 i32) (result i32) (i32.atomic.rmw.add (local.get 0) (local.get 1)))
+  (func (export "sub8") (param i32 i32) (result i32) (i32.atomic.rmw8.sub_u (local.get 0) (local.get 1)))
+  (func (export "xchg16") (param i32 i64) (result i64) (i64.atomic.rmw16.xchg_u (local.get 0) (local.get 1)))
+  (func (export "cmpxchg") (param i32 i32 i32) (result i32)
+    (i32.atomic.rmw.cmpxchg (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "cmpxchg8") (param i32 i64 i64) (result i64)
+    (i64.atomic.rmw8.cmpxchg_u (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "notify") (param i32 i32) (result i32)
+    (memory.atomic.notify (local.get 0) (local.get 1)))
+  (func (export "wait32") (param i32 i32 i64) (result i32)
+    (memory.atomic.wait32 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "wait64") (param i32 i64 i64) (result i32)
+    (memory.atomic.wait64 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "fence") (atomic.fence))
+)
+
+(invoke "store" (i32.const 0) (i32.const 0x12345678))
+(assert_return (invoke "load8" (i32.const 0)) (i32.const 0x78))
+(assert_return (invoke "add" (i32.const 0) (i32.const 1)) (i32.const 0x12345678))
+(assert_return (invoke "load" (i32.const 0)) (i32.const 0x12345679))
+(assert_return (invoke "sub8" (i32.const 0) (i32.const 0x7a)) (i32.const 0x79))
+(assert_return (invoke "load" (i32.const 0)) (i32.const 0x123456ff))
+(assert_return (invoke "xchg16" (i32.const 0) (i64.const 0x11112222)) (i64.const 0x56ff))
+(assert_return (invoke "load" (i32.const 0)) (i32.const 0x12342222))
+(assert_return (invoke "cmpxchg" (i32.const 0) (i32.const 0) (i32.const 1)) (i32.const 0x12342222))
+(assert_return (invoke "cmpxchg" (i32.const 0) (i32.const 0x12342222) (i32.const 1)) (i32.const 0x12342222))
+(assert_return (invoke "load" (i32.const 0)) (i32.const 1))
+(assert_return (invoke "cmpxchg8" (i32.const 0) (i64.const 0x101) (i64.const 0x1ff)) (i64.const 1))
+(assert_return (invoke "load" (i32.const 0)) (i32.const 0xff))
+(invoke "store64" (i32.const 8) (i64.const -1))
+(assert_return (invoke "load64" (i32.const 8)) (i64.const -1))
+(invoke "fence")
+
+(assert_trap (invoke "load" (i32.const 1)) "unaligned atomic")
+(assert_trap (invoke "load64" (i32.const 4)) "unaligned atomic")
+(assert_trap (invoke "load" (i32.const 65536)) "out of bounds memory access")
+(assert_trap (invoke "notify" (i32.const 2) (i32.const 1)) "unaligned atomic")
+
+(assert_return (invoke "notify" (i32.const 0) (i32.const 1)) (i32.const 0))
+(assert_return (invoke "wait32" (i32.const 0) (i32.const 0) (i64.const -1)) (i32.const 1))
+(assert_return (invoke "wait32" (i32.const 0) (i32.const 0xff) (i64.const 1000)) (i32.const 2))
+(assert_return (invoke "wait64" (i32.const 8) (i64.const -1) (i64.const 0)) (i32.const 2))
+
+(module
+  (memory 1)
+  (func (export "wait") (param i32 i32 i64) (result i32)
+    (memory.atomic.wait32 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "notify") (param i32 i32) (result i32)
+    (memory.atomic.notify (local.get 0) (local.get 1)))
+  (func (export "add") (param i32 i32) (result i32) (i32.atomic.rmw.add (local.get 0) (local.get 1)))
+)
+
+(assert_return (invoke "add" (i32.const 0) (i32.const 1)) (i32.const 0))
+(assert_return (invoke "add" (i32.const 0) (i32.const 1)) (i32.const 1))
+(assert_return (invoke "notify" (i32.const 0) (i32.const 1)) (i32.const 0))
+(assert_trap (invoke "wait" (i32.const 0) (i32.const 0) (i64.const 0)) "expected shared memory")
+
+(assert_invalid (module (memory 1 shared)) "shared memory must have maximum")
@@ -0,0 +1,53 @@
+(module
+  (memory (export "mem") 1)
+  (data $p "\01\02\03\04")
+  (data (i32.const 0) "abcdefgh")
+  (func (export "load8") (param i32) (result i32) (i32.load8_u (local.get 0)))
+  (func (export "fill") (param i32 i32 i32) (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy") (param i32 i32 i32) (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "init") (param i32 i32 i32) (memory.init $p (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "init_active") (param i32 i32 i32) (memory.init 1 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "drop") (data.drop $p)))
+
+(invoke "fill" (i32.const 100) (i32.const 0x1ff) (i32.const 3))
+(assert_return (invoke "load8" (i32.const 99)) (i32.const 0))
+(assert_return (invoke "load8" (i32.const 100)) (i32.const 0xff))
+(assert_return (invoke "load8" (i32.const 102)) (i32.const 0xff))
+(assert_return (invoke "load8" (i32.const 103)) (i32.const 0))
+(invoke "fill" (i32.const 65536) (i32.const 1) (i32.const 0))
+(assert_trap (invoke "fill" (i32.const 65537) (i32.const 1) (i32.const 0)) "out of bounds memory access")
+(assert_trap (invoke "fill" (i32.const 65535) (i32.const 7) (i32.const 2)) "out of bounds memory access")
+(assert_return (invoke "load8" (i32.const 65535)) (i32.const 0))
+
+;; Overlapping copies in both directions.
+(invoke "copy" (i32.const 2) (i32.const 0) (i32.const 4))
+(assert_return (invoke "load8" (i32.const 2)) (i32.const 97))
+(assert_return (invoke "load8" (i32.const 5)) (i32.const 100))
+(assert_return (invoke "load8" (i32.const 6)) (i32.const 103))
+(invoke "copy" (i32.const 0) (i32.const 2) (i32.const 4))
+(assert_return (invoke "load8" (i32.const 0)) (i32.const 97))
+(assert_return (invoke "load8" (i32.const 3)) (i32.const 100))
+(invoke "copy" (i32.const 65536) (i32.const 0) (i32.const 0))
+(assert_trap (invoke "copy" (i32.const 0) (i32.const 65537) (i32.const 0)) "out of bounds memory access")
+(assert_trap (invoke "copy" (i32.const 65535) (i32.const 0) (i32.const 2)) "out of bounds memory access")
+(assert_trap (invoke "copy" (i32.const 0) (i32.const 0xffffffff) (i32.const 2)) "out of bounds memory access")
+
+(invoke "init" (i32.const 200) (i32.const 1) (i32.const 3))
+(assert_return (invoke "load8" (i32.const 199)) (i32.const 0))
+(assert_return (invoke "load8" (i32.const 200)) (i32.const 2))
+(assert_return (invoke "load8" (i32.const 202)) (i32.const 4))
+(assert_trap (invoke "init" (i32.const 200) (i32.const 2) (i32.const 3)) "out of bounds memory access")
+(assert_trap (invoke "init" (i32.const 65535) (i32.const 0) (i32.const 2)) "out of bounds memory access")
+(invoke "init" (i32.const 65536) (i32.const 4) (i32.const 0))
+(assert_trap (invoke "init" (i32.const 0) (i32.const 5) (i32.const 0)) "out of bounds memory access")
+
+;; Active segments are dropped after instantiation.
+(invoke "init_active" (i32.const 0) (i32.const 0) (i32.const 0))
+(assert_trap (invoke "init_active" (i32.const 0) (i32.const 0) (i32.const 1)) "out of bounds memory access")
+
+(invoke "drop")
+(invoke "drop")
+(invoke "init" (i32.const 0) (i32.const 0) (i32.const 0))
+(assert_trap (invoke "init" (i32.const 0) (i32.const 0) (i32.const 1)) "out of bounds memory access")
+
+(assert_invalid (module (func (data.drop 0))) "unknown data segment")
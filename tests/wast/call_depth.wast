@@ -0,0 +1,20 @@
+(module
+  (func $d (export "d") (param i32) (result i32)
+    (if (result i32) (i32.eqz (local.get 0)) (then (i32.const 0))
+      (else (i32.add (i32.const 1) (call $d (i32.sub (local.get 0) (i32.const 1))))))))
+(assert_return (invoke "d" (i32.const 100)) (i32.const 100))
+(assert_return (invoke "d" (i32.const 1000)) (i32.const 1000))
+(assert_return (invoke "d" (i32.const 2000)) (i32.const 2000))
+(assert_exhaustion (invoke "d" (i32.const 100000)) "call stack exhausted")
+(module
+  (func $r (export "runaway") (call $r))
+  (func $m1 (export "mutual-runaway") (call $m2))
+  (func $m2 (call $m1))
+  (func $big (export "big") (param i32) (result i32)
+    (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+    (if (result i32) (i32.eqz (local.get 0)) (then (i32.const 0))
+      (else (call $big (i32.sub (local.get 0) (i32.const 1)))))))
+(assert_exhaustion (invoke "runaway") "call stack exhausted")
+(assert_exhaustion (invoke "mutual-runaway") "call stack exhausted")
+(assert_return (invoke "big" (i32.const 100)) (i32.const 0))
+(assert_exhaustion (invoke "big" (i32.const 450)) "call stack exhausted")
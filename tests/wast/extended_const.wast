@@ -0,0 +1,93 @@
+(module $g
+  (global (export "base") i32 (i32.const 100))
+  (global (export "base64") i64 (i64.const 0x1_0000_0000)))
+(register "g" $g)
+
+(module
+  (import "g" "base" (global $base i32))
+  (import "g" "base64" (global $base64 i64))
+  (global $a i32 (i32.add (global.get $base) (i32.const 20)))
+  (global $b i32 (i32.mul (i32.add (global.get $base) (i32.const 20)) (i32.const 2)))
+  (global $c i32 (i32.sub (i32.const 0) (i32.mul (global.get $base) (i32.const 2))))
+  (global $d i64 (i64.add (i64.mul (global.get $base64) (i64.const 3)) (i64.const -1)))
+  (global $e i32 (i32.mul (i32.const 0x7fffffff) (i32.const 2)))
+  (global $f funcref (ref.func $f))
+  (memory 1)
+  (table 4 funcref)
+  (data (offset (i32.add (global.get $base) (i32.const 4))) "\2a")
+  (elem (offset (i32.sub (i32.const 3) (i32.const 1))) $f)
+  (func $f (result i32) (i32.const 7))
+
+  (func (export "a") (result i32) (global.get $a))
+  (func (export "b") (result i32) (global.get $b))
+  (func (export "c") (result i32) (global.get $c))
+  (func (export "d") (result i64) (global.get $d))
+  (func (export "e") (result i32) (global.get $e))
+  (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0)))
+  (func (export "call") (param i32) (result i32) (call_indirect (result i32) (local.get 0)))
+)
+
+(assert_return (invoke "a") (i32.const 120))
+(assert_return (invoke "b") (i32.const 240))
+(assert_return (invoke "c") (i32.const -200))
+(assert_return (invoke "d") (i64.const 0x2_ffff_ffff))
+(assert_return (invoke "e") (i32.const -2))
+(assert_return (invoke "load" (i32.const 104)) (i32.const 42))
+(assert_return (invoke "call" (i32.const 2)) (i32.const 7))
+
+(module
+  (memory i64 1)
+  (data (offset (i64.add (i64.const 8) (i64.const 8))) "\01")
+  (func (export "load") (param i64) (result i32) (i32.load8_u (local.get 0))))
+
+(assert_return (invoke "load" (i64.const 16)) (i32.const 1))
+
+(assert_trap
+  (module
+    (memory 1)
+    (data (offset (i32.mul (i32.const 0x10000) (i32.const 2))) "\01"))
+  "out of bounds memory access")
+
+(assert_invalid
+  (module (global i32 (i32.add (i32.const 1) (i64.const 2))))
+  "type mismatch")
+(assert_invalid
+  (module (global i64 (i32.add (i32.const 1) (i32.const 2))))
+  "type mismatch")
+(assert_invalid
+  (module (global i32 (i32.const 1) (i32.const 2) (i32.add) (i32.const 3)))
+  "type mismatch")
+(assert_invalid
+  (module (global i32 (i32.div_s (i32.const 1) (i32.const 2))))
+  "constant expression required")
+(assert_invalid
+  (module
+    (import "g" "m" (global $m (mut i32)))
+    (global i32 (i32.add (global.get $m) (i32.const 1))))
+  "constant expression required")
+(assert_invalid
+  (module
+    (import "g" "m" (global $m (mut i32)))
+    (memory 1)
+    (data (global.get $m) ""))
+  "constant expression required")
+(assert_invalid
+  (module
+    (global i32 (i32.const 0))
+    (global i32 (i32.add (global.get 0) (i32.const 1))))
+  "unknown global")
+(assert_invalid
+  (module
+    (global i32 (i32.const 0))
+    (global i32 (global.get 0)))
+  "unknown global")
+(assert_invalid
+  (module
+    (global i32 (i32.add (global.get 1) (i32.const 1)))
+    (global i32 (i32.const 0)))
+  "unknown global")
+(assert_invalid
+  (module
+    (memory 1)
+    (data (offset (i64.add (i64.const 0) (i64.const 0))) ""))
+  "type mismatch")
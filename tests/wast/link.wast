@@ -0,0 +1,19 @@
+(module $M
+  (func (export "f") (param i32))
+  (memory (export "mem") 1 5)
+  (table (export "tab") 10 funcref)
+  (global (export "g") i32 (i32.const 3)))
+(register "M" $M)
+(module (import "M" "f" (func (param i32))) (import "M" "mem" (memory 1)) (import "M" "tab" (table 5 funcref)))
+(module (import "M" "mem" (memory 1 5)))
+(module (import "spectest" "memory" (memory 1 2)))
+(assert_unlinkable (module (import "M" "f" (func (param i64)))) "incompatible import type")
+(assert_unlinkable (module (import "M" "f" (func (param i32) (result i32)))) "incompatible import type")
+(assert_unlinkable (module (import "M" "mem" (memory 2))) "incompatible import type")
+(assert_unlinkable (module (import "M" "mem" (memory 1 4))) "incompatible import type")
+(assert_unlinkable (module (import "M" "tab" (table 5 20 funcref))) "incompatible import type")
+(assert_unlinkable (module (import "M" "g" (global i64))) "incompatible import type")
+(assert_unlinkable (module (import "M" "g" (func))) "incompatible import type")
+(assert_unlinkable (module (import "M" "nope" (func))) "unknown import")
+(assert_unlinkable (module (import "nope" "f" (func))) "unknown import")
+(assert_unlinkable (module (import "spectest" "memory" (memory 1 1))) "incompatible import type")
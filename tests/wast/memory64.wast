@@ -0,0 +1,60 @@
+(module
+  (memory (export "mem") i64 1 3)
+  (data (i64.const 8) "\01\02\03\04")
+  (func (export "size") (result i64) (memory.size))
+  (func (export "grow") (param i64) (result i64) (memory.grow (local.get 0)))
+  (func (export "load8") (param i64) (result i32) (i32.load8_u (local.get 0)))
+  (func (export "load") (param i64) (result i32) (i32.load offset=4 (local.get 0)))
+  (func (export "store") (param i64 i64) (i64.store (local.get 0) (local.get 1)))
+  (func (export "load64") (param i64) (result i64) (i64.load (local.get 0)))
+  (func (export "fill") (param i64 i32 i64) (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy") (param i64 i64 i64) (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "v128") (param i64) (result i32)
+    (i32x4.extract_lane 0 (v128.load (local.get 0))))
+)
+
+(assert_return (invoke "size") (i64.const 1))
+(assert_return (invoke "load" (i64.const 4)) (i32.const 0x04030201))
+(assert_return (invoke "v128" (i64.const 8)) (i32.const 0x04030201))
+(assert_return (invoke "load8" (i64.const 65535)) (i32.const 0))
+(assert_trap (invoke "load8" (i64.const 65536)) "out of bounds memory access")
+(assert_trap (invoke "load8" (i64.const 0x100000000)) "out of bounds memory access")
+(assert_trap (invoke "load8" (i64.const -1)) "out of bounds memory access")
+(assert_trap (invoke "load" (i64.const -4)) "out of bounds memory access")
+
+(assert_return (invoke "grow" (i64.const 1)) (i64.const 1))
+(assert_return (invoke "size") (i64.const 2))
+(assert_return (invoke "grow" (i64.const 2)) (i64.const -1))
+(assert_return (invoke "grow" (i64.const 0x1000000000000)) (i64.const -1))
+(assert_return (invoke "grow" (i64.const -1)) (i64.const -1))
+(invoke "store" (i64.const 65536) (i64.const 0x1122334455667788))
+(assert_return (invoke "load64" (i64.const 65536)) (i64.const 0x1122334455667788))
+
+(invoke "fill" (i64.const 100) (i32.const 0xaa) (i64.const 4))
+(assert_return (invoke "load" (i64.const 96)) (i32.const 0xaaaaaaaa))
+(assert_trap (invoke "fill" (i64.const 0x20000) (i32.const 0) (i64.const 1)) "out of bounds memory access")
+(assert_trap (invoke "fill" (i64.const 1) (i32.const 0) (i64.const -1)) "out of bounds memory access")
+(invoke "copy" (i64.const 200) (i64.const 8) (i64.const 4))
+(assert_return (invoke "load" (i64.const 196)) (i32.const 0x04030201))
+(assert_trap (invoke "copy" (i64.const 0) (i64.const -1) (i64.const 2)) "out of bounds memory access")
+
+(module
+  (memory i64 0)
+  (func (export "grow") (param i64) (result i64) (memory.grow (local.get 0)))
+  (func (export "size") (result i64) (memory.size))
+)
+(assert_return (invoke "grow" (i64.const 1)) (i64.const 0))
+(assert_return (invoke "size") (i64.const 1))
+
+(assert_invalid
+  (module (memory i64 1) (func (drop (i32.load (i32.const 0)))))
+  "type mismatch")
+(assert_invalid
+  (module (memory 1) (func (drop (i32.load (i64.const 0)))))
+  "type mismatch")
+
+(module $m32 (memory (export "mem") 1))
+(register "m32" $m32)
+(assert_unlinkable
+  (module (import "m32" "mem" (memory i64 1)))
+  "incompatible import type")
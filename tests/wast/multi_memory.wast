@@ -0,0 +1,79 @@
+(module $a
+  (memory (export "mem") 1)
+  (data (i32.const 0) "\01\02\03\04"))
+(register "a" $a)
+
+(module
+  (import "a" "mem" (memory $m0 1))
+  (memory $m1 1 2)
+  (memory $m2 i64 0)
+  (data (memory $m1) (i32.const 8) "\aa\bb\cc\dd")
+
+  (func (export "load0") (param i32) (result i32) (i32.load $m0 (local.get 0)))
+  (func (export "load1") (param i32) (result i32) (i32.load $m1 (local.get 0)))
+  (func (export "load2") (param i64) (result i32) (i32.load $m2 (local.get 0)))
+  (func (export "store1") (param i32 i32) (i32.store $m1 (local.get 0) (local.get 1)))
+  (func (export "store2") (param i64 i32) (i32.store $m2 (local.get 0) (local.get 1)))
+  (func (export "size0") (result i32) (memory.size $m0))
+  (func (export "size1") (result i32) (memory.size $m1))
+  (func (export "size2") (result i64) (memory.size $m2))
+  (func (export "grow1") (param i32) (result i32) (memory.grow $m1 (local.get 0)))
+  (func (export "grow2") (param i64) (result i64) (memory.grow $m2 (local.get 0)))
+  (func (export "fill1") (param i32 i32 i32) (memory.fill $m1 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy0to1") (param i32 i32 i32)
+    (memory.copy $m1 $m0 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy1to2") (param i64 i32 i32)
+    (memory.copy $m2 $m1 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy2to0") (param i32 i64 i32)
+    (memory.copy $m0 $m2 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy1") (param i32 i32 i32)
+    (memory.copy $m1 $m1 (local.get 0) (local.get 1) (local.get 2)))
+  ;; Alternates the memories to exercise switching between them.
+  (func (export "sum") (result i32)
+    (i32.add
+      (i32.add (i32.load8_u $m0 (i32.const 0)) (i32.load8_u $m1 (i32.const 8)))
+      (i32.add (i32.load8_u $m0 (i32.const 1)) (i32.load8_u $m1 (i32.const 9)))))
+)
+
+(assert_return (invoke "load0" (i32.const 0)) (i32.const 0x04030201))
+(assert_return (invoke "load1" (i32.const 0)) (i32.const 0))
+(assert_return (invoke "load1" (i32.const 8)) (i32.const 0xddccbbaa))
+(assert_return (invoke "sum") (i32.const 0x168))
+(assert_return (invoke "size0") (i32.const 1))
+(assert_return (invoke "size1") (i32.const 1))
+(assert_return (invoke "size2") (i64.const 0))
+(assert_trap (invoke "load2" (i64.const 0)) "out of bounds memory access")
+
+(assert_return (invoke "grow1" (i32.const 1)) (i32.const 1))
+(assert_return (invoke "grow1" (i32.const 1)) (i32.const -1))
+(assert_return (invoke "size1") (i32.const 2))
+(assert_return (invoke "size0") (i32.const 1))
+(assert_return (invoke "grow2" (i64.const 1)) (i64.const 0))
+
+(invoke "fill1" (i32.const 16) (i32.const 0x55) (i32.const 4))
+(assert_return (invoke "load1" (i32.const 16)) (i32.const 0x55555555))
+(assert_return (invoke "load0" (i32.const 16)) (i32.const 0))
+
+(invoke "copy0to1" (i32.const 100) (i32.const 0) (i32.const 4))
+(assert_return (invoke "load1" (i32.const 100)) (i32.const 0x04030201))
+(invoke "copy1to2" (i64.const 200) (i32.const 8) (i32.const 4))
+(assert_return (invoke "load2" (i64.const 200)) (i32.const 0xddccbbaa))
+(invoke "copy2to0" (i32.const 300) (i64.const 200) (i32.const 4))
+(assert_return (invoke "load0" (i32.const 300)) (i32.const 0xddccbbaa))
+(invoke "copy1" (i32.const 10) (i32.const 8) (i32.const 4))
+(assert_return (invoke "load1" (i32.const 8)) (i32.const 0xbbaabbaa))
+(assert_trap (invoke "copy0to1" (i32.const 0) (i32.const 65534) (i32.const 4)) "out of bounds memory access")
+(assert_trap (invoke "copy1to2" (i64.const 65534) (i32.const 0) (i32.const 4)) "out of bounds memory access")
+
+(invoke "store1" (i32.const 131068) (i32.const 7))
+(assert_return (invoke "load1" (i32.const 131068)) (i32.const 7))
+(assert_trap (invoke "load0" (i32.const 65536)) "out of bounds memory access")
+
+(module
+  (import "a" "mem" (memory $m0 1))
+  (func (export "load0") (param i32) (result i32) (i32.load $m0 (local.get 0))))
+(assert_return (invoke "load0" (i32.const 300)) (i32.const 0xddccbbaa))
+
+(assert_invalid
+  (module (memory 1) (func (drop (i32.load 1 (i32.const 0)))))
+  "unknown memory 1")
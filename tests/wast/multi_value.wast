@@ -0,0 +1,112 @@
+(module
+  (type $pair (func (param i32 i32) (result i32 i32)))
+  (type $to_pair (func (result i32 i64)))
+  (table funcref (elem $swap $swap))
+
+  (func $swap (export "swap") (type $pair) (local.get 1) (local.get 0))
+  (func (export "triple") (result i32 i64 f64)
+    (i32.const 1) (i64.const 2) (f64.const 3.5))
+  (func (export "call-swap") (param i32 i32) (result i32 i32)
+    (call $swap (local.get 0) (local.get 1)))
+  (func (export "call-indirect-swap") (param i32 i32) (result i32 i32)
+    (call_indirect (type $pair) (local.get 0) (local.get 1) (i32.const 1)))
+  (func (export "sub-swapped") (param i32 i32) (result i32)
+    (i32.sub (call $swap (local.get 0) (local.get 1))))
+
+  (func (export "block-params") (param i32 i32) (result i32)
+    (local.get 0) (local.get 1)
+    (block (param i32 i32) (result i32) (i32.sub)))
+  (func (export "block-results") (result i32 i64)
+    (block (result i32 i64) (i32.const 7) (i64.const 8)))
+  (func (export "block-br") (param i32) (result i32 i32)
+    (block $b (result i32 i32)
+      (i32.const 1) (i32.const 2)
+      (br_if $b (local.get 0))
+      (drop) (drop)
+      (i32.const 3) (i32.const 4)))
+  (func (export "block-br-extra") (result i32 i32 i32)
+    (i32.const 100)
+    (block $b (result i32 i32)
+      (i32.const 99) (i32.const 1) (i32.const 2)
+      (br $b))
+    (i32.add)
+    (i32.const 5))
+
+  (func (export "fib") (param i32) (result i64)
+    (i64.const 0) (i64.const 1) (local.get 0)
+    (loop $l (param i64 i64 i32) (result i64 i64)
+      (if (param i64 i64) (result i64 i64) (i32.eqz (local.tee 0))
+        (then)
+        (else
+          (call $fib-step)
+          (local.get 0) (i32.const 1) (i32.sub)
+          (br $l))))
+    (drop))
+  (func $fib-step (param i64 i64) (result i64 i64)
+    (local.get 1) (i64.add (local.get 0) (local.get 1)))
+
+  (func (export "if-params") (param i32 i32 i32) (result i32)
+    (local.get 0) (local.get 1)
+    (if (param i32 i32) (result i32) (local.get 2)
+      (then (i32.add))
+      (else (i32.sub))))
+  (func (export "if-no-else") (param i32 i32) (result i32 i32)
+    (local.get 0) (local.get 1)
+    (if (param i32 i32) (result i32 i32) (i32.eqz (local.get 0))
+      (then (drop) (drop) (i32.const 10) (i32.const 20))))
+  (func (export "if-br") (param i32) (result i32 i32)
+    (i32.const 1)
+    (if (param i32) (result i32 i32) (local.get 0)
+      (then (i32.const 2) (br 0) )
+      (else (i32.const 3))))
+
+  (func (export "br-table") (param i32) (result i32 i32)
+    (block $b2 (result i32 i32)
+      (block $b1 (result i32 i32)
+        (block $b0 (result i32 i32)
+          (i32.const 10) (i32.const 20)
+          (br_table $b0 $b1 $b2 (local.get 0)))
+        (i32.add) (i32.const 0)
+        (return))
+      (i32.sub) (i32.const 1)
+      (return)))
+  (func (export "return-multi") (param i32) (result i32 i64)
+    (i32.const 0) (i64.const 0)
+    (if (local.get 0) (then (return (i32.const 1) (i64.const 2))))
+    (drop) (drop)
+    (i32.const 3) (i64.const 4))
+)
+
+(assert_return (invoke "swap" (i32.const 1) (i32.const 2)) (i32.const 2) (i32.const 1))
+(assert_return (invoke "triple") (i32.const 1) (i64.const 2) (f64.const 3.5))
+(assert_return (invoke "call-swap" (i32.const 1) (i32.const 2)) (i32.const 2) (i32.const 1))
+(assert_return (invoke "call-indirect-swap" (i32.const 3) (i32.const 4))
+  (i32.const 4) (i32.const 3))
+(assert_return (invoke "sub-swapped" (i32.const 10) (i32.const 3)) (i32.const -7))
+(assert_return (invoke "block-params" (i32.const 10) (i32.const 3)) (i32.const 7))
+(assert_return (invoke "block-results") (i32.const 7) (i64.const 8))
+(assert_return (invoke "block-br" (i32.const 1)) (i32.const 1) (i32.const 2))
+(assert_return (invoke "block-br" (i32.const 0)) (i32.const 3) (i32.const 4))
+(assert_return (invoke "block-br-extra") (i32.const 100) (i32.const 3) (i32.const 5))
+(assert_return (invoke "fib" (i32.const 0)) (i64.const 0))
+(assert_return (invoke "fib" (i32.const 1)) (i64.const 1))
+(assert_return (invoke "fib" (i32.const 10)) (i64.const 55))
+(assert_return (invoke "if-params" (i32.const 10) (i32.const 3) (i32.const 1)) (i32.const 13))
+(assert_return (invoke "if-params" (i32.const 10) (i32.const 3) (i32.const 0)) (i32.const 7))
+(assert_return (invoke "if-no-else" (i32.const 0) (i32.const 3)) (i32.const 10) (i32.const 20))
+(assert_return (invoke "if-no-else" (i32.const 1) (i32.const 3)) (i32.const 1) (i32.const 3))
+(assert_return (invoke "if-br" (i32.const 1)) (i32.const 1) (i32.const 2))
+(assert_return (invoke "if-br" (i32.const 0)) (i32.const 1) (i32.const 3))
+(assert_return (invoke "br-table" (i32.const 0)) (i32.const 30) (i32.const 0))
+(assert_return (invoke "br-table" (i32.const 1)) (i32.const -10) (i32.const 1))
+(assert_return (invoke "br-table" (i32.const 2)) (i32.const 10) (i32.const 20))
+(assert_return (invoke "br-table" (i32.const 9)) (i32.const 10) (i32.const 20))
+(assert_return (invoke "return-multi" (i32.const 1)) (i32.const 1) (i64.const 2))
+(assert_return (invoke "return-multi" (i32.const 0)) (i32.const 3) (i64.const 4))
+
+(assert_invalid
+  (module (func (result i32 i32) (i32.const 1)))
+  "type mismatch")
+(assert_invalid
+  (module (func (block (param i32) (drop))))
+  "type mismatch")
@@ -0,0 +1,71 @@
+(module
+  (table $e 2 externref)
+  (table $f 3 funcref)
+  (global $g (mut externref) (ref.null extern))
+  (global $h funcref (ref.func $one))
+  (type $r (func (result i32)))
+  (func $one (result i32) (i32.const 1))
+  (func $two (result i32) (i32.const 2))
+  (elem (table $f) (i32.const 0) func $one)
+  (elem declare func $two)
+
+  (func (export "is_null") (param externref) (result i32) (ref.is_null (local.get 0)))
+  (func (export "null_func") (result funcref) (ref.null func))
+  (func (export "get_e") (param i32) (result externref) (table.get $e (local.get 0)))
+  (func (export "set_e") (param i32 externref) (table.set $e (local.get 0) (local.get 1)))
+  (func (export "size_e") (result i32) (table.size $e))
+  (func (export "grow_e") (param i32 externref) (result i32) (table.grow $e (local.get 1) (local.get 0)))
+  (func (export "fill_e") (param i32 externref i32) (table.fill $e (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "size_f") (result i32) (table.size $f))
+  (func (export "call_f") (param i32) (result i32) (call_indirect $f (type $r) (local.get 0)))
+  (func (export "set_two") (param i32) (table.set $f (local.get 0) (ref.func $two)))
+  (func (export "set_global") (table.set $f (i32.const 2) (global.get $h)))
+  (func (export "is_null_f") (param i32) (result i32) (ref.is_null (table.get $f (local.get 0))))
+  (func (export "copy_f_to_f") (table.copy $f $f (i32.const 1) (i32.const 0) (i32.const 1)))
+  (func (export "store") (param externref) (global.set $g (local.get 0)))
+  (func (export "load") (result externref) (global.get $g))
+  (func (export "select") (param externref externref i32) (result externref)
+    (select (result externref) (local.get 0) (local.get 1) (local.get 2))))
+
+(assert_return (invoke "is_null" (ref.null extern)) (i32.const 1))
+(assert_return (invoke "is_null" (ref.extern 1)) (i32.const 0))
+(assert_return (invoke "null_func") (ref.null func))
+(assert_return (invoke "get_e" (i32.const 1)) (ref.null extern))
+(invoke "set_e" (i32.const 1) (ref.extern 7))
+(assert_return (invoke "get_e" (i32.const 1)) (ref.extern 7))
+(assert_trap (invoke "get_e" (i32.const 2)) "out of bounds table access")
+(assert_trap (invoke "set_e" (i32.const 2) (ref.null extern)) "out of bounds table access")
+(assert_return (invoke "size_e") (i32.const 2))
+(assert_return (invoke "grow_e" (i32.const 3) (ref.extern 5)) (i32.const 2))
+(assert_return (invoke "size_e") (i32.const 5))
+(assert_return (invoke "get_e" (i32.const 4)) (ref.extern 5))
+(assert_return (invoke "grow_e" (i32.const 0xffffffff) (ref.null extern)) (i32.const -1))
+(invoke "fill_e" (i32.const 1) (ref.extern 3) (i32.const 3))
+(assert_return (invoke "get_e" (i32.const 0)) (ref.null extern))
+(assert_return (invoke "get_e" (i32.const 3)) (ref.extern 3))
+(assert_return (invoke "get_e" (i32.const 4)) (ref.extern 5))
+(assert_trap (invoke "fill_e" (i32.const 4) (ref.null extern) (i32.const 2)) "out of bounds table access")
+(assert_return (invoke "get_e" (i32.const 4)) (ref.extern 5))
+
+(assert_return (invoke "size_f") (i32.const 3))
+(assert_return (invoke "call_f" (i32.const 0)) (i32.const 1))
+(assert_return (invoke "is_null_f" (i32.const 1)) (i32.const 1))
+(invoke "set_two" (i32.const 1))
+(assert_return (invoke "call_f" (i32.const 1)) (i32.const 2))
+(invoke "set_global")
+(assert_return (invoke "call_f" (i32.const 2)) (i32.const 1))
+(invoke "copy_f_to_f")
+(assert_return (invoke "call_f" (i32.const 1)) (i32.const 1))
+
+(invoke "store" (ref.extern 11))
+(assert_return (invoke "load") (ref.extern 11))
+(assert_return (invoke "select" (ref.extern 1) (ref.extern 2) (i32.const 1)) (ref.extern 1))
+(assert_return (invoke "select" (ref.extern 1) (ref.extern 2) (i32.const 0)) (ref.extern 2))
+
+;; Tables of different element types do not link.
+(module $T (table (export "t") 1 externref))
+(register "T" $T)
+(assert_unlinkable (module (import "T" "t" (table 1 funcref))) "incompatible import type")
+(module (import "T" "t" (table 1 externref)))
+
+(assert_invalid (module (func (ref.func 0))) "undeclared function reference")
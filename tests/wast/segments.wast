@@ -0,0 +1,18 @@
+(assert_trap (module (memory 1) (data (i32.const 65535) "ab")) "out of bounds memory access")
+(assert_trap (module (table 1 funcref) (func) (elem (i32.const 1) 0)) "out of bounds table access")
+(module (memory 1) (data (i32.const 65536) ""))
+(module (table 1 funcref) (elem (i32.const 1)))
+(module $M (memory (export "mem") 1)
+  (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0))))
+(register "M" $M)
+(assert_trap (module (memory (import "M" "mem") 1) (data (i32.const 0) "a") (data (i32.const 65536) "b")) "out of bounds memory access")
+(assert_return (invoke $M "load" (i32.const 0)) (i32.const 97))
+(module $T (table (export "tab") 10 funcref) (type $t (func (result i32)))
+  (func (export "call") (param i32) (result i32) (call_indirect (type $t) (local.get 0))))
+(register "T" $T)
+(assert_trap (module (table (import "T" "tab") 10 funcref) (func $f (result i32) (i32.const 42))
+  (elem (i32.const 7) $f) (elem (i32.const 9) $f $f)) "out of bounds table access")
+(assert_return (invoke $T "call" (i32.const 7)) (i32.const 42))
+(assert_trap (module (table (import "T" "tab") 10 funcref) (memory 1) (func $f (result i32) (i32.const 43))
+  (elem (i32.const 5) $f) (data (i32.const 65536) "b")) "out of bounds memory access")
+(assert_return (invoke $T "call" (i32.const 5)) (i32.const 43))
@@ -0,0 +1,176 @@
+(module
+  (memory 1)
+  (data (i32.const 0) "\00\01\02\03\04\05\06\07\08\09\0a\0b\0c\0d\0e\0f\80\ff\7f\fe")
+  (func (export "load") (param i32) (result v128) (v128.load (local.get 0)))
+  (func (export "store") (param i32 v128) (result v128)
+    (v128.store offset=32 (local.get 0) (local.get 1))
+    (v128.load offset=32 (local.get 0)))
+  (func (export "load8_splat") (param i32) (result v128) (v8x16.load_splat (local.get 0)))
+  (func (export "load32_splat") (param i32) (result v128) (v32x4.load_splat (local.get 0)))
+  (func (export "load8x8_s") (param i32) (result v128) (i16x8.load8x8_s (local.get 0)))
+  (func (export "load8x8_u") (param i32) (result v128) (i16x8.load8x8_u (local.get 0)))
+  (func (export "load32x2_s") (param i32) (result v128) (i64x2.load32x2_s (local.get 0)))
+  (func (export "const") (result v128) (v128.const i32x4 1 2 3 4))
+
+  (func (export "i8x16.splat") (param i32) (result v128) (i8x16.splat (local.get 0)))
+  (func (export "i64x2.splat") (param i64) (result v128) (i64x2.splat (local.get 0)))
+  (func (export "f32x4.splat") (param f32) (result v128) (f32x4.splat (local.get 0)))
+  (func (export "i8x16.extract_lane_s") (param v128) (result i32) (i8x16.extract_lane_s 15 (local.get 0)))
+  (func (export "i8x16.extract_lane_u") (param v128) (result i32) (i8x16.extract_lane_u 15 (local.get 0)))
+  (func (export "i16x8.extract_lane_s") (param v128) (result i32) (i16x8.extract_lane_s 7 (local.get 0)))
+  (func (export "i64x2.extract_lane") (param v128) (result i64) (i64x2.extract_lane 1 (local.get 0)))
+  (func (export "f64x2.extract_lane") (param v128) (result f64) (f64x2.extract_lane 0 (local.get 0)))
+  (func (export "i32x4.replace_lane") (param v128 i32) (result v128) (i32x4.replace_lane 2 (local.get 0) (local.get 1)))
+  (func (export "f32x4.replace_lane") (param v128 f32) (result v128) (f32x4.replace_lane 0 (local.get 0) (local.get 1)))
+
+  (func (export "i8x16.eq") (param v128 v128) (result v128) (i8x16.eq (local.get 0) (local.get 1)))
+  (func (export "i8x16.lt_s") (param v128 v128) (result v128) (i8x16.lt_s (local.get 0) (local.get 1)))
+  (func (export "i8x16.lt_u") (param v128 v128) (result v128) (i8x16.lt_u (local.get 0) (local.get 1)))
+  (func (export "i32x4.ge_s") (param v128 v128) (result v128) (i32x4.ge_s (local.get 0) (local.get 1)))
+  (func (export "f32x4.lt") (param v128 v128) (result v128) (f32x4.lt (local.get 0) (local.get 1)))
+  (func (export "f64x2.ne") (param v128 v128) (result v128) (f64x2.ne (local.get 0) (local.get 1)))
+
+  (func (export "v128.not") (param v128) (result v128) (v128.not (local.get 0)))
+  (func (export "v128.andnot") (param v128 v128) (result v128) (v128.andnot (local.get 0) (local.get 1)))
+  (func (export "v128.bitselect") (param v128 v128 v128) (result v128) (v128.bitselect (local.get 0) (local.get 1) (local.get 2)))
+
+  (func (export "i8x16.abs") (param v128) (result v128) (i8x16.abs (local.get 0)))
+  (func (export "i16x8.neg") (param v128) (result v128) (i16x8.neg (local.get 0)))
+  (func (export "i32x4.any_true") (param v128) (result i32) (i32x4.any_true (local.get 0)))
+  (func (export "i32x4.all_true") (param v128) (result i32) (i32x4.all_true (local.get 0)))
+  (func (export "i8x16.bitmask") (param v128) (result i32) (i8x16.bitmask (local.get 0)))
+  (func (export "i16x8.shl") (param v128 i32) (result v128) (i16x8.shl (local.get 0) (local.get 1)))
+  (func (export "i8x16.shr_s") (param v128 i32) (result v128) (i8x16.shr_s (local.get 0) (local.get 1)))
+  (func (export "i64x2.shr_u") (param v128 i32) (result v128) (i64x2.shr_u (local.get 0) (local.get 1)))
+  (func (export "i8x16.add_saturate_s") (param v128 v128) (result v128) (i8x16.add_saturate_s (local.get 0) (local.get 1)))
+  (func (export "i8x16.add_saturate_u") (param v128 v128) (result v128) (i8x16.add_saturate_u (local.get 0) (local.get 1)))
+  (func (export "i16x8.sub_saturate_s") (param v128 v128) (result v128) (i16x8.sub_saturate_s (local.get 0) (local.get 1)))
+  (func (export "i32x4.add") (param v128 v128) (result v128) (i32x4.add (local.get 0) (local.get 1)))
+  (func (export "i64x2.mul") (param v128 v128) (result v128) (i64x2.mul (local.get 0) (local.get 1)))
+  (func (export "i32x4.min_s") (param v128 v128) (result v128) (i32x4.min_s (local.get 0) (local.get 1)))
+  (func (export "i32x4.max_u") (param v128 v128) (result v128) (i32x4.max_u (local.get 0) (local.get 1)))
+  (func (export "i8x16.avgr_u") (param v128 v128) (result v128) (i8x16.avgr_u (local.get 0) (local.get 1)))
+
+  (func (export "f32x4.add") (param v128 v128) (result v128) (f32x4.add (local.get 0) (local.get 1)))
+  (func (export "f32x4.min") (param v128 v128) (result v128) (f32x4.min (local.get 0) (local.get 1)))
+  (func (export "f64x2.max") (param v128 v128) (result v128) (f64x2.max (local.get 0) (local.get 1)))
+  (func (export "f64x2.sqrt") (param v128) (result v128) (f64x2.sqrt (local.get 0)))
+  (func (export "f32x4.neg") (param v128) (result v128) (f32x4.neg (local.get 0)))
+  (func (export "i32x4.trunc_sat_f32x4_s") (param v128) (result v128) (i32x4.trunc_sat_f32x4_s (local.get 0)))
+  (func (export "i32x4.trunc_sat_f32x4_u") (param v128) (result v128) (i32x4.trunc_sat_f32x4_u (local.get 0)))
+  (func (export "f32x4.convert_i32x4_u") (param v128) (result v128) (f32x4.convert_i32x4_u (local.get 0)))
+
+  (func (export "v8x16.swizzle") (param v128 v128) (result v128) (v8x16.swizzle (local.get 0) (local.get 1)))
+  (func (export "v8x16.shuffle") (param v128 v128) (result v128)
+    (v8x16.shuffle 31 0 30 1 29 2 28 3 27 4 26 5 25 6 24 7 (local.get 0) (local.get 1)))
+  (func (export "i8x16.narrow_i16x8_s") (param v128 v128) (result v128) (i8x16.narrow_i16x8_s (local.get 0) (local.get 1)))
+  (func (export "i8x16.narrow_i16x8_u") (param v128 v128) (result v128) (i8x16.narrow_i16x8_u (local.get 0) (local.get 1)))
+  (func (export "i16x8.narrow_i32x4_u") (param v128 v128) (result v128) (i16x8.narrow_i32x4_u (local.get 0) (local.get 1)))
+  (func (export "i16x8.widen_high_i8x16_s") (param v128) (result v128) (i16x8.widen_high_i8x16_s (local.get 0)))
+  (func (export "i32x4.widen_low_i16x8_u") (param v128) (result v128) (i32x4.widen_low_i16x8_u (local.get 0)))
+
+  (func (export "locals") (result v128) (local v128) (local.get 0))
+  (global $g (mut v128) (v128.const i64x2 7 8))
+  (func (export "global") (param v128) (result v128) (global.get $g) (global.set $g (local.get 0)))
+  (func (export "select") (param v128 v128 i32) (result v128) (select (result v128) (local.get 0) (local.get 1) (local.get 2))))
+
+(assert_return (invoke "load" (i32.const 0)) (v128.const i8x16 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15))
+(assert_return (invoke "load" (i32.const 4)) (v128.const i32x4 0x07060504 0x0b0a0908 0x0f0e0d0c 0xfe7fff80))
+(assert_trap (invoke "load" (i32.const 65521)) "out of bounds memory access")
+(assert_return (invoke "store" (i32.const 1) (v128.const i64x2 -1 2)) (v128.const i64x2 -1 2))
+(assert_trap (invoke "store" (i32.const 65490) (v128.const i64x2 0 0)) "out of bounds memory access")
+(assert_return (invoke "load8_splat" (i32.const 17)) (v128.const i8x16 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1))
+(assert_return (invoke "load32_splat" (i32.const 0)) (v128.const i32x4 0x03020100 0x03020100 0x03020100 0x03020100))
+(assert_return (invoke "load8x8_s" (i32.const 14)) (v128.const i16x8 14 15 -128 -1 127 -2 0 0))
+(assert_return (invoke "load8x8_u" (i32.const 14)) (v128.const i16x8 14 15 128 255 127 254 0 0))
+(assert_return (invoke "load32x2_s" (i32.const 16)) (v128.const i64x2 -25165952 0))
+(assert_return (invoke "const") (v128.const i32x4 1 2 3 4))
+
+(assert_return (invoke "i8x16.splat" (i32.const 0x1ff)) (v128.const i8x16 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1 -1))
+(assert_return (invoke "i64x2.splat" (i64.const 5)) (v128.const i64x2 5 5))
+(assert_return (invoke "f32x4.splat" (f32.const 1.5)) (v128.const f32x4 1.5 1.5 1.5 1.5))
+(assert_return (invoke "i8x16.extract_lane_u" (v128.const i8x16 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 -1)) (i32.const 255))
+(assert_return (invoke "i8x16.extract_lane_s" (v128.const i8x16 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 -1)) (i32.const -1))
+(assert_return (invoke "i16x8.extract_lane_s" (v128.const i16x8 0 0 0 0 0 0 0 -2)) (i32.const -2))
+(assert_return (invoke "i64x2.extract_lane" (v128.const i64x2 1 -3)) (i64.const -3))
+(assert_return (invoke "f64x2.extract_lane" (v128.const f64x2 -0.5 1)) (f64.const -0.5))
+(assert_return (invoke "i32x4.replace_lane" (v128.const i32x4 1 2 3 4) (i32.const 9)) (v128.const i32x4 1 2 9 4))
+(assert_return (invoke "f32x4.replace_lane" (v128.const f32x4 1 2 3 4) (f32.const nan)) (v128.const f32x4 nan 2 3 4))
+
+(assert_return (invoke "i8x16.eq" (v128.const i8x16 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16) (v128.const i8x16 1 0 3 0 5 0 7 0 9 0 11 0 13 0 15 0))
+  (v128.const i8x16 -1 0 -1 0 -1 0 -1 0 -1 0 -1 0 -1 0 -1 0))
+(assert_return (invoke "i8x16.lt_s" (v128.const i8x16 -1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0) (v128.const i8x16 1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0))
+  (v128.const i8x16 -1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0))
+(assert_return (invoke "i8x16.lt_u" (v128.const i8x16 -1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0) (v128.const i8x16 1 1 0 0 0 0 0 0 0 0 0 0 0 0 0 0))
+  (v128.const i8x16 0 -1 0 0 0 0 0 0 0 0 0 0 0 0 0 0))
+(assert_return (invoke "i32x4.ge_s" (v128.const i32x4 -1 2 3 0x80000000) (v128.const i32x4 1 2 2 0))
+  (v128.const i32x4 0 -1 -1 0))
+(assert_return (invoke "f32x4.lt" (v128.const f32x4 1 nan -0 2) (v128.const f32x4 2 1 0 1))
+  (v128.const i32x4 -1 0 0 0))
+(assert_return (invoke "f64x2.ne" (v128.const f64x2 nan 1) (v128.const f64x2 nan 1))
+  (v128.const i64x2 -1 0))
+
+(assert_return (invoke "v128.not" (v128.const i64x2 0 -1)) (v128.const i64x2 -1 0))
+(assert_return (invoke "v128.andnot" (v128.const i32x4 0xff 0xff 0 -1) (v128.const i32x4 0x0f 0 -1 0xf0f0f0f0))
+  (v128.const i32x4 0xf0 0xff 0 0x0f0f0f0f))
+(assert_return (invoke "v128.bitselect" (v128.const i32x4 -1 -1 0 0) (v128.const i32x4 0 0 -1 -1) (v128.const i32x4 0xff 0 0xff 0))
+  (v128.const i32x4 0xff 0 0xffffff00 -1))
+
+(assert_return (invoke "i8x16.abs" (v128.const i8x16 -128 -1 1 0 -5 0 0 0 0 0 0 0 0 0 0 0))
+  (v128.const i8x16 -128 1 1 0 5 0 0 0 0 0 0 0 0 0 0 0))
+(assert_return (invoke "i16x8.neg" (v128.const i16x8 1 -1 0 -32768 2 3 4 5)) (v128.const i16x8 -1 1 0 -32768 -2 -3 -4 -5))
+(assert_return (invoke "i32x4.any_true" (v128.const i32x4 0 0 1 0)) (i32.const 1))
+(assert_return (invoke "i32x4.any_true" (v128.const i32x4 0 0 0 0)) (i32.const 0))
+(assert_return (invoke "i32x4.all_true" (v128.const i32x4 1 1 1 0)) (i32.const 0))
+(assert_return (invoke "i32x4.all_true" (v128.const i32x4 1 2 3 -1)) (i32.const 1))
+(assert_return (invoke "i8x16.bitmask" (v128.const i8x16 -1 0 -1 0 0 0 0 0 0 0 0 0 0 0 0 -128)) (i32.const 0x8005))
+(assert_return (invoke "i16x8.shl" (v128.const i16x8 1 2 3 4 5 6 7 -1) (i32.const 17)) (v128.const i16x8 2 4 6 8 10 12 14 -2))
+(assert_return (invoke "i8x16.shr_s" (v128.const i8x16 -128 64 0 0 0 0 0 0 0 0 0 0 0 0 0 -1) (i32.const 1))
+  (v128.const i8x16 -64 32 0 0 0 0 0 0 0 0 0 0 0 0 0 -1))
+(assert_return (invoke "i64x2.shr_u" (v128.const i64x2 -1 16) (i32.const 68)) (v128.const i64x2 0x0fffffffffffffff 1))
+(assert_return (invoke "i8x16.add_saturate_s" (v128.const i8x16 127 -128 1 0 0 0 0 0 0 0 0 0 0 0 0 0) (v128.const i8x16 1 -1 1 0 0 0 0 0 0 0 0 0 0 0 0 0))
+  (v128.const i8x16 127 -128 2 0 0 0 0 0 0 0 0 0 0 0 0 0))
+(assert_return (invoke "i8x16.add_saturate_u" (v128.const i8x16 255 254 1 0 0 0 0 0 0 0 0 0 0 0 0 0) (v128.const i8x16 1 1 1 0 0 0 0 0 0 0 0 0 0 0 0 0))
+  (v128.const i8x16 255 255 2 0 0 0 0 0 0 0 0 0 0 0 0 0))
+(assert_return (invoke "i16x8.sub_saturate_s" (v128.const i16x8 -32768 32767 0 0 0 0 0 0) (v128.const i16x8 1 -1 1 0 0 0 0 0))
+  (v128.const i16x8 -32768 32767 -1 0 0 0 0 0))
+(assert_return (invoke "i32x4.add" (v128.const i32x4 -1 1 2 3) (v128.const i32x4 1 1 1 1)) (v128.const i32x4 0 2 3 4))
+(assert_return (invoke "i64x2.mul" (v128.const i64x2 -3 0x100000000) (v128.const i64x2 5 0x100000000)) (v128.const i64x2 -15 0))
+(assert_return (invoke "i32x4.min_s" (v128.const i32x4 -1 1 2 3) (v128.const i32x4 1 -1 2 0)) (v128.const i32x4 -1 -1 2 0))
+(assert_return (invoke "i32x4.max_u" (v128.const i32x4 -1 1 2 3) (v128.const i32x4 1 -1 2 0)) (v128.const i32x4 -1 -1 2 3))
+(assert_return (invoke "i8x16.avgr_u" (v128.const i8x16 255 0 1 2 0 0 0 0 0 0 0 0 0 0 0 0) (v128.const i8x16 255 1 2 2 0 0 0 0 0 0 0 0 0 0 0 0))
+  (v128.const i8x16 255 1 2 2 0 0 0 0 0 0 0 0 0 0 0 0))
+
+(assert_return (invoke "f32x4.add" (v128.const f32x4 1 2 inf nan) (v128.const f32x4 0.5 -2 -inf 1))
+  (v128.const f32x4 1.5 0 nan:arithmetic nan:arithmetic))
+(assert_return (invoke "f32x4.min" (v128.const f32x4 -0 0 nan 1) (v128.const f32x4 0 -0 1 -1))
+  (v128.const f32x4 -0 -0 nan:canonical -1))
+(assert_return (invoke "f64x2.max" (v128.const f64x2 -0 nan) (v128.const f64x2 0 1))
+  (v128.const f64x2 0 nan:canonical))
+(assert_return (invoke "f64x2.sqrt" (v128.const f64x2 4 -1)) (v128.const f64x2 2 nan:canonical))
+(assert_return (invoke "f32x4.neg" (v128.const f32x4 1 -0 nan -inf)) (v128.const f32x4 -1 0 -nan inf))
+(assert_return (invoke "i32x4.trunc_sat_f32x4_s" (v128.const f32x4 1.9 -1.9 nan 1e10)) (v128.const i32x4 1 -1 0 0x7fffffff))
+(assert_return (invoke "i32x4.trunc_sat_f32x4_u" (v128.const f32x4 1.9 -1.9 nan 1e10)) (v128.const i32x4 1 0 0 -1))
+(assert_return (invoke "f32x4.convert_i32x4_u" (v128.const i32x4 1 -1 0 16)) (v128.const f32x4 1 4294967296 0 16))
+
+(assert_return (invoke "v8x16.swizzle" (v128.const i8x16 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25) (v128.const i8x16 15 0 16 -1 1 1 1 1 1 1 1 1 1 1 1 2))
+  (v128.const i8x16 25 10 0 0 11 11 11 11 11 11 11 11 11 11 11 12))
+(assert_return (invoke "v8x16.shuffle" (v128.const i8x16 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15) (v128.const i8x16 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31))
+  (v128.const i8x16 31 0 30 1 29 2 28 3 27 4 26 5 25 6 24 7))
+(assert_return (invoke "i8x16.narrow_i16x8_s" (v128.const i16x8 -129 128 1 -1 0 0 0 0) (v128.const i16x8 0 0 0 0 0 0 0 300))
+  (v128.const i8x16 -128 127 1 -1 0 0 0 0 0 0 0 0 0 0 0 127))
+(assert_return (invoke "i8x16.narrow_i16x8_u" (v128.const i16x8 -129 128 1 -1 0 0 0 0) (v128.const i16x8 0 0 0 0 0 0 0 300))
+  (v128.const i8x16 0 128 1 0 0 0 0 0 0 0 0 0 0 0 0 255))
+(assert_return (invoke "i16x8.narrow_i32x4_u" (v128.const i32x4 70000 -1 5 0) (v128.const i32x4 0 0 0 65535))
+  (v128.const i16x8 65535 0 5 0 0 0 0 65535))
+(assert_return (invoke "i16x8.widen_high_i8x16_s" (v128.const i8x16 0 0 0 0 0 0 0 0 -1 1 -128 127 0 0 0 0))
+  (v128.const i16x8 -1 1 -128 127 0 0 0 0))
+(assert_return (invoke "i32x4.widen_low_i16x8_u" (v128.const i16x8 -1 1 -32768 5 0 0 0 0))
+  (v128.const i32x4 65535 1 32768 5))
+
+(assert_return (invoke "locals") (v128.const i64x2 0 0))
+(assert_return (invoke "global" (v128.const i64x2 1 2)) (v128.const i64x2 7 8))
+(assert_return (invoke "global" (v128.const i64x2 3 4)) (v128.const i64x2 1 2))
+(assert_return (invoke "select" (v128.const i64x2 1 2) (v128.const i64x2 3 4) (i32.const 0)) (v128.const i64x2 3 4))
+
+(assert_invalid (module (func (result v128) (i8x16.splat (i64.const 0)))) "type mismatch")
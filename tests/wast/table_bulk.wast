@@ -0,0 +1,49 @@
+(module
+  (table $t 6 funcref)
+  (type $r (func (result i32)))
+  (func $f0 (result i32) (i32.const 0))
+  (func $f1 (result i32) (i32.const 1))
+  (func $f2 (result i32) (i32.const 2))
+  (elem $p func $f0 $f1 $f2)
+  (elem (i32.const 0) $f2)
+  (elem declare func $f1)
+  (func (export "call") (param i32) (result i32) (call_indirect (type $r) (local.get 0)))
+  (func (export "init") (param i32 i32 i32) (table.init $p (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "init_active") (param i32 i32 i32) (table.init 1 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "init_declared") (param i32 i32 i32) (table.init 2 (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "copy") (param i32 i32 i32) (table.copy (local.get 0) (local.get 1) (local.get 2)))
+  (func (export "drop") (elem.drop $p)))
+
+(assert_return (invoke "call" (i32.const 0)) (i32.const 2))
+(assert_trap (invoke "call" (i32.const 1)) "uninitialized element")
+(invoke "init" (i32.const 1) (i32.const 0) (i32.const 3))
+(assert_return (invoke "call" (i32.const 1)) (i32.const 0))
+(assert_return (invoke "call" (i32.const 3)) (i32.const 2))
+(assert_trap (invoke "init" (i32.const 4) (i32.const 0) (i32.const 3)) "out of bounds table access")
+(assert_trap (invoke "call" (i32.const 4)) "uninitialized element")
+(assert_trap (invoke "init" (i32.const 0) (i32.const 2) (i32.const 2)) "out of bounds table access")
+(invoke "init" (i32.const 6) (i32.const 3) (i32.const 0))
+(assert_trap (invoke "init" (i32.const 7) (i32.const 0) (i32.const 0)) "out of bounds table access")
+(assert_trap (invoke "init" (i32.const 0) (i32.const 4) (i32.const 0)) "out of bounds table access")
+
+;; Overlapping copies in both directions.
+(invoke "copy" (i32.const 2) (i32.const 0) (i32.const 4))
+(assert_return (invoke "call" (i32.const 2)) (i32.const 2))
+(assert_return (invoke "call" (i32.const 3)) (i32.const 0))
+(assert_return (invoke "call" (i32.const 5)) (i32.const 2))
+(invoke "copy" (i32.const 0) (i32.const 3) (i32.const 3))
+(assert_return (invoke "call" (i32.const 0)) (i32.const 0))
+(assert_return (invoke "call" (i32.const 1)) (i32.const 1))
+(assert_return (invoke "call" (i32.const 2)) (i32.const 2))
+(assert_trap (invoke "copy" (i32.const 5) (i32.const 0) (i32.const 2)) "out of bounds table access")
+(assert_trap (invoke "copy" (i32.const 0) (i32.const 7) (i32.const 0)) "out of bounds table access")
+(invoke "copy" (i32.const 6) (i32.const 6) (i32.const 0))
+
+;; Active and declared segments are dropped after instantiation.
+(invoke "init_active" (i32.const 0) (i32.const 0) (i32.const 0))
+(assert_trap (invoke "init_active" (i32.const 0) (i32.const 0) (i32.const 1)) "out of bounds table access")
+(assert_trap (invoke "init_declared" (i32.const 0) (i32.const 0) (i32.const 1)) "out of bounds table access")
+
+(invoke "drop")
+(invoke "init" (i32.const 0) (i32.const 0) (i32.const 0))
+(assert_trap (invoke "init" (i32.const 0) (i32.const 0) (i32.const 1)) "out of bounds table access")
@@ -0,0 +1,56 @@
+(module
+  (type $i64_i64 (func (param i64 i64) (result i64)))
+  (table funcref (elem $fac-acc $count-i))
+
+  (func $fac-acc (export "fac-acc") (param i64 i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (local.get 1))
+      (else
+        (return_call $fac-acc
+          (i64.sub (local.get 0) (i64.const 1))
+          (i64.mul (local.get 0) (local.get 1))))))
+
+  (func $count (export "count") (param i64) (result i64)
+    (local i32 i32 i32)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (local.get 0))
+      (else (return_call $count (i64.sub (local.get 0) (i64.const 1))))))
+
+  (func $even (export "even") (param i64) (result i32)
+    (if (result i32) (i64.eqz (local.get 0))
+      (then (i32.const 44))
+      (else (return_call $odd (i64.sub (local.get 0) (i64.const 1))))))
+  (func $odd (export "odd") (param i64) (result i32)
+    (if (result i32) (i64.eqz (local.get 0))
+      (then (i32.const 99))
+      (else (return_call $even (i64.sub (local.get 0) (i64.const 1))))))
+
+  (func $count-i (param i64 i64) (result i64)
+    (if (result i64) (i64.eqz (local.get 0))
+      (then (local.get 1))
+      (else
+        (return_call_indirect (type $i64_i64)
+          (i64.sub (local.get 0) (i64.const 1))
+          (i64.add (local.get 1) (i64.const 2))
+          (i32.const 1)))))
+  (func (export "count-indirect") (param i64) (result i64)
+    (i64.add (i64.const 1)
+      (call $count-i (local.get 0) (i64.const 0))))
+
+  (func (export "bad-index") (result i64)
+    (return_call_indirect (type $i64_i64) (i64.const 0) (i64.const 0) (i32.const 2)))
+)
+
+(assert_return (invoke "fac-acc" (i64.const 0) (i64.const 1)) (i64.const 1))
+(assert_return (invoke "fac-acc" (i64.const 25) (i64.const 1)) (i64.const 7034535277573963776))
+(assert_return (invoke "count" (i64.const 0)) (i64.const 0))
+(assert_return (invoke "count" (i64.const 100000)) (i64.const 0))
+(assert_return (invoke "even" (i64.const 100000)) (i32.const 44))
+(assert_return (invoke "odd" (i64.const 100001)) (i32.const 44))
+(assert_return (invoke "odd" (i64.const 100000)) (i32.const 99))
+(assert_return (invoke "count-indirect" (i64.const 100000)) (i64.const 200001))
+(assert_trap (invoke "bad-index") "undefined element")
+
+(assert_invalid
+  (module (func $f (result i32) (return_call $g)) (func $g (result i64) (i64.const 0)))
+  "type mismatch")
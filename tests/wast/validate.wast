@@ -0,0 +1,7 @@
+(assert_invalid (module (func (result i32) (i64.const 1))) "type mismatch")
+(assert_invalid (module (func (call 3))) "unknown function")
+(assert_invalid (module (memory 2 1)) "size minimum must not be greater than maximum")
+(assert_malformed (module quote "(func (i32.const))") "unexpected token")
+(assert_malformed (module binary "\00asm\02\00\00\00") "unknown binary version")
+(module (func (export "ok") (result i32) (i32.const 7)))
+(assert_return (invoke "ok") (i32.const 7))